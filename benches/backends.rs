@@ -0,0 +1,113 @@
+//! The classic Lox benchmarks, scaled down to run many times, on both the
+//! tree-walker and the VM. Criterion reports the change from the previous
+//! run, so a regression in either backend shows up there.
+
+use std::rc::Rc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rslox::{
+    interpreter::Interpreter,
+    lexer::Scanner,
+    parser::{Ast, Parser},
+    resolver::Resolver,
+    vm::Vm,
+};
+
+const FIB: &str = "
+fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 2) + fib(n - 1);
+}
+var result = fib(18);
+";
+
+const ZOO: &str = "
+class Zoo {
+  init() {
+    this.aarvark  = 1;
+    this.baboon   = 1;
+    this.cat      = 1;
+    this.donkey   = 1;
+    this.elephant = 1;
+    this.fox      = 1;
+  }
+  ant()    { return this.aarvark; }
+  banana() { return this.baboon; }
+  tuna()   { return this.cat; }
+  hay()    { return this.donkey; }
+  grass()  { return this.elephant; }
+  mouse()  { return this.fox; }
+}
+
+var zoo = Zoo();
+var sum = 0;
+while (sum < 20000) {
+  sum = sum + zoo.ant()
+            + zoo.banana()
+            + zoo.tuna()
+            + zoo.hay()
+            + zoo.grass()
+            + zoo.mouse();
+}
+";
+
+const STRING_EQUALITY: &str = r#"
+var a1 = "a1"; var a2 = "a2"; var a3 = "a3"; var a4 = "a4";
+var count = 0;
+for (var i = 0; i < 5000; i = i + 1) {
+  if ("a1" == a1) count = count + 1;
+  if ("a2" == a1) count = count + 1;
+  if ("a3" == a2) count = count + 1;
+  if (a3 == a3) count = count + 1;
+  if (a4 == "a" + "4") count = count + 1;
+  if (a1 == a2) count = count + 1;
+}
+"#;
+
+const INSTANTIATION: &str = "
+class Foo {
+  init() {}
+}
+for (var i = 0; i < 5000; i = i + 1) {
+  Foo(); Foo(); Foo(); Foo(); Foo();
+}
+";
+
+fn parse(source: &str) -> Ast {
+    let tokens = Scanner::new(source.to_string())
+        .scan_tokens()
+        .expect("benchmark source scans");
+    Parser::new(tokens)
+        .parse()
+        .expect("benchmark source parses")
+}
+
+fn programs(c: &mut Criterion) {
+    for (name, source) in [
+        ("fib", FIB),
+        ("zoo", ZOO),
+        ("string_equality", STRING_EQUALITY),
+        ("instantiation", INSTANTIATION),
+    ] {
+        let mut group = c.benchmark_group(name);
+        let ast = Rc::new(parse(source));
+        let locals = Resolver::new()
+            .resolve(&ast)
+            .expect("benchmark source resolves");
+
+        group.bench_function("tree", |b| {
+            b.iter(|| {
+                let mut interpreter = Interpreter::new();
+                interpreter.resolve(locals.clone());
+                interpreter.interpret(black_box(&ast)).unwrap()
+            })
+        });
+        group.bench_function("vm", |b| {
+            b.iter(|| Vm::new().interpret(black_box(&ast)).unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, programs);
+criterion_main!(benches);
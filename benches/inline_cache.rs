@@ -0,0 +1,74 @@
+//! Hot loops that hit the same global and the same class's fields and
+//! methods on every iteration, which is what the VM's inline caches speed
+//! up. To see by how much, save a baseline on a commit without them and
+//! compare:
+//!
+//! ```text
+//! cargo bench --bench inline_cache -- --save-baseline before
+//! cargo bench --bench inline_cache -- --baseline before
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rslox::{
+    lexer::Scanner,
+    parser::{Ast, Parser},
+    vm::Vm,
+};
+
+const GLOBALS: &str = "
+var step = 1;
+var limit = 100000;
+var total = 0;
+for (var i = 0; i < limit; i = i + step) {
+  total = total + step;
+}
+";
+
+const FIELDS: &str = "
+class Counter {
+  init() { this.a = 0; this.b = 1; this.c = 2; this.count = 0; }
+}
+var counter = Counter();
+for (var i = 0; i < 100000; i = i + 1) {
+  counter.count = counter.count + counter.b;
+}
+";
+
+const METHODS: &str = "
+class Counter {
+  init() { this.count = 0; }
+  bump(n) { this.count = this.count + n; }
+  value() { return this.count; }
+}
+var counter = Counter();
+for (var i = 0; i < 100000; i = i + 1) {
+  counter.bump(counter.value() - counter.count + 1);
+}
+";
+
+fn parse(source: &str) -> Ast {
+    let tokens = Scanner::new(source.to_string())
+        .scan_tokens()
+        .expect("benchmark source scans");
+    Parser::new(tokens)
+        .parse()
+        .expect("benchmark source parses")
+}
+
+fn programs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inline_cache");
+    for (name, source) in [
+        ("globals", GLOBALS),
+        ("fields", FIELDS),
+        ("methods", METHODS),
+    ] {
+        let ast = parse(source);
+        group.bench_function(name, |b| {
+            b.iter(|| Vm::new().interpret(black_box(&ast)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, programs);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+//! Parses a large script, dominated by expression trees, to measure how much
+//! building the AST costs.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rslox::{lexer::Scanner, parser::Parser};
+
+const SNIPPET: &str = r#"
+fun fib(n) {
+  if (n <= 1) return n;
+  return fib(n - 2) + fib(n - 1);
+}
+var total = 0;
+for (var i = 0; i < 20; i = i + 1) {
+  total = total + (i * 2 - 1) / (i + 1) * fib(i) + (i > 3 ? i % 3 : -i);
+  if (total > 100 and !(i == 7) or total < -5) print "big ${total} at ${i}";
+}
+class Point {
+  init(x, y) { this.x = x; this.y = y; }
+  len() { return (this.x * this.x + this.y * this.y) ** 0.5; }
+}
+print Point(3, 4).len() + (1 + (2 + (3 + (4 + (5 + 6)))));
+"#;
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parser");
+    let source = SNIPPET.repeat(2000);
+    let tokens = Scanner::new(source.clone()).scan_tokens().unwrap();
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("large", |b| {
+        b.iter_batched(
+            || tokens.clone(),
+            |tokens| Parser::new(black_box(tokens)).parse().unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+//! Scans progressively larger sources to show the scanner stays linear in
+//! the input size.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rslox::lexer::Scanner;
+
+const SNIPPET: &str = r#"
+// Compute a few Fibonacci numbers, with a comment for good measure.
+fun fib(n) {
+  if (n <= 1) return n;
+  return fib(n - 2) + fib(n - 1);
+}
+var greeting = "héllo, wörld";
+for (var i = 0; i < 20; i = i + 1) {
+  print greeting + " " + fib(i) * 1.5;
+}
+"#;
+
+fn scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scanner");
+    for size in [64 * 1024, 512 * 1024, 4 * 1024 * 1024] {
+        let source = SNIPPET.repeat(size / SNIPPET.len() + 1);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &source, |b, source| {
+            b.iter(|| {
+                Scanner::new(black_box(source.clone()))
+                    .scan_tokens()
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scan);
+criterion_main!(benches);
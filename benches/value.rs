@@ -0,0 +1,73 @@
+//! Compares the VM's value representations. Run once as-is for the enum
+//! `Value` and once with `--features nan-boxing` for the packed one:
+//!
+//! ```text
+//! cargo bench --bench value
+//! cargo bench --bench value --features nan-boxing
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rslox::{
+    lexer::Scanner,
+    parser::{Ast, Parser},
+    vm::{value::Value, Vm},
+};
+
+const FIB: &str = "
+fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 1) + fib(n - 2);
+}
+var result = fib(20);
+";
+
+const LOOP: &str = "
+var sum = 0;
+for (var i = 0; i < 100000; i = i + 1) {
+  if (i == i and !(i < 0)) sum = sum + i * 2;
+}
+";
+
+fn parse(source: &str) -> Ast {
+    let tokens = Scanner::new(source.to_string())
+        .scan_tokens()
+        .expect("benchmark source scans");
+    Parser::new(tokens)
+        .parse()
+        .expect("benchmark source parses")
+}
+
+fn programs(c: &mut Criterion) {
+    for (name, source) in [("fib", FIB), ("loop", LOOP)] {
+        let ast = parse(source);
+        c.bench_function(&format!("vm/{}", name), |b| {
+            b.iter(|| Vm::new().interpret(black_box(&ast)).unwrap())
+        });
+    }
+}
+
+fn stack_arithmetic(c: &mut Criterion) {
+    c.bench_function("value/stack_arithmetic", |b| {
+        let mut stack: Vec<Value> = Vec::with_capacity(1024);
+        b.iter(|| {
+            stack.clear();
+            for i in 0..1000 {
+                stack.push(Value::number(i as f64));
+                stack.push(Value::bool(i % 2 == 0));
+                stack.push(Value::nil());
+            }
+            let mut total = 0.0;
+            for value in &stack {
+                if let Some(n) = value.as_number() {
+                    total += n;
+                } else if !value.is_falsey() {
+                    total += 1.0;
+                }
+            }
+            black_box(total)
+        })
+    });
+}
+
+criterion_group!(benches, programs, stack_arithmetic);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+/* C API for embedding rslox, a Lox interpreter. Build the library with
+ * `cargo build --release --features ffi` and link against librslox. */
+
+#ifndef RSLOX_H
+#define RSLOX_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct RsLox RsLox;
+
+typedef enum {
+    RSLOX_NIL,
+    RSLOX_BOOL,
+    RSLOX_NUMBER,
+    RSLOX_STRING,
+} RsloxType;
+
+/* A Lox value. Only the field matching `kind` is meaningful. */
+typedef struct {
+    RsloxType kind;
+    bool boolean;
+    double number;
+    /* NUL-terminated UTF-8. */
+    const char *string;
+} RsloxValue;
+
+/* A C function scripts can call. Write the result to `result` and return
+ * true, or return false to raise a runtime error, with `result` as the
+ * message if it is a string. Argument strings are only valid during the
+ * call; a string result is copied as soon as the function returns. */
+typedef bool (*RsloxFn)(const RsloxValue *args, size_t arg_count, void *user_data,
+                        RsloxValue *result);
+
+/* Creates an interpreter. Free it with rslox_free. */
+RsLox *rslox_new(void);
+
+/* Frees an interpreter. NULL is ignored. */
+void rslox_free(RsLox *lox);
+
+/* Runs a program, reporting any error to stderr. Globals it defines stay
+ * defined for later runs. Returns 0 on success and 1 on an error. */
+int rslox_run(RsLox *lox, const char *source);
+
+/* Defines a global function `name` taking `arity` arguments that calls
+ * `function` with `user_data`. Returns 0 on success and 1 if `name` isn't
+ * valid UTF-8. */
+int rslox_register_fn(RsLox *lox, const char *name, size_t arity, RsloxFn function,
+                      void *user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,310 @@
+use std::rc::Rc;
+
+use crate::{
+    lexer::{Token, TokenType},
+    parser::{Ast, ExprId, ExprRef, FunctionDecl, LiteralValue, Stmt, SHORT_CHAIN},
+    visitor::{ExprVisitor, StmtVisitor},
+};
+
+/// Renders a program in a Lisp-like parenthesized form that makes the tree's
+/// structure and operator precedence explicit, one statement per line.
+pub fn print_program(ast: &Ast) -> String {
+    ast.statements()
+        .iter()
+        .map(|statement| print_stmt(ast, statement) + "\n")
+        .collect()
+}
+
+pub fn print_stmt(ast: &Ast, stmt: &Stmt) -> String {
+    stmt.accept(&mut AstPrinter::new(ast))
+}
+
+pub fn print_expr(ast: &Ast, expr: ExprRef) -> String {
+    AstPrinter::new(ast).expr(expr)
+}
+
+struct AstPrinter<'a> {
+    ast: &'a Ast,
+    /// The link of a long [chain](Ast::chain) printed last, for the next
+    /// link to continue from.
+    chained: Option<(ExprRef, String)>,
+}
+
+impl<'a> AstPrinter<'a> {
+    fn new(ast: &'a Ast) -> Self {
+        Self { ast, chained: None }
+    }
+
+    fn expr(&mut self, expr: ExprRef) -> String {
+        if let Some((_, printed)) = self.chained.take_if(|(link, _)| *link == expr) {
+            return printed;
+        }
+        // While a link's text is being handed on, `expr` is the next link of
+        // a chain already being printed.
+        if self.chained.is_none() && self.ast.chain_length(expr) > SHORT_CHAIN {
+            return self.chain(expr);
+        }
+        self.ast[expr].accept(self)
+    }
+
+    /// Prints a long [chain](Ast::chain) from the innermost link out, each
+    /// handing its text to the next, rather than recursing down it.
+    fn chain(&mut self, expr: ExprRef) -> String {
+        let (_, links) = self.ast.chain(expr);
+        for &link in &links[..links.len() - 1] {
+            let printed = self.expr(link);
+            self.chained = Some((link, printed));
+        }
+        self.expr(expr)
+    }
+
+    fn function(&mut self, declaration: &FunctionDecl) -> String {
+        let params: Vec<&str> = declaration
+            .params
+            .iter()
+            .map(|param| &*param.lexeme)
+            .collect();
+        let mut out = format!("(fun {} ({})", declaration.name.lexeme, params.join(" "));
+        for statement in &declaration.body {
+            out.push(' ');
+            out.push_str(&statement.accept(self));
+        }
+        out + ")"
+    }
+
+    fn parenthesize(&mut self, name: &str, exprs: &[ExprRef]) -> String {
+        let mut out = format!("({}", name);
+        for expr in exprs {
+            out.push(' ');
+            out.push_str(&self.expr(*expr));
+        }
+        out + ")"
+    }
+}
+
+impl StmtVisitor<String> for AstPrinter<'_> {
+    fn visit_block(&mut self, statements: &[Stmt]) -> String {
+        let mut out = "(block".to_string();
+        for statement in statements {
+            out.push(' ');
+            out.push_str(&statement.accept(self));
+        }
+        out + ")"
+    }
+
+    fn visit_break(&mut self, _keyword: &Token) -> String {
+        "(break)".to_string()
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<ExprRef>,
+        methods: &[Rc<FunctionDecl>],
+        class_methods: &[Rc<FunctionDecl>],
+    ) -> String {
+        let mut out = format!("(class {}", name.lexeme);
+        if let Some(superclass) = superclass {
+            out.push_str(&format!(" < {}", self.expr(superclass)));
+        }
+        for method in methods {
+            out.push(' ');
+            out.push_str(&self.function(method));
+        }
+        for method in class_methods {
+            out.push_str(&format!(" (class {})", self.function(method)));
+        }
+        out + ")"
+    }
+
+    fn visit_continue(&mut self, _keyword: &Token) -> String {
+        "(continue)".to_string()
+    }
+
+    fn visit_expression(&mut self, expression: ExprRef) -> String {
+        format!("(; {})", self.expr(expression))
+    }
+
+    fn visit_function(&mut self, declaration: &Rc<FunctionDecl>) -> String {
+        self.function(declaration)
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: ExprRef,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> String {
+        let condition = self.expr(condition);
+        let then_branch = then_branch.accept(self);
+        match else_branch {
+            Some(else_branch) => format!(
+                "(if {} {} {})",
+                condition,
+                then_branch,
+                else_branch.accept(self)
+            ),
+            None => format!("(if {} {})", condition, then_branch),
+        }
+    }
+
+    fn visit_print(&mut self, expression: ExprRef) -> String {
+        format!("(print {})", self.expr(expression))
+    }
+
+    fn visit_return(&mut self, _keyword: &Token, value: Option<ExprRef>) -> String {
+        match value {
+            Some(value) => format!("(return {})", self.expr(value)),
+            None => "(return)".to_string(),
+        }
+    }
+
+    fn visit_var(&mut self, name: &Token, initializer: Option<ExprRef>) -> String {
+        match initializer {
+            Some(initializer) => format!("(var {} {})", name.lexeme, self.expr(initializer)),
+            None => format!("(var {})", name.lexeme),
+        }
+    }
+
+    fn visit_while(
+        &mut self,
+        condition: ExprRef,
+        body: &Stmt,
+        increment: Option<ExprRef>,
+    ) -> String {
+        let condition = self.expr(condition);
+        let body = body.accept(self);
+        match increment {
+            Some(increment) => format!("(while {} {} {})", condition, body, self.expr(increment)),
+            None => format!("(while {} {})", condition, body),
+        }
+    }
+}
+
+impl ExprVisitor<String> for AstPrinter<'_> {
+    fn visit_assign(&mut self, _id: ExprId, name: &Token, value: ExprRef) -> String {
+        self.parenthesize(&format!("= {}", name.lexeme), &[value])
+    }
+
+    fn visit_binary(&mut self, left: ExprRef, operator: &Token, right: ExprRef) -> String {
+        self.parenthesize(&operator.lexeme, &[left, right])
+    }
+
+    fn visit_call(&mut self, callee: ExprRef, _paren: &Token, arguments: &[ExprRef]) -> String {
+        let mut exprs = vec![callee];
+        exprs.extend(arguments);
+        self.parenthesize("call", &exprs)
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: ExprRef,
+        then_branch: ExprRef,
+        else_branch: ExprRef,
+    ) -> String {
+        self.parenthesize("?:", &[condition, then_branch, else_branch])
+    }
+
+    fn visit_get(&mut self, object: ExprRef, name: &Token) -> String {
+        format!("(. {} {})", self.expr(object), name.lexeme)
+    }
+
+    fn visit_grouping(&mut self, expression: ExprRef) -> String {
+        self.parenthesize("group", &[expression])
+    }
+
+    fn visit_index(&mut self, object: ExprRef, _bracket: &Token, index: ExprRef) -> String {
+        self.parenthesize("[]", &[object, index])
+    }
+
+    fn visit_lambda(&mut self, declaration: &Rc<FunctionDecl>) -> String {
+        self.function(declaration)
+    }
+
+    fn visit_list(&mut self, _bracket: &Token, elements: &[ExprRef]) -> String {
+        self.parenthesize("list", elements)
+    }
+
+    fn visit_literal(&mut self, value: &LiteralValue) -> String {
+        match value {
+            LiteralValue::Nil => "nil".to_string(),
+            LiteralValue::Bool(b) => b.to_string(),
+            LiteralValue::Number(n) => n.to_string(),
+            LiteralValue::String(s) => format!("{:?}", &**s),
+        }
+    }
+
+    fn visit_logical(&mut self, left: ExprRef, operator: &Token, right: ExprRef) -> String {
+        self.parenthesize(&operator.lexeme, &[left, right])
+    }
+
+    fn visit_map(&mut self, _brace: &Token, entries: &[(ExprRef, ExprRef)]) -> String {
+        let exprs: Vec<ExprRef> = entries
+            .iter()
+            .flat_map(|&(key, value)| [key, value])
+            .collect();
+        self.parenthesize("map", &exprs)
+    }
+
+    fn visit_set(
+        &mut self,
+        object: ExprRef,
+        name: &Token,
+        operator: Option<&Token>,
+        value: ExprRef,
+    ) -> String {
+        format!(
+            "(set{} {} {} {})",
+            operator.map_or("", |operator| &operator.lexeme),
+            self.expr(object),
+            name.lexeme,
+            self.expr(value)
+        )
+    }
+
+    fn visit_set_index(
+        &mut self,
+        object: ExprRef,
+        _bracket: &Token,
+        index: ExprRef,
+        operator: Option<&Token>,
+        value: ExprRef,
+    ) -> String {
+        let name = format!("[]{}=", operator.map_or("", |operator| &operator.lexeme));
+        self.parenthesize(&name, &[object, index, value])
+    }
+
+    fn visit_slice(
+        &mut self,
+        object: ExprRef,
+        _bracket: &Token,
+        start: Option<ExprRef>,
+        end: Option<ExprRef>,
+    ) -> String {
+        let object = self.expr(object);
+        let mut bound =
+            |bound: Option<ExprRef>| bound.map_or("nil".to_string(), |bound| self.expr(bound));
+        let (start, end) = (bound(start), bound(end));
+        format!("([:] {} {} {})", object, start, end)
+    }
+
+    fn visit_super(&mut self, _id: ExprId, _keyword: &Token, method: &Token) -> String {
+        format!("(super {})", method.lexeme)
+    }
+
+    fn visit_this(&mut self, _id: ExprId, _keyword: &Token) -> String {
+        "this".to_string()
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: ExprRef) -> String {
+        match operator.token_type {
+            // The parser wraps each `${...}` of an interpolated string in this.
+            TokenType::Interpolation => self.parenthesize("str", &[right]),
+            _ => self.parenthesize(&operator.lexeme, &[right]),
+        }
+    }
+
+    fn visit_variable(&mut self, _id: ExprId, name: &Token) -> String {
+        name.lexeme.to_string()
+    }
+}
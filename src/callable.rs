@@ -0,0 +1,13 @@
+use crate::{error::LoxError, interpreter::Interpreter, value::Value};
+
+/// Anything a Lox call expression can invoke: functions, classes, and
+/// natives.
+pub trait LoxCallable {
+    /// The number of arguments a call must pass.
+    fn arity(&self) -> usize;
+
+    /// Runs the call. The interpreter has already checked the argument
+    /// count against [`arity`](LoxCallable::arity).
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>)
+        -> Result<Value, LoxError>;
+}
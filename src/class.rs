@@ -0,0 +1,186 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+
+use crate::{
+    callable::LoxCallable,
+    error::LoxError,
+    function::LoxFunction,
+    intern::{intern, Symbol},
+    interpreter::Interpreter,
+    lexer::Token,
+    suggest,
+    value::Value,
+};
+
+pub struct LoxClass {
+    pub name: Symbol,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<Symbol, Rc<LoxFunction>>,
+    /// The class's own class, holding its class methods. Inherits from the
+    /// superclass's metaclass, so class methods are inherited too. `None` for
+    /// metaclasses themselves.
+    metaclass: Option<Rc<LoxClass>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: Symbol,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<Symbol, Rc<LoxFunction>>,
+        class_methods: HashMap<Symbol, Rc<LoxFunction>>,
+    ) -> Self {
+        let metaclass = LoxClass {
+            name: intern(&format!("{} metaclass", name)),
+            superclass: superclass
+                .as_ref()
+                .and_then(|superclass| superclass.metaclass.clone()),
+            methods: class_methods,
+            metaclass: None,
+        };
+        Self {
+            name,
+            superclass,
+            methods,
+            metaclass: Some(Rc::new(metaclass)),
+        }
+    }
+
+    /// Looks up a method on this class, then up the superclass chain.
+    pub fn find_method(&self, name: &Symbol) -> Option<Rc<LoxFunction>> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(method.clone());
+        }
+        self.superclass
+            .as_ref()
+            .and_then(|superclass| superclass.find_method(name))
+    }
+
+    /// Whether this class is `class` or inherits from it.
+    pub fn inherits_from(&self, class: &Rc<LoxClass>) -> bool {
+        std::ptr::eq(self, &**class)
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.inherits_from(class))
+    }
+
+    /// Looks up a class method, including those inherited from superclasses.
+    pub fn find_class_method(&self, name: &Symbol) -> Option<Rc<LoxFunction>> {
+        self.metaclass
+            .as_ref()
+            .and_then(|metaclass| metaclass.find_method(name))
+    }
+
+    /// The names of this class's methods, inherited ones included.
+    pub fn method_names(&self) -> Vec<Symbol> {
+        let mut names = self
+            .superclass
+            .as_ref()
+            .map_or_else(Vec::new, |superclass| superclass.method_names());
+        names.extend(self.methods.keys().cloned());
+        names
+    }
+
+    /// The names of this class's class methods, inherited ones included.
+    pub fn class_method_names(&self) -> Vec<Symbol> {
+        self.metaclass
+            .as_ref()
+            .map_or_else(Vec::new, |metaclass| metaclass.method_names())
+    }
+
+    /// Looks up a class method bound to `class`.
+    pub fn get(class: &Rc<LoxClass>, name: &Token) -> Result<Value, LoxError> {
+        match class.find_class_method(&name.lexeme) {
+            Some(method) => Ok(Value::Function(Rc::new(
+                method.bind(Value::Class(class.clone())),
+            ))),
+            None => Err(undefined_property(name, &class.class_method_names())),
+        }
+    }
+}
+
+/// The error for reading property `name` where only `properties` exist.
+pub fn undefined_property(name: &Token, properties: &[Symbol]) -> LoxError {
+    let candidates = properties.iter().map(|property| &**property);
+    LoxError::runtime_at(
+        name,
+        "R0005",
+        suggest::undefined("property", &name.lexeme, candidates),
+    )
+}
+
+impl LoxCallable for Rc<LoxClass> {
+    fn arity(&self) -> usize {
+        self.find_method(&intern("init"))
+            .map_or(0, |initializer| initializer.arity())
+    }
+
+    /// Creates a new instance, running `init` on it if the class defines one.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, LoxError> {
+        let instance = Rc::new(RefCell::new(LoxInstance::new(self.clone())));
+        if let Some(initializer) = self.find_method(&intern("init")) {
+            initializer
+                .bind(Value::Instance(instance.clone()))
+                .call(interpreter, arguments)?;
+        }
+        Ok(Value::Instance(instance))
+    }
+}
+
+impl Debug for LoxClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    fields: HashMap<Symbol, Value>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Looks up a field, falling back to a method bound to `instance`.
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Result<Value, LoxError> {
+        if let Some(value) = instance.borrow().fields.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+
+        let method = instance.borrow().class.find_method(&name.lexeme);
+        match method {
+            Some(method) => Ok(Value::Function(Rc::new(
+                method.bind(Value::Instance(instance.clone())),
+            ))),
+            None => {
+                let instance = instance.borrow();
+                let mut properties = instance.class.method_names();
+                properties.extend(instance.fields.keys().cloned());
+                Err(undefined_property(name, &properties))
+            }
+        }
+    }
+
+    /// The names of the fields set on this instance so far.
+    pub fn field_names(&self) -> impl Iterator<Item = &Symbol> {
+        self.fields.keys()
+    }
+
+    pub fn set(&mut self, name: &Token, value: Value) {
+        self.fields.insert(name.lexeme.clone(), value);
+    }
+}
+
+impl Debug for LoxInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
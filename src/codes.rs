@@ -0,0 +1,112 @@
+//! Stable codes for every kind of diagnostic, like `P0012` for a missing
+//! `;`, and the longer explanations `rslox explain` prints for them.
+//!
+//! The letter says which pass reports it: `L` the scanner, `P` the parser,
+//! `S` the resolver, `C` the bytecode compiler, `R` the running program,
+//! and `W` a warning. Codes are never reused for something else, so they
+//! can be searched for and linked to.
+
+use crate::error::WarningKind;
+
+/// A kind of diagnostic.
+pub struct Code {
+    pub id: &'static str,
+    /// What it means and how to fix it, with examples.
+    pub explanation: &'static str,
+}
+
+impl Code {
+    /// The first paragraph of the explanation, which sums it up, on one
+    /// line.
+    pub fn summary(&self) -> String {
+        let paragraph = self.explanation.split("\n\n").next().unwrap_or_default();
+        paragraph.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+macro_rules! code {
+    ($id:literal) => {
+        Code {
+            id: $id,
+            explanation: include_str!(concat!("codes/", $id, ".md")),
+        }
+    };
+}
+
+pub const CODES: &[Code] = &[
+    code!("L0001"),
+    code!("L0002"),
+    code!("L0003"),
+    code!("L0004"),
+    code!("L0005"),
+    code!("L0006"),
+    code!("P0001"),
+    code!("P0002"),
+    code!("P0003"),
+    code!("P0004"),
+    code!("P0005"),
+    code!("P0006"),
+    code!("P0007"),
+    code!("P0008"),
+    code!("P0009"),
+    code!("P0010"),
+    code!("P0011"),
+    code!("P0012"),
+    code!("P0013"),
+    code!("P0014"),
+    code!("P0015"),
+    code!("P0016"),
+    code!("S0001"),
+    code!("S0002"),
+    code!("S0003"),
+    code!("S0004"),
+    code!("S0005"),
+    code!("S0006"),
+    code!("S0007"),
+    code!("S0008"),
+    code!("S0009"),
+    code!("C0001"),
+    code!("C0002"),
+    code!("C0003"),
+    code!("C0004"),
+    code!("C0005"),
+    code!("R0001"),
+    code!("R0002"),
+    code!("R0003"),
+    code!("R0004"),
+    code!("R0005"),
+    code!("R0006"),
+    code!("R0007"),
+    code!("R0008"),
+    code!("R0009"),
+    code!("R0010"),
+    code!("R0011"),
+    code!("R0012"),
+    code!("R0013"),
+    code!("R0014"),
+    code!("R0015"),
+    // Whatever else a native function reports.
+    code!("R0016"),
+    code!("R0017"),
+    code!("R0018"),
+    code!("R0019"),
+    code!("R0020"),
+    code!("R0021"),
+    code!("W0001"),
+    code!("W0002"),
+    code!("W0003"),
+];
+
+/// Looks up the code `id`, ignoring case.
+pub fn lookup(id: &str) -> Option<&'static Code> {
+    CODES.iter().find(|code| code.id.eq_ignore_ascii_case(id))
+}
+
+pub fn for_warning(kind: WarningKind) -> &'static Code {
+    let id = match kind {
+        WarningKind::UnusedVariable => "W0001",
+        WarningKind::UnreachableCode => "W0002",
+        WarningKind::Shadowing => "W0003",
+    };
+    lookup(id).expect("every warning has a code")
+}
@@ -0,0 +1,326 @@
+//! Error reports for people to read: the message, then the line of source
+//! it is about with the offending text underlined, along the lines of
+//! rustc's.
+//!
+//! ```text
+//! error[P0012]: Expect ';' after value.
+//!  --> hello.lox:2:1
+//!   |
+//! 2 | print "world";
+//!   | ^^^^^
+//!   = help: Statements end with a ';'.
+//! ```
+
+use std::io::{self, Write};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    codes,
+    error::{LoxError, Warning},
+    lexer::Span,
+};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// How wide a tab is shown, so the underline still lines up with it.
+const TAB_WIDTH: usize = 4;
+
+/// The help for adding a string to something that isn't one.
+pub const INTERPOLATE: &str =
+    "To join a string with another value, interpolate it, as in \"total: ${n}\".";
+
+/// A script and the name to refer to it by in reports.
+pub struct SourceFile<'a> {
+    pub name: &'a str,
+    pub text: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Stops the program from running, or stopped it.
+    Error,
+    /// Points out something that is likely a mistake.
+    Warning,
+}
+
+/// A problem with a script, ready to be rendered against its source.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Its [code](crate::codes), like `P0012`.
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub line: usize,
+    /// The text to underline. Without one, as for a program stopped by a
+    /// limit, the line is quoted as it is.
+    pub span: Option<Span>,
+    /// More about where the problem is, like the calls a runtime error
+    /// happened in.
+    pub notes: Vec<String>,
+    /// A suggestion for how to fix it.
+    pub help: Option<String>,
+}
+
+impl From<&LoxError> for Diagnostic {
+    fn from(error: &LoxError) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: Some(error.code()),
+            message: error.message().to_string(),
+            line: error.line(),
+            span: error.span(),
+            notes: error.backtrace(),
+            help: error
+                .help()
+                .or_else(|| help(error.code()))
+                .map(str::to_string),
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            code: Some(codes::for_warning(warning.kind).id),
+            message: warning.message.clone(),
+            line: warning.token.line(),
+            span: Some(warning.token.span()),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+}
+
+/// The line a diagnostic is about, and the part of it to underline as a
+/// byte range.
+struct Quote<'a> {
+    line: usize,
+    text: &'a str,
+    underline: Option<(usize, usize)>,
+}
+
+impl Diagnostic {
+    /// Writes the diagnostic to `out`, quoting `file`, with ANSI colors if
+    /// `color` is set.
+    pub fn render(&self, file: &SourceFile, color: bool, out: &mut dyn Write) -> io::Result<()> {
+        let paint = |style: &str, text: &str| match color {
+            true => format!("{}{}{}", style, text, RESET),
+            false => text.to_string(),
+        };
+        let (label, style) = match self.severity {
+            Severity::Error => ("error", RED),
+            Severity::Warning => ("warning", YELLOW),
+        };
+        let label = match self.code {
+            Some(code) => format!("{}[{}]", label, code),
+            None => label.to_string(),
+        };
+        writeln!(
+            out,
+            "{}{}",
+            paint(style, &label),
+            paint(BOLD, &format!(": {}", self.message))
+        )?;
+
+        let quote = self.quote(file.text);
+        let line = quote.as_ref().map_or(self.line, |quote| quote.line);
+        let gutter = " ".repeat(line.to_string().len());
+        let bar = paint(BLUE, "|");
+        let location = match &quote {
+            Some(Quote {
+                text,
+                underline: Some((start, _)),
+                ..
+            }) => {
+                let column = text[..*start].chars().count() + 1;
+                format!("{}:{}:{}", file.name, line, column)
+            }
+            _ => format!("{}:{}", file.name, line),
+        };
+        writeln!(out, "{}{} {}", gutter, paint(BLUE, "-->"), location)?;
+
+        if let Some(quote) = &quote {
+            writeln!(out, "{} {}", gutter, bar)?;
+            let number = paint(BLUE, &quote.line.to_string());
+            writeln!(out, "{} {} {}", number, bar, expand_tabs(quote.text))?;
+            if let Some((start, end)) = quote.underline {
+                let indent = width(&quote.text[..start]);
+                let carets = width(&quote.text[start..end]).max(1);
+                writeln!(
+                    out,
+                    "{} {} {}{}",
+                    gutter,
+                    bar,
+                    " ".repeat(indent),
+                    paint(style, &"^".repeat(carets))
+                )?;
+            }
+        }
+        for note in &self.notes {
+            writeln!(out, "{} {} note: {}", gutter, paint(BLUE, "="), note)?;
+        }
+        if let Some(help) = &self.help {
+            writeln!(out, "{} {} help: {}", gutter, paint(BLUE, "="), help)?;
+        }
+        Ok(())
+    }
+
+    /// The diagnostic as a JSON object, for editors and CI to read:
+    ///
+    /// ```text
+    /// {"code":"P0012","column":1,"file":"hello.lox","help":"Statements end with a ';'.",
+    ///  "line":2,"message":"Expect ';' after value.","notes":[],"severity":"error",
+    ///  "span":{"len":5,"start":14}}
+    /// ```
+    ///
+    /// `column` counts characters from 1, and `span` is in bytes; both are
+    /// null when the diagnostic is about a whole line.
+    pub fn to_json(&self, file: &SourceFile) -> serde_json::Value {
+        let quote = self.quote(file.text);
+        let line = quote.as_ref().map_or(self.line, |quote| quote.line);
+        let column = quote.and_then(|quote| {
+            let (start, _) = quote.underline?;
+            Some(quote.text[..start].chars().count() + 1)
+        });
+        let span = self
+            .span
+            .map(|span| serde_json::json!({ "start": span.start, "len": span.len }));
+        serde_json::json!({
+            "severity": match self.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            },
+            "code": self.code,
+            "message": self.message,
+            "file": file.name,
+            "line": line,
+            "column": column,
+            "span": span,
+            "notes": self.notes,
+            "help": self.help,
+        })
+    }
+
+    /// Finds the line the diagnostic is about in `source`, if it's there.
+    fn quote<'a>(&self, source: &'a str) -> Option<Quote<'a>> {
+        // A span into other source text, like an earlier REPL input, is
+        // ignored rather than risk cutting a character in two.
+        let span = self.span.filter(|span| {
+            let end = span.start.checked_add(span.len);
+            end.is_some_and(|end| source.get(span.start..end).is_some())
+        });
+        let Some(span) = span else {
+            let text = source.lines().nth(self.line.checked_sub(1)?)?;
+            return Some(Quote {
+                line: self.line,
+                text,
+                underline: None,
+            });
+        };
+
+        let mut start = span.start.min(source.len());
+        // An error at the end of the file is about what the file ends
+        // with, not the blank space after it.
+        if source[start..].trim().is_empty() {
+            start = start.min(source.trim_end().len());
+        }
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        let start = (start - line_start).min(text.len());
+        // A multi-line token, like a string, is underlined up to the end of
+        // its first line.
+        let end = (start + span.len).min(text.len());
+        Some(Quote {
+            line: source[..line_start].matches('\n').count() + 1,
+            text,
+            underline: Some((start, end)),
+        })
+    }
+}
+
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+/// How many columns `text` takes up in a terminal once its tabs are
+/// expanded: two for most CJK characters and emoji, none for combining
+/// marks.
+fn width(text: &str) -> usize {
+    text.chars()
+        .map(|c| match c {
+            '\t' => TAB_WIDTH,
+            c => c.width().unwrap_or(0),
+        })
+        .sum()
+}
+
+/// A suggestion for the common errors where the message alone may not say
+/// what to do, by their code.
+fn help(code: &str) -> Option<&'static str> {
+    let help = match code {
+        "P0012" => "Statements end with a ';'.",
+        "R0003" => "Declare variables with 'var' before using them.",
+        "L0002" => "Close the string with a '\"'.",
+        "L0003" => "Close the comment with '*/'.",
+        "S0002" => "Give the new variable another name to read the one it shadows.",
+        "S0004" => "An initializer always returns 'this'; use a bare 'return;' to leave it early.",
+        "P0002" => "Only variables and properties can be assigned to.",
+        "R0010" => "Check that each recursive function has a case that stops it.",
+        _ => return None,
+    };
+    Some(help)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, span: Span) -> String {
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: "Oops.".to_string(),
+            line: 1,
+            span: Some(span),
+            notes: Vec::new(),
+            help: None,
+        };
+        let file = SourceFile {
+            name: "test.lox",
+            text: source,
+        };
+        let mut out = Vec::new();
+        diagnostic.render(&file, false, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn caret_lines_up_after_wide_characters() {
+        let source = "print \"日本\" + nil;";
+        let start = source.find("nil").unwrap();
+        let span = Span {
+            start,
+            len: 3,
+            column: 13,
+        };
+        assert_eq!(
+            render(source, span),
+            concat!(
+                "error: Oops.\n",
+                " --> test.lox:1:14\n",
+                "  |\n",
+                "1 | print \"日本\" + nil;\n",
+                "  |                ^^^\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{error::LoxError, intern::Symbol, lexer::Token, suggest, value::Value};
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    /// `None` marks a variable declared without a value, see
+    /// [`declare`](Environment::declare).
+    values: HashMap<Symbol, Option<Value>>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        self.values.insert(name, Some(value));
+    }
+
+    /// Adds a variable that has no value until it is first assigned.
+    pub fn declare(&mut self, name: Symbol) {
+        self.values.insert(name, None);
+    }
+
+    /// The variables defined directly in this scope, in no particular order.
+    pub fn variables(&self) -> impl Iterator<Item = (&Symbol, Option<&Value>)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name, value.as_ref()))
+    }
+
+    /// Walks `distance` scopes up the chain starting at `environment`.
+    fn ancestor(
+        environment: &Rc<RefCell<Environment>>,
+        distance: usize,
+    ) -> Rc<RefCell<Environment>> {
+        let mut environment = environment.clone();
+        for _ in 0..distance {
+            let enclosing = environment
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("resolver produced a distance deeper than the scope chain");
+            environment = enclosing;
+        }
+        environment
+    }
+
+    /// Reads a variable the resolver found exactly `distance` scopes away, or
+    /// `None` if it was declared without a value and not yet assigned.
+    pub fn get_at(
+        environment: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &Symbol,
+    ) -> Option<Value> {
+        Self::ancestor(environment, distance)
+            .borrow()
+            .values
+            .get(name)
+            .cloned()
+            .expect("resolver produced a binding missing from its scope")
+    }
+
+    pub fn assign_at(
+        environment: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &Symbol,
+        value: Value,
+    ) {
+        Self::ancestor(environment, distance)
+            .borrow_mut()
+            .values
+            .insert(name.clone(), Some(value));
+    }
+
+    /// Like [`get_at`](Environment::get_at), but searches the scope chain by
+    /// name.
+    pub fn get(&self, name: &Token) -> Result<Option<Value>, LoxError> {
+        if let Some(value) = self.values.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get(name),
+            None => Err(self.undefined(name)),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), LoxError> {
+        if let Some(slot) = self.values.get_mut(&name.lexeme) {
+            *slot = Some(value);
+            return Ok(());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(self.undefined(name)),
+        }
+    }
+
+    /// The error for using `name` from `environment`, where it isn't
+    /// defined. Unlike the one [`get`](Environment::get) returns, its
+    /// suggestion also considers the local variables in scope.
+    pub fn undefined_from(environment: &Rc<RefCell<Environment>>, name: &Token) -> LoxError {
+        let mut candidates = Vec::new();
+        let mut scope = Some(environment.clone());
+        while let Some(current) = scope {
+            let current = current.borrow();
+            candidates.extend(current.values.keys().cloned());
+            scope = current.enclosing.clone();
+        }
+        LoxError::runtime_at(
+            name,
+            "R0003",
+            suggest::undefined(
+                "variable",
+                &name.lexeme,
+                candidates.iter().map(|name| &**name),
+            ),
+        )
+    }
+
+    /// The error for reading or assigning `name` in the outermost scope,
+    /// where it isn't defined.
+    fn undefined(&self, name: &Token) -> LoxError {
+        let candidates = self.values.keys().map(|name| &**name);
+        LoxError::runtime_at(
+            name,
+            "R0003",
+            suggest::undefined("variable", &name.lexeme, candidates),
+        )
+    }
+}
@@ -1,15 +1,383 @@
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+use crate::lexer::{Span, Token, TokenType};
+
+/// How many of the innermost and outermost frames of a long backtrace
+/// [`LoxError::report`] shows.
+const TRACE_HEAD: usize = 10;
+const TRACE_TAIL: usize = 3;
+
+/// Why a program couldn't be run, or stopped partway through.
 #[derive(Debug)]
-pub struct LoxError {
-    pub line: usize,
+pub enum LoxError {
+    /// The source contains something that isn't a token, like a stray `@`
+    /// or an unterminated string.
+    ScanError {
+        line: usize,
+        code: &'static str,
+        message: String,
+        /// The offending source text, when the error can be pinned to it.
+        span: Option<Span>,
+    },
+    /// The tokens don't form a valid program.
+    ParseError {
+        token: Box<Token>,
+        code: &'static str,
+        message: String,
+    },
+    /// The program parses, but breaks a rule the resolver checks, like
+    /// returning from top-level code.
+    ResolveError {
+        token: Box<Token>,
+        code: &'static str,
+        message: String,
+    },
+    /// The program is valid, but goes over one of the bytecode compiler's
+    /// limits, like the number of locals in a function.
+    CompileError {
+        line: usize,
+        /// The token the compiler had reached, if the limit is about one.
+        token: Option<Box<Token>>,
+        code: &'static str,
+        message: String,
+    },
+    /// The program failed while it was running.
+    RuntimeError {
+        line: usize,
+        code: &'static str,
+        message: String,
+        /// The source text the error is about, when it is known.
+        span: Option<Span>,
+        /// A suggestion for fixing it that depends on how it happened, like
+        /// on what the operands were.
+        help: Option<&'static str>,
+        /// For an error raised inside a function, the calls that led to it,
+        /// innermost first. Empty for errors in top-level code.
+        trace: Vec<TraceFrame>,
+    },
+    /// The program didn't fail by itself, but was stopped for going over
+    /// one of the limits it was run with.
+    LimitExceeded {
+        line: usize,
+        limit: Limit,
+        /// The calls in progress when it was stopped, as for a runtime
+        /// error.
+        trace: Vec<TraceFrame>,
+    },
+}
+
+/// A runtime error from code that doesn't know which line it is running,
+/// like a native function or a list method: the message, and the
+/// [code](crate::codes) to report it with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fault {
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Something the resolver found that is allowed but is likely a mistake.
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub token: Token,
     pub message: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A local variable, function, or class that is never read.
+    UnusedVariable,
+    /// Statements after a `return`, `break`, or `continue` in the same
+    /// block.
+    UnreachableCode,
+    /// A local variable with the same name as one in an enclosing scope.
+    Shadowing,
+}
+
+/// The limits an embedder can run an untrusted program under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// The most statements the tree-walker, or instructions the VM, may
+    /// execute.
+    Steps,
+    /// How long the program may run for.
+    Time,
+    /// How many bytes the VM's heap may hold.
+    Memory,
+}
+
+/// A function that was running when a runtime error occurred, and the line
+/// it had reached.
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    /// The function's name, or `None` for top-level code.
+    pub function: Option<String>,
+    pub line: usize,
+}
+
 impl LoxError {
-    pub fn new(line: usize, message: String) -> Self {
-        Self { line, message }
+    pub fn scan(line: usize, code: &'static str, message: String) -> Self {
+        LoxError::ScanError {
+            line,
+            code,
+            message,
+            span: None,
+        }
+    }
+
+    /// Creates a parse error pointing at `token`, e.g. `Error at 'foo'`.
+    pub fn parse(token: &Token, code: &'static str, message: String) -> Self {
+        LoxError::ParseError {
+            token: Box::new(token.clone()),
+            code,
+            message,
+        }
+    }
+
+    pub fn resolve(token: &Token, code: &'static str, message: String) -> Self {
+        LoxError::ResolveError {
+            token: Box::new(token.clone()),
+            code,
+            message,
+        }
+    }
+
+    pub fn compile(line: usize, code: &'static str, message: String) -> Self {
+        LoxError::CompileError {
+            line,
+            token: None,
+            code,
+            message,
+        }
+    }
+
+    /// Creates a compile error pointing at `token`.
+    pub fn compile_at(token: &Token, code: &'static str, message: String) -> Self {
+        LoxError::CompileError {
+            line: token.line,
+            token: Some(Box::new(token.clone())),
+            code,
+            message,
+        }
+    }
+
+    pub fn runtime(line: usize, code: &'static str, message: String) -> Self {
+        LoxError::RuntimeError {
+            line,
+            code,
+            message,
+            span: None,
+            help: None,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Creates a runtime error pointing at `token`.
+    pub fn runtime_at(token: &Token, code: &'static str, message: String) -> Self {
+        LoxError::runtime(token.line, code, message).with_span(token.span)
+    }
+
+    /// Creates the error that stops a program for going over `limit`.
+    pub fn limit_exceeded(line: usize, limit: Limit) -> Self {
+        LoxError::LimitExceeded {
+            line,
+            limit,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Pins a scan or runtime error to the source text it is about.
+    pub fn with_span(mut self, at: Span) -> Self {
+        if let LoxError::ScanError { span, .. } | LoxError::RuntimeError { span, .. } = &mut self {
+            *span = Some(at);
+        }
+        self
+    }
+
+    /// Adds a suggestion for fixing a runtime error.
+    pub fn with_help(mut self, suggestion: &'static str) -> Self {
+        if let LoxError::RuntimeError { help, .. } = &mut self {
+            *help = Some(suggestion);
+        }
+        self
+    }
+
+    pub fn line(&self) -> usize {
+        match self {
+            LoxError::ParseError { token, .. } | LoxError::ResolveError { token, .. } => token.line,
+            LoxError::ScanError { line, .. }
+            | LoxError::CompileError { line, .. }
+            | LoxError::RuntimeError { line, .. }
+            | LoxError::LimitExceeded { line, .. } => *line,
+        }
     }
 
+    pub fn message(&self) -> &str {
+        match self {
+            LoxError::ScanError { message, .. }
+            | LoxError::ParseError { message, .. }
+            | LoxError::ResolveError { message, .. }
+            | LoxError::CompileError { message, .. }
+            | LoxError::RuntimeError { message, .. } => message,
+            LoxError::LimitExceeded { limit, .. } => match limit {
+                Limit::Steps => "Step limit exceeded.",
+                Limit::Time => "Time limit exceeded.",
+                Limit::Memory => "Out of memory.",
+            },
+        }
+    }
+
+    /// The [code](crate::codes) the error is reported with, like `P0012`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LoxError::ScanError { code, .. }
+            | LoxError::ParseError { code, .. }
+            | LoxError::ResolveError { code, .. }
+            | LoxError::CompileError { code, .. }
+            | LoxError::RuntimeError { code, .. } => code,
+            LoxError::LimitExceeded { limit, .. } => match limit {
+                Limit::Steps => "R0012",
+                Limit::Time => "R0013",
+                Limit::Memory => "R0014",
+            },
+        }
+    }
+
+    /// The token the error is about, if there is one.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            LoxError::ParseError { token, .. } | LoxError::ResolveError { token, .. } => {
+                Some(token)
+            }
+            LoxError::CompileError { token, .. } => token.as_deref(),
+            LoxError::ScanError { .. }
+            | LoxError::RuntimeError { .. }
+            | LoxError::LimitExceeded { .. } => None,
+        }
+    }
+
+    /// The offending source text, when the error can be pinned to it.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            LoxError::ScanError { span, .. } | LoxError::RuntimeError { span, .. } => *span,
+            _ => self.token().map(|token| token.span),
+        }
+    }
+
+    /// The suggestion the error was given for how to fix it, if any.
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            LoxError::RuntimeError { help, .. } => *help,
+            _ => None,
+        }
+    }
+
+    /// Where in the line the error is, as the report puts it: ` at 'foo'`,
+    /// ` at end`, or nothing.
+    pub fn location(&self) -> String {
+        match self.token() {
+            Some(token) if token.token_type == TokenType::Eof => " at end".to_string(),
+            Some(token) => format!(" at '{}'", token.lexeme),
+            None => String::new(),
+        }
+    }
+
+    /// For a runtime error, the calls that led to it, innermost first.
+    pub fn trace(&self) -> &[TraceFrame] {
+        match self {
+            LoxError::RuntimeError { trace, .. } | LoxError::LimitExceeded { trace, .. } => trace,
+            _ => &[],
+        }
+    }
+
+    /// The limit the program was stopped for going over, if that's why it
+    /// stopped.
+    pub fn limit(&self) -> Option<Limit> {
+        match self {
+            LoxError::LimitExceeded { limit, .. } => Some(*limit),
+            _ => None,
+        }
+    }
+
+    /// Prints the error and its backtrace to stderr.
     pub fn report(&self) {
-        eprintln!("[line {}] Error: {}", self.line, self.message);
+        // Like `eprintln!`, except that a closed stderr isn't worth a panic.
+        let _ = self.report_to(&mut io::stderr());
+    }
+
+    /// Writes the error and its backtrace to `out`, as [`LoxError::report`]
+    /// prints them.
+    pub fn report_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", self)?;
+        for line in self.backtrace() {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// The lines of the backtrace as reports show it, one per frame.
+    pub fn backtrace(&self) -> Vec<String> {
+        // Deep recursion would bury the error under thousands of frames, so
+        // only the innermost and outermost are shown.
+        let trace = self.trace();
+        let omitted = trace.len().saturating_sub(TRACE_HEAD + TRACE_TAIL);
+        if omitted == 0 {
+            return trace.iter().map(TraceFrame::to_string).collect();
+        }
+        let head = trace[..TRACE_HEAD].iter().map(TraceFrame::to_string);
+        let tail = trace[trace.len() - TRACE_TAIL..]
+            .iter()
+            .map(TraceFrame::to_string);
+        head.chain([format!("... {} more frames ...", omitted)])
+            .chain(tail)
+            .collect()
+    }
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Error{}: {}",
+            self.line(),
+            self.location(),
+            self.message()
+        )
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+impl Fault {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Fault {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The runtime error the fault is, pointing at `token`.
+    pub fn at(self, token: &Token) -> LoxError {
+        LoxError::runtime_at(token, self.code, self.message)
+    }
+}
+
+/// A message from a native function, which gets the code for natives
+/// that fail.
+impl From<String> for Fault {
+    fn from(message: String) -> Self {
+        Fault::new("R0016", message)
+    }
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(function) => write!(f, "[line {}] in {}()", self.line, function),
+            None => write!(f, "[line {}] in script", self.line),
+        }
     }
 }
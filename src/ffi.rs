@@ -0,0 +1,181 @@
+//! A C API for embedding the tree-walking interpreter, declared in
+//! `include/rslox.h`. Each function here mirrors a method of [`Lox`].
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    ptr,
+};
+
+use crate::{intern::intern, value::Value, Lox};
+
+/// The type of an [`RsloxValue`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RsloxType {
+    Nil,
+    Bool,
+    Number,
+    String,
+}
+
+/// A Lox value passed to or returned from a C function. Only the field
+/// matching `kind` is meaningful.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RsloxValue {
+    pub kind: RsloxType,
+    pub boolean: bool,
+    pub number: f64,
+    /// A NUL-terminated UTF-8 string.
+    pub string: *const c_char,
+}
+
+impl RsloxValue {
+    fn nil() -> Self {
+        Self {
+            kind: RsloxType::Nil,
+            boolean: false,
+            number: 0.0,
+            string: ptr::null(),
+        }
+    }
+}
+
+/// A C function scripts can call. It receives the arguments and the
+/// `user_data` it was registered with, and writes its result to `result`,
+/// returning `true`. Returning `false` raises a runtime error, with
+/// `result` as the message if it is a string.
+///
+/// Argument strings are only valid during the call. A string result is
+/// copied as soon as the function returns.
+pub type RsloxFn = extern "C" fn(
+    args: *const RsloxValue,
+    arg_count: usize,
+    user_data: *mut c_void,
+    result: *mut RsloxValue,
+) -> bool;
+
+/// Creates an interpreter. Free it with [`rslox_free`].
+#[no_mangle]
+pub extern "C" fn rslox_new() -> *mut Lox {
+    Box::into_raw(Box::new(Lox::new()))
+}
+
+/// Frees an interpreter made by [`rslox_new`]. Null is ignored.
+///
+/// # Safety
+///
+/// `lox` must be null or come from [`rslox_new`], and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn rslox_free(lox: *mut Lox) {
+    if !lox.is_null() {
+        drop(Box::from_raw(lox));
+    }
+}
+
+/// Runs the NUL-terminated UTF-8 program `source`, as [`Lox::run`] does,
+/// reporting any error to stderr. Returns 0 on success and 1 on an error.
+///
+/// # Safety
+///
+/// `lox` must come from [`rslox_new`] and `source` must point to a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rslox_run(lox: *mut Lox, source: *const c_char) -> c_int {
+    let lox = &mut *lox;
+    let Ok(source) = CStr::from_ptr(source).to_str() else {
+        eprintln!("Error: Source is not valid UTF-8.");
+        return 1;
+    };
+    match lox.run(source) {
+        Ok(_) => 0,
+        Err(error) => {
+            lox.report(&error);
+            1
+        }
+    }
+}
+
+/// Defines a global function `name` taking `arity` arguments that calls
+/// `function` with `user_data`, as [`Lox::register_native`] does. Returns 0
+/// on success and 1 if `name` isn't valid UTF-8.
+///
+/// # Safety
+///
+/// `lox` must come from [`rslox_new`] and `name` must point to a
+/// NUL-terminated string. `user_data` is passed to `function` as is, so it
+/// must stay valid for as long as scripts can call the function.
+#[no_mangle]
+pub unsafe extern "C" fn rslox_register_fn(
+    lox: *mut Lox,
+    name: *const c_char,
+    arity: usize,
+    function: RsloxFn,
+    user_data: *mut c_void,
+) -> c_int {
+    let lox = &mut *lox;
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return 1;
+    };
+    lox.register_native(name, arity, move |arguments| {
+        call_c_function(function, user_data, arguments)
+    });
+    0
+}
+
+fn call_c_function(
+    function: RsloxFn,
+    user_data: *mut c_void,
+    arguments: &[Value],
+) -> Result<Value, String> {
+    // Owns the argument strings until the call returns.
+    let mut strings = Vec::new();
+    let mut args = Vec::with_capacity(arguments.len());
+    for argument in arguments {
+        let mut value = RsloxValue::nil();
+        match argument {
+            Value::Nil => {}
+            Value::Bool(b) => {
+                value.kind = RsloxType::Bool;
+                value.boolean = *b;
+            }
+            Value::Number(n) => {
+                value.kind = RsloxType::Number;
+                value.number = *n;
+            }
+            Value::String(s) => {
+                let s = CString::new(s.as_str())
+                    .map_err(|_| "Strings passed to C can't contain NUL.".to_string())?;
+                value.kind = RsloxType::String;
+                value.string = s.as_ptr();
+                strings.push(s);
+            }
+            _ => {
+                return Err(
+                    "Native functions only take nil, booleans, numbers, and strings.".to_string(),
+                )
+            }
+        }
+        args.push(value);
+    }
+
+    let mut result = RsloxValue::nil();
+    let ok = function(args.as_ptr(), args.len(), user_data, &mut result);
+    let value = match result.kind {
+        RsloxType::Nil => Value::Nil,
+        RsloxType::Bool => Value::Bool(result.boolean),
+        RsloxType::Number => Value::Number(result.number),
+        RsloxType::String if result.string.is_null() => Value::Nil,
+        // SAFETY: `RsloxFn` requires a string result to be NUL-terminated
+        // and valid until it has been copied, which is here.
+        RsloxType::String => {
+            let s = unsafe { CStr::from_ptr(result.string) };
+            Value::String(intern(&s.to_string_lossy()))
+        }
+    };
+    match (ok, value) {
+        (true, value) => Ok(value),
+        (false, Value::String(message)) => Err(message.to_string()),
+        (false, _) => Err("Native function failed.".to_string()),
+    }
+}
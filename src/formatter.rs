@@ -0,0 +1,249 @@
+//! Source formatting for `rslox fmt`. Works on the token stream, with the
+//! whitespace and comments kept as trivia, so only the spacing and the
+//! indentation change: line breaks stay where they were written, except
+//! that runs of blank lines shrink to one.
+
+use crate::{
+    error::LoxError,
+    lexer::{Scanner, Token, TokenType, TriviaKind},
+    parser::Parser,
+};
+
+const INDENT: &str = "    ";
+
+/// Formats `source`, which has to parse; otherwise its syntax errors are
+/// returned instead.
+pub fn format(source: &str) -> Result<String, Vec<LoxError>> {
+    let tokens = Scanner::with_trivia(source.to_string()).scan_tokens()?;
+    // Formatting code that doesn't parse would mean guessing at what it
+    // was meant to be.
+    Parser::new(tokens.clone()).parse()?;
+
+    let mut formatter = Formatter::default();
+    for (index, token) in tokens.iter().enumerate() {
+        formatter.token(&tokens[index..], token);
+    }
+    Ok(formatter.finish())
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    /// Each open bracket, the line it is on, and whether it indents the
+    /// lines up to its closing bracket. Only the first bracket left open on
+    /// a line does, so `f(fun () {` indents its body once, not twice.
+    brackets: Vec<(TokenType, usize, bool)>,
+    line: usize,
+    /// The previous token, if there is one on the line being written.
+    previous: Option<TokenType>,
+    /// Whether the previous token ends an operand, which tells a binary
+    /// `-` from a negation, and a postfix `++` from a prefix one.
+    after_operand: bool,
+    /// Whether the previous token binds to the one after it, like `(`, `.`,
+    /// or a prefix operator.
+    glued: bool,
+    /// `?`s still waiting for their `:`. Any other `:` ends a `case` label
+    /// or a map key, or splits a slice's bounds.
+    open_conditionals: usize,
+}
+
+impl Formatter {
+    /// Writes `token`, the first of `rest`, and the trivia before it.
+    fn token(&mut self, rest: &[Token], token: &Token) {
+        let mut newlines = 0;
+        for trivia in token.leading_trivia() {
+            match trivia.kind {
+                TriviaKind::Whitespace => {}
+                TriviaKind::Newline => newlines += 1,
+                TriviaKind::Shebang => self.out.push_str(&trivia.text),
+                TriviaKind::LineComment | TriviaKind::BlockComment => {
+                    let indent = self.indent(0);
+                    self.separate(newlines, indent);
+                    if self.previous.is_some() {
+                        self.out.push(' ');
+                    }
+                    self.out.push_str(&trivia.text);
+                    // Whatever follows is spaced out from the comment.
+                    self.previous = Some(TokenType::Semicolon);
+                    self.glued = false;
+                    newlines = 0;
+                }
+            }
+        }
+        if token.kind() == TokenType::Eof {
+            return;
+        }
+
+        // Closing brackets that start a line are at the indentation of the
+        // line that opened them.
+        let closers = rest
+            .iter()
+            .enumerate()
+            .take_while(|&(i, token)| is_closer(token.kind()) && (i == 0 || !starts_line(token)))
+            .count();
+        self.separate(newlines, self.indent(closers));
+
+        let kind = token.kind();
+        let unary = match kind {
+            TokenType::Minus | TokenType::PlusPlus | TokenType::MinusMinus => !self.after_operand,
+            TokenType::Bang | TokenType::Tilde => true,
+            _ => false,
+        };
+        let postfix = matches!(kind, TokenType::PlusPlus | TokenType::MinusMinus) && !unary;
+        let case_label = kind == TokenType::Colon && self.open_conditionals == 0;
+        let slice =
+            case_label && matches!(self.brackets.last(), Some((TokenType::LeftBracket, _, _)));
+        if let Some(previous) = self.previous {
+            // `- -x` can't lose its space and become `--x`.
+            let merges = matches!(
+                (previous, kind),
+                (
+                    TokenType::Minus | TokenType::MinusMinus,
+                    TokenType::Minus | TokenType::MinusMinus
+                ) | (TokenType::PlusPlus, TokenType::PlusPlus)
+            );
+            let glued = self.glued
+                || postfix
+                || case_label
+                || continues_string(token)
+                || matches!(
+                    kind,
+                    TokenType::RightParen
+                        | TokenType::RightBracket
+                        | TokenType::Comma
+                        | TokenType::Semicolon
+                        | TokenType::Dot
+                )
+                || (kind == TokenType::LeftParen && is_callee(previous))
+                || (kind == TokenType::LeftBracket && self.after_operand)
+                || (kind == TokenType::RightBrace && previous == TokenType::LeftBrace);
+            let space = merges || !glued;
+            if space {
+                self.out.push(' ');
+            }
+        }
+        self.out.push_str(token.lexeme());
+
+        match kind {
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => {
+                let indents = !self
+                    .brackets
+                    .iter()
+                    .any(|&(_, line, indents)| indents && line == self.line);
+                self.brackets.push((kind, self.line, indents));
+            }
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                self.brackets.pop();
+            }
+            TokenType::Question => self.open_conditionals += 1,
+            TokenType::Colon if !case_label => self.open_conditionals -= 1,
+            _ => {}
+        }
+        self.previous = Some(kind);
+        self.after_operand = postfix || ends_operand(token);
+        self.glued = unary
+            || slice
+            || matches!(
+                kind,
+                TokenType::LeftParen
+                    | TokenType::LeftBracket
+                    | TokenType::Dot
+                    | TokenType::Interpolation
+            );
+    }
+
+    /// Ends the line being written after `newlines` line breaks in the
+    /// source, keeping at most one blank line, and indents the next one by
+    /// `indent` levels.
+    fn separate(&mut self, newlines: usize, indent: usize) {
+        if newlines == 0 {
+            return;
+        }
+        if !self.out.is_empty() {
+            self.trim_end();
+            self.out.push('\n');
+            if newlines > 1 {
+                self.out.push('\n');
+            }
+        }
+        self.out.push_str(&INDENT.repeat(indent));
+        self.line += 1;
+        self.previous = None;
+        self.glued = false;
+    }
+
+    /// The indentation of a line starting with `closers` closing brackets:
+    /// that of the line with the outermost one's opening bracket.
+    fn indent(&self, closers: usize) -> usize {
+        let before_line = match closers {
+            0 => usize::MAX,
+            _ => self.brackets[self.brackets.len().saturating_sub(closers)].1,
+        };
+        self.brackets
+            .iter()
+            .filter(|&&(_, line, indents)| indents && line < before_line)
+            .count()
+    }
+
+    fn trim_end(&mut self) {
+        let len = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(len);
+    }
+
+    fn finish(mut self) -> String {
+        let len = self.out.trim_end().len();
+        self.out.truncate(len);
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+}
+
+fn starts_line(token: &Token) -> bool {
+    token
+        .leading_trivia()
+        .iter()
+        .any(|trivia| trivia.kind == TriviaKind::Newline)
+}
+
+fn is_closer(kind: TokenType) -> bool {
+    matches!(
+        kind,
+        TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace
+    )
+}
+
+/// Whether a `(` after a token of kind `kind` starts a call's arguments or
+/// a function's parameters, rather than a grouping or a condition.
+fn is_callee(kind: TokenType) -> bool {
+    matches!(
+        kind,
+        TokenType::Identifier
+            | TokenType::RightParen
+            | TokenType::RightBracket
+            | TokenType::This
+            | TokenType::Super
+    )
+}
+
+/// Whether `token` is the part of a string literal after a `${...}`.
+fn continues_string(token: &Token) -> bool {
+    matches!(token.kind(), TokenType::String | TokenType::Interpolation)
+        && token.lexeme().starts_with('}')
+}
+
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token.kind(),
+        TokenType::Identifier
+            | TokenType::Number
+            | TokenType::String
+            | TokenType::RightParen
+            | TokenType::RightBracket
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::This
+    )
+}
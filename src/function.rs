@@ -0,0 +1,128 @@
+use std::{cell::RefCell, fmt::Debug, rc::Rc};
+
+use crate::{
+    callable::LoxCallable,
+    environment::Environment,
+    error::LoxError,
+    intern::intern,
+    interpreter::{Interpreter, Unwind},
+    parser::{Ast, FunctionDecl},
+    value::Value,
+};
+
+pub struct LoxFunction {
+    ast: Rc<Ast>,
+    declaration: Rc<FunctionDecl>,
+    closure: Rc<RefCell<Environment>>,
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        ast: Rc<Ast>,
+        declaration: Rc<FunctionDecl>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        Self {
+            ast,
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    /// Returns a copy of this method whose closure binds `this` to `this`: an
+    /// instance, or for a class method, the class.
+    pub fn bind(&self, this: Value) -> LoxFunction {
+        let mut environment = Environment::with_enclosing(self.closure.clone());
+        environment.define(intern("this"), this);
+        LoxFunction::new(
+            self.ast.clone(),
+            self.declaration.clone(),
+            Rc::new(RefCell::new(environment)),
+            self.is_initializer,
+        )
+    }
+
+    pub fn name(&self) -> &str {
+        &self.declaration.name.lexeme
+    }
+
+    /// Runs the body, leaving a tail call in `Err(Unwind::TailCall)` for the
+    /// caller to make.
+    fn execute_body(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, Unwind> {
+        let mut environment = Environment::with_enclosing(self.closure.clone());
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            environment.define(param.lexeme.clone(), argument);
+        }
+
+        let enclosing_ast = std::mem::replace(&mut interpreter.ast, self.ast.clone());
+        let result = interpreter.execute_block(&self.declaration.body, environment);
+        interpreter.ast = enclosing_ast;
+
+        let result = match result {
+            Ok(()) => Value::Nil,
+            Err(Unwind::Return(value)) => value,
+            Err(unwind) => return Err(unwind),
+        };
+
+        // Initializers always hand back the instance, even on an early `return;`.
+        if self.is_initializer {
+            let this = Environment::get_at(&self.closure, 0, &intern("this"));
+            return Ok(this.expect("`this` is bound on creation"));
+        }
+        Ok(result)
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    /// Tail calls to Lox functions run in a loop here rather than recursing.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, LoxError> {
+        let mut result = self.execute_body(interpreter, arguments);
+        loop {
+            match result {
+                Ok(value) => return Ok(value),
+                Err(Unwind::Error(error)) => return Err(error),
+                Err(Unwind::TailCall {
+                    callee: Value::Function(function),
+                    arguments,
+                    ..
+                }) if arguments.len() == function.arity() => {
+                    interpreter.replace_callee(Value::Function(function.clone()));
+                    result = function.execute_body(interpreter, arguments);
+                }
+                // Anything else, including a call that's about to fail, goes
+                // through the usual path.
+                Err(Unwind::TailCall {
+                    callee,
+                    line,
+                    span,
+                    arguments,
+                }) => return interpreter.call(callee, line, span, arguments),
+                Err(Unwind::Return(_)) => unreachable!("the body's return is handled"),
+                Err(Unwind::Break | Unwind::Continue) => {
+                    unreachable!("the resolver rejects break and continue outside loops")
+                }
+            }
+        }
+    }
+}
+
+impl Debug for LoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+    ops::Deref,
+    rc::Rc,
+};
+
+/// Table size at which unreferenced strings are first pruned.
+const INITIAL_PRUNE_AT: usize = 1024;
+
+/// An interned string. Two symbols are equal exactly when they share the same
+/// table entry, so comparing or hashing one never looks at its bytes.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).cast::<u8>().hash(state);
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&*self.0, f)
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&*self.0, f)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        intern(s)
+    }
+}
+
+struct Interner {
+    strings: HashSet<Rc<str>>,
+    prune_at: usize,
+}
+
+impl Interner {
+    fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(existing) = self.strings.get(s) {
+            return Symbol(existing.clone());
+        }
+
+        if self.strings.len() >= self.prune_at {
+            self.prune();
+        }
+
+        let string: Rc<str> = Rc::from(s);
+        self.strings.insert(string.clone());
+        Symbol(string)
+    }
+
+    /// Drops strings that nothing outside the table refers to any more.
+    fn prune(&mut self) {
+        self.strings.retain(|s| Rc::strong_count(s) > 1);
+        self.prune_at = (self.strings.len() * 2).max(INITIAL_PRUNE_AT);
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+        strings: HashSet::new(),
+        prune_at: INITIAL_PRUNE_AT,
+    });
+}
+
+/// Returns the unique [`Symbol`] for `s`, adding it to the table if needed.
+pub fn intern(s: &str) -> Symbol {
+    INTERNER.with(|interner| interner.borrow_mut().intern(s))
+}
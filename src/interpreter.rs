@@ -0,0 +1,1318 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    callable::LoxCallable,
+    class::{undefined_property, LoxClass, LoxInstance},
+    diagnostic,
+    environment::Environment,
+    error::{Fault, Limit, LoxError, TraceFrame},
+    function::LoxFunction,
+    intern::{intern, Symbol},
+    introspect,
+    lexer::{Span, Token, TokenType},
+    list::{self, ListMethod},
+    map::{self, LoxMap, MapMethod},
+    native::{natives, Capability, Deadline, Input, NativeFunction, TypedNative, CONSTANTS},
+    parser::{Ast, Expr, ExprId, ExprRef, FunctionDecl, Stmt, SHORT_CHAIN},
+    string::{self, StringMethod},
+    value::{shift_left, shift_right, to_integer, ToLox, Value},
+};
+
+/// Non-local exits that unwind through `execute`: runtime errors, `return`,
+/// `break`, and `continue`.
+pub enum Unwind {
+    Error(LoxError),
+    Return(Value),
+    /// `return callee(arguments);`, left for the function being returned
+    /// from to call in its own place so tail recursion doesn't grow the Rust
+    /// stack.
+    TailCall {
+        callee: Value,
+        /// The line of the call, and the text its errors point at.
+        line: usize,
+        span: Span,
+        arguments: Vec<Value>,
+    },
+    Break,
+    Continue,
+}
+
+impl From<LoxError> for Unwind {
+    fn from(error: LoxError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+/// How many calls deep a program may go before it is stopped with a stack
+/// overflow error, in either backend.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// How many bytes of the Rust stack the tree-walker's calls may take by
+/// default: half the 1 MiB a WebAssembly module or a Windows main thread
+/// gets, leaving the rest for the code around the run.
+pub const DEFAULT_MAX_STACK: usize = 512 * 1024;
+
+/// How many steps run between checks of the clock against a timeout.
+pub(crate) const CLOCK_CHECK_INTERVAL: u64 = 1024;
+
+/// Settings that change how the interpreter runs programs.
+#[derive(Debug, Clone)]
+pub struct InterpreterOptions {
+    /// Make `var a;` leave `a` without a value, so reading it before an
+    /// assignment is a runtime error instead of producing `nil`.
+    pub error_on_uninitialized: bool,
+    /// Calls nested deeper than this are a "Stack overflow." error.
+    pub max_call_depth: usize,
+    /// Calls that take more than this many bytes of the Rust stack, which
+    /// each one recurses on, are a "Stack overflow." error too. The stack
+    /// of the thread running the interpreter has to be bigger.
+    pub max_stack: usize,
+    /// Stop each run after this many statements with a
+    /// [`Limit::Steps`] error.
+    pub max_steps: Option<u64>,
+    /// Stop each run that takes longer than this with a [`Limit::Time`]
+    /// error.
+    pub timeout: Option<Duration>,
+    /// Whether natives that read and write files are defined.
+    pub allow_fs: bool,
+    /// Whether natives that make network connections are defined.
+    pub allow_net: bool,
+    /// Whether natives that start processes, read the environment, or exit
+    /// are defined.
+    pub allow_process: bool,
+}
+
+impl InterpreterOptions {
+    /// The default options, minus every [`Capability`], for running
+    /// programs that shouldn't reach outside the interpreter.
+    pub fn sandboxed() -> Self {
+        Self {
+            allow_fs: false,
+            allow_net: false,
+            allow_process: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Fs => self.allow_fs,
+            Capability::Net => self.allow_net,
+            Capability::Process => self.allow_process,
+        }
+    }
+}
+
+impl Default for InterpreterOptions {
+    fn default() -> Self {
+        Self {
+            error_on_uninitialized: false,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_stack: DEFAULT_MAX_STACK,
+            max_steps: None,
+            timeout: None,
+            allow_fs: true,
+            allow_net: true,
+            allow_process: true,
+        }
+    }
+}
+
+/// A call in progress, kept so runtime errors can report how they were
+/// reached.
+struct CallSite {
+    callee: Value,
+    line: usize,
+    span: Span,
+}
+
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    locals: HashMap<ExprId, usize>,
+    call_stack: Vec<CallSite>,
+    options: InterpreterOptions,
+    /// Statements executed so far in the current run.
+    steps: u64,
+    /// The line of the statement being executed.
+    line: usize,
+    /// When the current run has to stop by, given a timeout.
+    deadline: Deadline,
+    /// Where on the Rust stack the current run started, which the stack
+    /// its calls take is measured from.
+    stack_base: usize,
+    /// Set from elsewhere, e.g. a signal handler, to stop the current run.
+    interrupt: Option<Arc<AtomicBool>>,
+    /// The arena holding the expressions of the code being run. Calling a
+    /// function switches to the one its declaration was parsed into.
+    pub(crate) ast: Rc<Ast>,
+    /// Where `print` writes.
+    stdout: Box<dyn Write>,
+    /// Where [`Interpreter::report`] writes.
+    stderr: Box<dyn Write>,
+    /// Where `readLine` reads.
+    stdin: Input,
+    /// The value of the link of a long [chain](Ast::chain) evaluated last,
+    /// for the next link to continue from.
+    chained: Option<(ExprRef, Value)>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_options(InterpreterOptions::default())
+    }
+
+    pub fn with_options(options: InterpreterOptions) -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let mut interpreter = Self {
+            environment: globals.clone(),
+            globals,
+            locals: HashMap::new(),
+            call_stack: Vec::new(),
+            options,
+            steps: 0,
+            line: 0,
+            deadline: Deadline::default(),
+            stack_base: stack_address(),
+            interrupt: None,
+            ast: Rc::default(),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            stdin: Input::default(),
+            chained: None,
+        };
+        let allowed = |capability| interpreter.options.allows(capability);
+        for native in natives(allowed, &interpreter.stdin, &interpreter.deadline) {
+            interpreter.define_native(native);
+        }
+        for native in introspect::natives() {
+            interpreter.define_native(native);
+        }
+        for (name, value) in CONSTANTS {
+            interpreter
+                .globals
+                .borrow_mut()
+                .define(intern(name), Value::Number(value));
+        }
+        interpreter
+    }
+
+    /// Defines a global function `name` that scripts can call with `arity`
+    /// arguments to run `function`. Like the built-in natives, it only sees
+    /// nil, booleans, numbers, strings, and lists and maps of them, and may
+    /// return the same. The bytecode VM hands it copies of lists and maps.
+    /// An `Err` becomes a runtime error at the call site. Arguments can be
+    /// unpacked with [`FromLox`](crate::value::FromLox), and anything
+    /// [`ToLox`] can be returned.
+    pub fn register_native<R: ToLox>(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<R, String> + 'static,
+    ) {
+        self.define_native(NativeFunction::new(name, arity, function));
+    }
+
+    /// Like [`register_native`](Interpreter::register_native), for a
+    /// `function` whose parameters are each [`FromLox`](crate::value::FromLox),
+    /// which receives its arguments already converted. See
+    /// [`NativeFunction::typed`].
+    pub fn register_typed_native<Args, F: TypedNative<Args> + 'static>(
+        &mut self,
+        name: &str,
+        function: F,
+    ) {
+        self.define_native(NativeFunction::typed(name, function));
+    }
+
+    fn define_native(&mut self, native: NativeFunction) {
+        self.globals
+            .borrow_mut()
+            .define(intern(&native.name), Value::Native(Rc::new(native)));
+    }
+
+    /// Sends `print` output to `stdout` and error reports to `stderr`
+    /// instead of the process's own, e.g. to capture them in a buffer.
+    pub fn set_output(&mut self, stdout: Box<dyn Write>, stderr: Box<dyn Write>) {
+        self.stdout = stdout;
+        self.stderr = stderr;
+    }
+
+    /// Has `readLine` and `readNumber` read from `stdin` instead of the
+    /// process's own, e.g. to feed a script canned input.
+    pub fn set_input(&mut self, stdin: Box<dyn BufRead>) {
+        *self.stdin.borrow_mut() = Some(stdin);
+    }
+
+    /// Stops the run in progress with an "Interrupted." error once `flag`
+    /// is set, clearing it again. It is checked before every statement, so
+    /// it can be set from another thread or a signal handler.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
+
+    /// Each global variable's name and its value as `print` would show it,
+    /// sorted by name.
+    pub fn globals(&self) -> Vec<(String, String)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .borrow()
+            .variables()
+            .map(|(name, value)| {
+                let value = value.map_or("<uninitialized>".to_string(), Value::to_lox_string);
+                (name.to_string(), value)
+            })
+            .collect();
+        globals.sort();
+        globals
+    }
+
+    /// The properties that can be accessed on the global variable `name`:
+    /// an instance's fields and methods, a class's class methods, or a
+    /// list's or map's methods. Sorted, and empty if `name` holds anything else.
+    pub fn members(&self, name: &str) -> Vec<String> {
+        let value = self
+            .globals
+            .borrow()
+            .variables()
+            .find(|(global, _)| global.as_str() == name)
+            .and_then(|(_, value)| value.cloned());
+        let mut members: Vec<String> = match value {
+            Some(Value::Instance(instance)) => {
+                let instance = instance.borrow();
+                instance
+                    .field_names()
+                    .cloned()
+                    .chain(instance.class.method_names())
+                    .map(|name| name.to_string())
+                    .collect()
+            }
+            Some(Value::Class(class)) => class
+                .class_method_names()
+                .into_iter()
+                .map(|name| name.to_string())
+                .collect(),
+            Some(Value::List(_)) => ListMethod::ALL
+                .iter()
+                .map(|method| method.name().to_string())
+                .collect(),
+            Some(Value::Map(_)) => MapMethod::ALL
+                .iter()
+                .map(|method| method.name().to_string())
+                .collect(),
+            Some(Value::String(_)) => StringMethod::ALL
+                .iter()
+                .map(|method| method.name().to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+        members.sort();
+        members.dedup();
+        members
+    }
+
+    /// Writes `error` and its backtrace to this interpreter's stderr.
+    pub fn report(&mut self, error: &LoxError) {
+        let _ = error.report_to(&mut self.stderr);
+    }
+
+    /// Records the scope depths computed by the resolver.
+    pub fn resolve(&mut self, locals: HashMap<ExprId, usize>) {
+        self.locals.extend(locals);
+    }
+
+    pub fn interpret(&mut self, ast: &Rc<Ast>) -> Result<(), LoxError> {
+        self.run(ast).map(|_| ())
+    }
+
+    /// Runs a program like [`Interpreter::interpret`], producing the value
+    /// of its final statement if that is an expression statement, or else
+    /// nil.
+    pub fn run(&mut self, ast: &Rc<Ast>) -> Result<Value, LoxError> {
+        self.ast = ast.clone();
+        self.steps = 0;
+        self.deadline
+            .set(self.options.timeout.map(|timeout| Instant::now() + timeout));
+        self.stack_base = stack_address();
+        let statements = ast.statements();
+        for (index, statement) in statements.iter().enumerate() {
+            let result = match statement {
+                Stmt::Expression { expression } if index == statements.len() - 1 => {
+                    return self.evaluate(*expression);
+                }
+                _ => self.execute(statement),
+            };
+            match result {
+                Ok(()) => {}
+                Err(Unwind::Error(error)) => return Err(error),
+                // A top-level `return` simply ends the script.
+                Err(Unwind::Return(value)) => return Ok(value),
+                Err(Unwind::TailCall {
+                    callee,
+                    line,
+                    span,
+                    arguments,
+                }) => return self.call(callee, line, span, arguments),
+                Err(Unwind::Break | Unwind::Continue) => {
+                    unreachable!("the resolver rejects break and continue outside loops")
+                }
+            }
+        }
+        Ok(Value::Nil)
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        if let Some(line) = stmt.line(&self.ast) {
+            self.line = line;
+        }
+        self.check_limits()?;
+        match stmt {
+            Stmt::Block { statements } => {
+                let environment = Environment::with_enclosing(self.environment.clone());
+                self.execute_block(statements, environment)
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                class_methods,
+            } => {
+                let superclass = match superclass {
+                    Some(expr) => match self.evaluate(*expr)? {
+                        Value::Class(class) => Some(class),
+                        _ => {
+                            return Err(LoxError::runtime_at(
+                                name,
+                                "R0009",
+                                "Superclass must be a class.".to_string(),
+                            )
+                            .into())
+                        }
+                    },
+                    None => None,
+                };
+
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), Value::Nil);
+
+                // Methods of a subclass close over an extra scope binding `super`.
+                let enclosing = self.environment.clone();
+                if let Some(superclass) = &superclass {
+                    let mut environment = Environment::with_enclosing(enclosing.clone());
+                    environment.define(intern("super"), Value::Class(superclass.clone()));
+                    self.environment = Rc::new(RefCell::new(environment));
+                }
+
+                let methods = self.methods(methods, true);
+                let class_methods = self.methods(class_methods, false);
+
+                self.environment = enclosing;
+
+                let class = LoxClass::new(name.lexeme.clone(), superclass, methods, class_methods);
+                self.environment
+                    .borrow_mut()
+                    .assign(name, Value::Class(Rc::new(class)))?;
+                Ok(())
+            }
+            Stmt::Expression { expression } => {
+                self.evaluate(*expression)?;
+                Ok(())
+            }
+            Stmt::Function(declaration) => {
+                let function = LoxFunction::new(
+                    self.ast.clone(),
+                    declaration.clone(),
+                    self.environment.clone(),
+                    false,
+                );
+                self.environment.borrow_mut().define(
+                    declaration.name.lexeme.clone(),
+                    Value::Function(Rc::new(function)),
+                );
+                Ok(())
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(*condition)?.is_truthy() {
+                    self.execute(then_branch)
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)
+                } else {
+                    Ok(())
+                }
+            }
+            Stmt::Print { expression } => {
+                let value = self.evaluate(*expression)?;
+                writeln!(self.stdout, "{}", value.to_lox_string()).map_err(|error| {
+                    let line = self.ast.line(*expression);
+                    LoxError::runtime(line, "R0016", format!("Error writing output: {}", error))
+                })?;
+                Ok(())
+            }
+            Stmt::Return { value, .. } => Err(match value {
+                Some(value) => self.return_value(*value)?,
+                None => Unwind::Return(Value::Nil),
+            }),
+            Stmt::Var { name, initializer } => {
+                let value = match initializer {
+                    Some(initializer) => self.evaluate(*initializer)?,
+                    None if self.options.error_on_uninitialized => {
+                        self.environment.borrow_mut().declare(name.lexeme.clone());
+                        return Ok(());
+                    }
+                    None => Value::Nil,
+                };
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), value);
+                Ok(())
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                while self.evaluate(*condition)?.is_truthy() {
+                    match self.execute(body) {
+                        Ok(()) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
+                    if let Some(increment) = increment {
+                        self.evaluate(*increment)?;
+                    }
+                }
+                Ok(())
+            }
+            Stmt::Break { .. } => Err(Unwind::Break),
+            Stmt::Continue { .. } => Err(Unwind::Continue),
+        }
+    }
+
+    /// Runs `statements` in `environment`, restoring the previous environment
+    /// afterwards even if one of them fails.
+    pub(crate) fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Environment,
+    ) -> Result<(), Unwind> {
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+
+        let result = statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement));
+
+        self.environment = previous;
+        result
+    }
+
+    /// Evaluates a long [chain](Ast::chain), like `a + b + c + ...`. Each
+    /// link starts by evaluating the one it continues, so evaluating the
+    /// last would recurse all the way down it. Instead its links are
+    /// evaluated from the innermost out, each handing its value to the next
+    /// through `chained`.
+    fn evaluate_chain(&mut self, ast: &Ast, expr: ExprRef) -> Result<Value, LoxError> {
+        let (_, links) = ast.chain(expr);
+        for &link in &links[..links.len() - 1] {
+            let value = self.evaluate(link)?;
+            self.chained = Some((link, value));
+        }
+        self.evaluate(expr)
+    }
+
+    fn evaluate(&mut self, expr: ExprRef) -> Result<Value, LoxError> {
+        if let Some((_, value)) = self.chained.take_if(|(link, _)| *link == expr) {
+            return Ok(value);
+        }
+        let ast = self.ast.clone();
+        // While a link's value is being handed on, `expr` is the next link
+        // of a chain already being evaluated.
+        if self.chained.is_none() && ast.chain_length(expr) > SHORT_CHAIN {
+            return self.evaluate_chain(&ast, expr);
+        }
+        match &ast[expr] {
+            Expr::Assign { id, name, value } => {
+                let value = self.evaluate(*value)?;
+                match self.locals.get(id) {
+                    Some(distance) => Environment::assign_at(
+                        &self.environment,
+                        *distance,
+                        &name.lexeme,
+                        value.clone(),
+                    ),
+                    None => {
+                        let assigned = self.globals.borrow_mut().assign(name, value.clone());
+                        assigned
+                            .map_err(|_| Environment::undefined_from(&self.environment, name))?
+                    }
+                }
+                Ok(value)
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.evaluate(*left)?;
+                let right = self.evaluate(*right)?;
+                self.binary(operator, left, right)
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                let span = call_span(&self.ast, *callee, paren);
+                let callee = self.evaluate(*callee)?;
+                let arguments = self.arguments(arguments)?;
+                self.call(callee, paren.line, span, arguments)
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(*condition)?.is_truthy() {
+                    self.evaluate(*then_branch)
+                } else {
+                    self.evaluate(*else_branch)
+                }
+            }
+            Expr::Lambda { declaration } => {
+                let function = LoxFunction::new(
+                    self.ast.clone(),
+                    declaration.clone(),
+                    self.environment.clone(),
+                    false,
+                );
+                Ok(Value::Function(Rc::new(function)))
+            }
+            Expr::Get { object, name } => {
+                let object = self.evaluate(*object)?;
+                get_property(&object, name)
+            }
+            Expr::Grouping { expression } => self.evaluate(*expression),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.evaluate(*object)?;
+                let index = self.evaluate(*index)?;
+                get_index(&object, &index).map_err(|fault| fault.at(bracket))
+            }
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+            } => {
+                let object = self.evaluate(*object)?;
+                let start = start.map_or(Ok(Value::Nil), |start| self.evaluate(start))?;
+                let end = end.map_or(Ok(Value::Nil), |end| self.evaluate(end))?;
+                get_slice(&object, &start, &end).map_err(|fault| fault.at(bracket))
+            }
+            Expr::List { elements, .. } => {
+                let elements = self.arguments(elements)?;
+                Ok(Value::List(Rc::new(RefCell::new(elements))))
+            }
+            Expr::Map { brace, entries } => {
+                let mut map = LoxMap::default();
+                for (key, value) in entries {
+                    let key = self.evaluate(*key)?;
+                    let key = map::key(&key).map_err(|fault| fault.at(brace))?;
+                    map.insert(key, self.evaluate(*value)?);
+                }
+                Ok(Value::Map(Rc::new(RefCell::new(map))))
+            }
+            Expr::Literal { value } => Ok(Value::from(value)),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.evaluate(*left)?;
+
+                if operator.token_type == TokenType::Or {
+                    if left.is_truthy() {
+                        return Ok(left);
+                    }
+                } else if !left.is_truthy() {
+                    return Ok(left);
+                }
+
+                self.evaluate(*right)
+            }
+            Expr::Set {
+                object,
+                name,
+                operator,
+                value,
+            } => {
+                let object = self.evaluate(*object)?;
+                let current = match operator {
+                    Some(_) => Some(get_property(&object, name)?),
+                    None => None,
+                };
+                let Value::Instance(instance) = object else {
+                    return Err(LoxError::runtime_at(
+                        name,
+                        "R0006",
+                        "Only instances have fields.".to_string(),
+                    ));
+                };
+
+                let mut value = self.evaluate(*value)?;
+                if let (Some(operator), Some(current)) = (operator, current) {
+                    value = self.binary(operator, current, value)?;
+                }
+                instance.borrow_mut().set(name, value.clone());
+                Ok(value)
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                operator,
+                value,
+            } => {
+                let object = self.evaluate(*object)?;
+                let index = self.evaluate(*index)?;
+                let value = match operator {
+                    Some(operator) => {
+                        let current =
+                            get_index(&object, &index).map_err(|fault| fault.at(bracket))?;
+                        let value = self.evaluate(*value)?;
+                        self.binary(operator, current, value)?
+                    }
+                    None => self.evaluate(*value)?,
+                };
+                set_index(&object, &index, value.clone()).map_err(|fault| fault.at(bracket))?;
+                Ok(value)
+            }
+            Expr::Super {
+                id,
+                keyword,
+                method,
+            } => {
+                let distance = self.locals[id];
+                let superclass = Environment::get_at(&self.environment, distance, &keyword.lexeme);
+                // `this` is always bound one scope inside the one holding `super`.
+                let this = Environment::get_at(&self.environment, distance - 1, &intern("this"));
+
+                let (Some(Value::Class(superclass)), Some(this)) = (superclass, this) else {
+                    unreachable!("resolver guarantees 'super' and 'this' bindings");
+                };
+
+                // In a class method `this` is a class, so `super` finds class methods.
+                let on_class = matches!(this, Value::Class(_));
+                let found = if on_class {
+                    superclass.find_class_method(&method.lexeme)
+                } else {
+                    superclass.find_method(&method.lexeme)
+                };
+                match found {
+                    Some(found) => Ok(Value::Function(Rc::new(found.bind(this)))),
+                    None => {
+                        let methods = if on_class {
+                            superclass.class_method_names()
+                        } else {
+                            superclass.method_names()
+                        };
+                        Err(undefined_property(method, &methods))
+                    }
+                }
+            }
+            Expr::This { id, keyword } => self.look_up_variable(*id, keyword),
+            Expr::Unary { operator, right } => {
+                let right = self.evaluate(*right)?;
+                match operator.token_type {
+                    TokenType::Minus => Ok(Value::Number(-number_operand(operator, &right)?)),
+                    TokenType::Bang => Ok(Value::Bool(!right.is_truthy())),
+                    TokenType::Tilde => {
+                        Ok(Value::Number(!integer_operand(operator, &right)? as f64))
+                    }
+                    TokenType::Interpolation => Ok(Value::String(intern(&right.to_lox_string()))),
+                    _ => unreachable!("invalid unary operator"),
+                }
+            }
+            Expr::Variable { id, name } => self.look_up_variable(*id, name),
+        }
+    }
+
+    /// Creates the functions for a class's methods, closing over the current
+    /// environment.
+    fn methods(
+        &self,
+        declarations: &[Rc<FunctionDecl>],
+        allow_initializer: bool,
+    ) -> HashMap<Symbol, Rc<LoxFunction>> {
+        declarations
+            .iter()
+            .map(|method| {
+                let function = LoxFunction::new(
+                    self.ast.clone(),
+                    method.clone(),
+                    self.environment.clone(),
+                    allow_initializer && method.name.lexeme == "init",
+                );
+                (method.name.lexeme.clone(), Rc::new(function))
+            })
+            .collect()
+    }
+
+    fn look_up_variable(&self, id: ExprId, name: &Token) -> Result<Value, LoxError> {
+        let value = match self.locals.get(&id) {
+            Some(distance) => Environment::get_at(&self.environment, *distance, &name.lexeme),
+            None => {
+                let value = self.globals.borrow().get(name);
+                value.map_err(|_| Environment::undefined_from(&self.environment, name))?
+            }
+        };
+        value.ok_or_else(|| {
+            LoxError::runtime_at(
+                name,
+                "R0004",
+                format!("Uninitialized variable '{}'.", name.lexeme),
+            )
+        })
+    }
+
+    /// What `return value;` unwinds with. A call, including one in either
+    /// branch of a `?:`, is left for the function returning to make in its
+    /// own place.
+    fn return_value(&mut self, value: ExprRef) -> Result<Unwind, LoxError> {
+        let ast = self.ast.clone();
+        match &ast[value] {
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                let span = call_span(&ast, *callee, paren);
+                let callee = self.evaluate(*callee)?;
+                let arguments = self.arguments(arguments)?;
+                Ok(Unwind::TailCall {
+                    callee,
+                    line: paren.line,
+                    span,
+                    arguments,
+                })
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(*condition)?.is_truthy() {
+                    self.return_value(*then_branch)
+                } else {
+                    self.return_value(*else_branch)
+                }
+            }
+            _ => Ok(Unwind::Return(self.evaluate(value)?)),
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[ExprRef]) -> Result<Vec<Value>, LoxError> {
+        arguments
+            .iter()
+            .map(|argument| self.evaluate(*argument))
+            .collect()
+    }
+
+    pub(crate) fn call(
+        &mut self,
+        callee: Value,
+        line: usize,
+        span: Span,
+        arguments: Vec<Value>,
+    ) -> Result<Value, LoxError> {
+        let callable: &dyn LoxCallable = match &callee {
+            Value::Function(function) => function.as_ref(),
+            Value::Native(native) => native.as_ref(),
+            Value::Class(class) => class,
+            _ => {
+                return Err(LoxError::runtime(
+                    line,
+                    "R0007",
+                    "Can only call functions and classes.".to_string(),
+                )
+                .with_span(span))
+            }
+        };
+
+        let arity = callable.arity();
+        if arguments.len() != arity {
+            return Err(LoxError::runtime(
+                line,
+                "R0008",
+                format!("Expected {} arguments but got {}.", arity, arguments.len()),
+            )
+            .with_span(span));
+        }
+        if self.call_stack.len() >= self.options.max_call_depth
+            || stack_address().abs_diff(self.stack_base) > self.options.max_stack
+        {
+            return Err(
+                LoxError::runtime(line, "R0010", "Stack overflow.".to_string()).with_span(span),
+            );
+        }
+
+        self.call_stack.push(CallSite {
+            callee: callee.clone(),
+            line,
+            span,
+        });
+        let mut result = callable.call(self, arguments);
+        // The innermost call an error passes through records the whole stack.
+        if let Err(
+            LoxError::RuntimeError { line, trace, .. }
+            | LoxError::LimitExceeded { line, trace, .. },
+        ) = &mut result
+        {
+            if trace.is_empty() {
+                *trace = self.backtrace(*line);
+            }
+        }
+        self.call_stack.pop();
+        result
+    }
+
+    /// Records that the innermost call in progress has handed over to a tail
+    /// call of `callee`, which now takes its place in backtraces.
+    pub(crate) fn replace_callee(&mut self, callee: Value) {
+        if let Some(site) = self.call_stack.last_mut() {
+            site.callee = callee;
+        }
+    }
+
+    /// Counts a step towards the limits the interpreter was given and fails
+    /// once one is exceeded.
+    fn check_limits(&mut self) -> Result<(), LoxError> {
+        self.steps += 1;
+        if self.options.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(LoxError::limit_exceeded(self.line, Limit::Steps));
+        }
+        if let Some(interrupt) = &self.interrupt {
+            if interrupt.load(Ordering::Relaxed) {
+                interrupt.store(false, Ordering::Relaxed);
+                return Err(LoxError::runtime(
+                    self.line,
+                    "R0015",
+                    "Interrupted.".to_string(),
+                ));
+            }
+        }
+        // Reading the clock costs more than a statement, so only look now
+        // and then.
+        if self.steps.is_multiple_of(CLOCK_CHECK_INTERVAL) {
+            self.check_deadline()?;
+        }
+        Ok(())
+    }
+
+    /// Fails once the run is past its deadline. Also checked as each native
+    /// returns, since one can take far longer than a statement.
+    pub(crate) fn check_deadline(&self) -> Result<(), LoxError> {
+        if self
+            .deadline
+            .get()
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(LoxError::limit_exceeded(self.line, Limit::Time));
+        }
+        Ok(())
+    }
+
+    /// The runtime error `fault` is, pointing at the innermost call in
+    /// progress.
+    pub(crate) fn call_fault(&self, fault: Fault) -> LoxError {
+        match self.call_stack.last() {
+            Some(site) => {
+                LoxError::runtime(site.line, fault.code, fault.message).with_span(site.span)
+            }
+            None => LoxError::runtime(0, fault.code, fault.message),
+        }
+    }
+
+    /// Lists the calls in progress, innermost first, given the line the
+    /// innermost one has reached. Each caller is at the line of its call.
+    fn backtrace(&self, line: usize) -> Vec<TraceFrame> {
+        let mut line = line;
+        let mut frames = Vec::new();
+        for site in self.call_stack.iter().rev() {
+            let function = match &site.callee {
+                Value::Function(function) => function.name().to_string(),
+                Value::Class(class) => class.name.to_string(),
+                // Natives fail at their call site, already the line reported
+                // for the caller.
+                Value::Native(_) => continue,
+                _ => unreachable!("only callables are called"),
+            };
+            frames.push(TraceFrame {
+                function: Some(function),
+                line,
+            });
+            line = site.line;
+        }
+        // A native called from top-level code leaves nothing to trace.
+        if frames.is_empty() {
+            return frames;
+        }
+        frames.push(TraceFrame {
+            function: None,
+            line,
+        });
+        frames
+    }
+
+    fn binary(&mut self, operator: &Token, left: Value, right: Value) -> Result<Value, LoxError> {
+        match operator.token_type {
+            // Both operands are evaluated for their effects; the right one wins.
+            TokenType::Comma => Ok(right),
+            TokenType::EqualEqual => Ok(Value::Bool(left == right)),
+            TokenType::BangEqual => Ok(Value::Bool(left != right)),
+            TokenType::Plus => match (left, right) {
+                (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l + r)),
+                (Value::String(l), Value::String(r)) => {
+                    Ok(Value::String(intern(&format!("{}{}", l, r))))
+                }
+                (left, right) => {
+                    let error = LoxError::runtime_at(
+                        operator,
+                        "R0002",
+                        "Operands must be two numbers or two strings.".to_string(),
+                    );
+                    if matches!(left, Value::String(_)) || matches!(right, Value::String(_)) {
+                        Err(error.with_help(diagnostic::INTERPOLATE))
+                    } else {
+                        Err(error)
+                    }
+                }
+            },
+            TokenType::Ampersand
+            | TokenType::Pipe
+            | TokenType::Caret
+            | TokenType::LessLess
+            | TokenType::GreaterGreater => {
+                let (l, r) = integer_operands(operator, &left, &right)?;
+                let result = match operator.token_type {
+                    TokenType::Ampersand => l & r,
+                    TokenType::Pipe => l | r,
+                    TokenType::Caret => l ^ r,
+                    TokenType::LessLess => shift_left(l, r).ok_or_else(|| shift_error(operator))?,
+                    _ => shift_right(l, r).ok_or_else(|| shift_error(operator))?,
+                };
+                Ok(Value::Number(result as f64))
+            }
+            _ => {
+                let (l, r) = number_operands(operator, &left, &right)?;
+                Ok(match operator.token_type {
+                    TokenType::Minus => Value::Number(l - r),
+                    TokenType::Star => Value::Number(l * r),
+                    TokenType::Slash => Value::Number(l / r),
+                    TokenType::Percent => Value::Number(l % r),
+                    TokenType::StarStar => Value::Number(l.powf(r)),
+                    TokenType::Greater => Value::Bool(l > r),
+                    TokenType::GreaterEqual => Value::Bool(l >= r),
+                    TokenType::Less => Value::Bool(l < r),
+                    TokenType::LessEqual => Value::Bool(l <= r),
+                    _ => unreachable!("invalid binary operator"),
+                })
+            }
+        }
+    }
+}
+
+/// Roughly where the top of the Rust stack is now.
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// `object.name`, reading a field or method.
+fn get_property(object: &Value, name: &Token) -> Result<Value, LoxError> {
+    match object {
+        Value::Instance(instance) => LoxInstance::get(instance, name),
+        Value::Class(class) => LoxClass::get(class, name),
+        Value::List(list) => list::get(list, name),
+        Value::Map(map) => map::get(map, name),
+        Value::String(s) => string::get(s, name),
+        _ => Err(LoxError::runtime_at(
+            name,
+            "R0006",
+            "Only instances have properties.".to_string(),
+        )),
+    }
+}
+
+/// `object[index]`.
+fn get_index(object: &Value, index: &Value) -> Result<Value, Fault> {
+    match object {
+        Value::List(list) => {
+            let list = list.borrow();
+            Ok(list[list::element(index.as_number(), list.len())?].clone())
+        }
+        Value::Map(map) => {
+            let key = map::key(index)?;
+            let map = map.borrow();
+            map.get(&key)
+                .cloned()
+                .ok_or_else(|| map::missing_key(&key.describe(|s| s.to_string())))
+        }
+        Value::String(s) => Ok(Value::String(intern(string::char_at(
+            s,
+            index.as_number(),
+        )?))),
+        _ => Err(not_indexable()),
+    }
+}
+
+/// `object[index] = value`.
+fn set_index(object: &Value, index: &Value, value: Value) -> Result<(), Fault> {
+    match object {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            let index = list::element(index.as_number(), list.len())?;
+            list[index] = value;
+        }
+        Value::Map(map) => map.borrow_mut().insert(map::key(index)?, value),
+        Value::String(_) => return Err(string::immutable()),
+        _ => return Err(not_indexable()),
+    }
+    Ok(())
+}
+
+/// `object[start:end]`, with a `nil` bound standing for the start or end.
+fn get_slice(object: &Value, start: &Value, end: &Value) -> Result<Value, Fault> {
+    let bound = |bound: &Value, default: usize| match bound {
+        Value::Nil => Some(default as f64),
+        bound => bound.as_number(),
+    };
+    match object {
+        Value::List(list) => {
+            let list = list.borrow();
+            let slice = list::slice(&list, bound(start, 0), bound(end, list.len()))?;
+            Ok(Value::List(Rc::new(RefCell::new(slice))))
+        }
+        Value::String(s) => {
+            let (start, end) = (bound(start, 0), bound(end, s.chars().count()));
+            Ok(Value::String(intern(string::substring(s, start, end)?)))
+        }
+        _ => Err(list::not_sliceable()),
+    }
+}
+
+/// The text errors calling `callee` point at: the method's name for a
+/// method call, else the call's closing `)`.
+fn call_span(ast: &Ast, callee: ExprRef, paren: &Token) -> Span {
+    match &ast[callee] {
+        Expr::Get { name, .. } | Expr::Super { method: name, .. } => name.span,
+        _ => paren.span,
+    }
+}
+
+fn not_indexable() -> Fault {
+    Fault::new("R0018", "Can only index lists, maps, and strings.")
+}
+
+fn number_operand(operator: &Token, operand: &Value) -> Result<f64, LoxError> {
+    match operand {
+        Value::Number(n) => Ok(*n),
+        _ => Err(LoxError::runtime_at(
+            operator,
+            "R0001",
+            "Operand must be a number.".to_string(),
+        )),
+    }
+}
+
+fn integer_operand(operator: &Token, operand: &Value) -> Result<i64, LoxError> {
+    match operand {
+        Value::Number(n) => to_integer(*n),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        LoxError::runtime_at(operator, "R0011", "Operand must be an integer.".to_string())
+    })
+}
+
+fn integer_operands(operator: &Token, left: &Value, right: &Value) -> Result<(i64, i64), LoxError> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => to_integer(*l).zip(to_integer(*r)),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        LoxError::runtime_at(operator, "R0011", "Operands must be integers.".to_string())
+    })
+}
+
+fn shift_error(operator: &Token) -> LoxError {
+    LoxError::runtime_at(
+        operator,
+        "R0011",
+        "Shift amount must be between 0 and 63.".to_string(),
+    )
+}
+
+fn number_operands(operator: &Token, left: &Value, right: &Value) -> Result<(f64, f64), LoxError> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => Ok((*l, *r)),
+        _ => Err(LoxError::runtime_at(
+            operator,
+            "R0001",
+            "Operands must be numbers.".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+
+    /// Runs `source` on both backends, checking that it succeeds, and
+    /// returns what it printed.
+    fn run(source: &str) -> String {
+        let outcome = testing::run(source);
+        assert_eq!(outcome.error, None, "{:?} failed", source);
+        outcome.output
+    }
+
+    #[test]
+    fn do_while_runs_its_body_once() {
+        assert_eq!(
+            run("var n = 0; do n = n + 1; while (false); print n;"),
+            "1\n"
+        );
+    }
+
+    #[test]
+    fn break_leaves_do_while() {
+        let source = "
+            var i = 0;
+            do {
+                i = i + 1;
+                if (i == 3) break;
+            } while (true);
+            print i;";
+        assert_eq!(run(source), "3\n");
+    }
+
+    #[test]
+    fn continue_in_do_while_checks_the_condition() {
+        // Jumping back to the top of the body instead would go round again.
+        let source = "
+            var n = 0;
+            do {
+                n = n + 1;
+                if (n > 5) break;
+                continue;
+            } while (false);
+            print n;";
+        assert_eq!(run(source), "1\n");
+
+        let source = "
+            var i = 0;
+            var sum = 0;
+            do {
+                i = i + 1;
+                if (i == 2) continue;
+                sum = sum + i;
+            } while (i < 4);
+            print sum;";
+        assert_eq!(run(source), "8\n");
+    }
+
+    #[test]
+    fn break_and_continue_in_a_nested_loop_stay_inside_it() {
+        let source = "
+            var outer = 0;
+            var inner = 0;
+            do {
+                outer = outer + 1;
+                for (var j = 0; j < 10; j = j + 1) {
+                    if (j == 1) continue;
+                    if (j == 3) break;
+                    inner = inner + 1;
+                }
+            } while (outer < 2);
+            print outer;
+            print inner;";
+        assert_eq!(run(source), "2\n4\n");
+    }
+
+    #[test]
+    fn method_calls_in_return_position_are_tail_calls() {
+        // Far deeper than the default limit on calls in progress.
+        let source = "
+            class A {
+                count(k) { return k == 0 ? \"a\" : this.count(k - 1); }
+            }
+            class B < A {
+                count(k) {
+                    if (k == 0) return \"b\";
+                    return k % 2 == 0 ? super.count(k - 1) : this.count(k - 1);
+                }
+            }
+            fun f(n) { return n == 0 ? \"f\" : f(n - 1); }
+            print A().count(5000);
+            print B().count(5000);
+            print f(5000);";
+        let outcome = testing::run(source);
+        assert_eq!(outcome.error, None);
+        assert_eq!(outcome.output, "a\nb\nf\n");
+    }
+
+    #[test]
+    fn undefined_variables_suggest_locals_in_scope() {
+        let outcome = testing::run("fun f(local) { { var other = 1; print locl; } } f(1);");
+        assert_eq!(outcome.error, Some("R0003"));
+        assert_eq!(
+            outcome.message.as_deref(),
+            Some("Undefined variable 'locl'. Did you mean 'local'?")
+        );
+
+        let outcome = testing::run("fun f() { var count = 0; fun g() { cont = 1; } g(); } f();");
+        assert_eq!(
+            outcome.message.as_deref(),
+            Some("Undefined variable 'cont'. Did you mean 'count'?")
+        );
+    }
+
+    #[test]
+    fn strings_in_collections_print_quoted() {
+        let source = r#"
+            print ["a", 1, nil, ["b"]];
+            print {"k": "v", 2: "say \"hi\"\n"};
+            print ["\\", "$5", "\${x}"];
+            print "top";"#;
+        let outcome = testing::run(source);
+        let expected = r#"["a", 1, nil, ["b"]]
+{"k": "v", 2: "say \"hi\"\n"}
+["\\", "$5", "\${x}"]
+top
+"#;
+        assert_eq!(outcome.output, expected);
+    }
+}
@@ -0,0 +1,78 @@
+//! `type(value)`, `isInstance(value, class)`, and `str(value)`, the
+//! natives that look at what a value is or how it prints. Unlike the
+//! [others](crate::native::natives), they take any value, not only those
+//! both backends share, so each backend runs them itself.
+
+use crate::{error::Fault, native::NativeFunction, value::Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Introspection {
+    /// `isInstance(value, class)`: whether `value` is an instance of
+    /// `class` or of a class that inherits from it.
+    IsInstance,
+    /// `str(value)`: the text `print` would show for `value`.
+    Str,
+    /// `type(value)`: the name of the kind of value it is, like `"number"`.
+    Type,
+}
+
+impl Introspection {
+    pub const ALL: [Introspection; 3] = [
+        Introspection::IsInstance,
+        Introspection::Str,
+        Introspection::Type,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Introspection::IsInstance => "isInstance",
+            Introspection::Str => "str",
+            Introspection::Type => "type",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Introspection::IsInstance => 2,
+            Introspection::Str | Introspection::Type => 1,
+        }
+    }
+}
+
+/// The error for `isInstance` given something other than a class to check
+/// against.
+pub fn not_a_class() -> Fault {
+    Fault::new("R0016", "Second argument must be a class.")
+}
+
+/// The tree-walking interpreter's natives for each [`Introspection`].
+pub fn natives() -> Vec<NativeFunction> {
+    let is_instance = Introspection::IsInstance;
+    let is_instance =
+        NativeFunction::with_faults(is_instance.name(), 2, |arguments| match &arguments[1] {
+            Value::Class(class) => Ok(match &arguments[0] {
+                Value::Instance(instance) => instance.borrow().class.inherits_from(class),
+                _ => false,
+            }),
+            _ => Err(not_a_class()),
+        });
+    let str = Introspection::Str;
+    let str = NativeFunction::new(str.name(), 1, |arguments| Ok(arguments[0].to_lox_string()));
+    let type_of = Introspection::Type;
+    let type_of = NativeFunction::new(type_of.name(), 1, |arguments| Ok(type_name(&arguments[0])));
+    vec![is_instance, str, type_of]
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Function(_) | Value::Native(_) => "function",
+        Value::Class(_) => "class",
+        Value::Instance(_) => "instance",
+        Value::List(_) => "list",
+        Value::Map(_) => "map",
+    }
+}
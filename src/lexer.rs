@@ -1,40 +1,78 @@
 use std::fmt::Display;
 
-use crate::error::LoxError;
+use unicode_ident::{is_xid_continue, is_xid_start};
 
-#[derive(Debug, Clone)]
-enum TokenType {
+use crate::{
+    error::LoxError,
+    intern::{intern, Symbol},
+};
+
+/// Every reserved word, in the order [`Scanner`] matches them.
+pub const KEYWORDS: &[&str] = &[
+    "and", "break", "case", "class", "continue", "default", "do", "else", "false", "for", "fun",
+    "if", "nil", "or", "print", "return", "super", "switch", "this", "true", "var", "while",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenType {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Ampersand,
+    Caret,
     Comma,
     Dot,
     Minus,
+    Percent,
+    Pipe,
     Plus,
     Semicolon,
     Slash,
     Star,
+    StarStar,
+    Question,
+    Colon,
+    Tilde,
 
     // one or two character tokens
+    MinusEqual,
+    MinusMinus,
+    PlusEqual,
+    PlusPlus,
+    SlashEqual,
+    StarEqual,
     Bang,
     BangEqual,
     Equal,
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
 
     // Literals
     Identifier,
     String,
+    /// The part of a string literal before a `${`. The embedded expression's
+    /// tokens follow, then either another `Interpolation` or the closing
+    /// `String` segment.
+    Interpolation,
     Number,
 
     // Keywords
     And,
+    Break,
+    Case,
     Class,
+    Continue,
+    Default,
+    Do,
     Else,
     False,
     Fun,
@@ -45,6 +83,7 @@ enum TokenType {
     Print,
     Return,
     Super,
+    Switch,
     This,
     True,
     Var,
@@ -54,18 +93,91 @@ enum TokenType {
     Eof,
 }
 
-#[derive(Debug, Clone)]
-enum Literal {
+/// The value of a string or number literal, decoded from its lexeme.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
     String(String),
     Number(f64),
 }
 
+/// Where a token sits in the source it was scanned from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    /// Byte offset of the token's first character.
+    pub start: usize,
+    /// Length of the token in bytes.
+    pub len: usize,
+    /// 1-based column of the token's first character, counted in characters.
+    pub column: usize,
+}
+
+impl Span {
+    /// Byte offset just past the token's last character.
+    pub fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    /// A run of spaces, tabs, and carriage returns.
+    Whitespace,
+    Newline,
+    LineComment,
+    BlockComment,
+    Shebang,
+}
+
+/// Source text between tokens that doesn't affect the program, kept only
+/// when scanning with [`Scanner::with_trivia`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
-    token_type: TokenType,
-    lexeme: String,
-    literal: Option<Literal>,
-    line: usize,
+    pub(crate) token_type: TokenType,
+    pub(crate) lexeme: Symbol,
+    pub(crate) literal: Option<Literal>,
+    pub(crate) line: usize,
+    pub(crate) span: Span,
+    pub(crate) leading_trivia: Vec<Trivia>,
+}
+
+impl Token {
+    pub fn kind(&self) -> TokenType {
+        self.token_type
+    }
+
+    /// The source text of the token, exactly as written.
+    pub fn lexeme(&self) -> &str {
+        &self.lexeme
+    }
+
+    pub fn literal(&self) -> Option<&Literal> {
+        self.literal.as_ref()
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.span.column
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Whitespace and comments preceding the token. Always empty unless the
+    /// scanner was created with [`Scanner::with_trivia`].
+    pub fn leading_trivia(&self) -> &[Trivia] {
+        &self.leading_trivia
+    }
 }
 
 impl Display for Token {
@@ -81,9 +193,23 @@ impl Display for Token {
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
+    errors: Vec<LoxError>,
+    /// Byte offset of the first character of the token being scanned.
     start: usize,
+    /// Byte offset of the next character to consume.
     current: usize,
     line: usize,
+    /// Column of the character at `start`.
+    start_column: usize,
+    /// Column of the character at `current`.
+    column: usize,
+    /// Whether whitespace and comments are kept on the tokens that follow.
+    keep_trivia: bool,
+    /// Trivia scanned since the last token.
+    trivia: Vec<Trivia>,
+    /// One entry per `${` we are inside, counting the `{`s opened since, so
+    /// the `}` that ends the embedded expression can be told apart.
+    interpolations: Vec<usize>,
 }
 
 impl Scanner {
@@ -91,26 +217,102 @@ impl Scanner {
         Self {
             source,
             tokens: Vec::new(),
+            errors: Vec::new(),
             start: 0,
             current: 0,
-            line: 0,
+            line: 1,
+            start_column: 1,
+            column: 1,
+            keep_trivia: false,
+            trivia: Vec::new(),
+            interpolations: Vec::new(),
+        }
+    }
+
+    /// Creates a scanner that attaches whitespace and comments to the token
+    /// after them, and any at the end of the file to the EOF token, so that
+    /// concatenating each token's trivia and lexeme reproduces the source.
+    pub fn with_trivia(source: String) -> Self {
+        Self {
+            keep_trivia: true,
+            ..Self::new(source)
+        }
+    }
+
+    /// The value of `text` if it is a single number literal and nothing
+    /// else, like `1_000` or `0x10`, but not ` 1`, `.5`, or `inf`.
+    pub fn number_literal(text: &str) -> Option<f64> {
+        if !text.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let mut scanner = Scanner::new(text.to_string());
+        scanner.advance();
+        scanner.number().ok()?;
+        if !scanner.is_at_end() {
+            return None;
+        }
+        match scanner.tokens.pop()?.literal? {
+            Literal::Number(n) => Some(n),
+            Literal::String(_) => None,
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, LoxError> {
+    /// Scans the whole source. A lexical error doesn't stop the scan: it is
+    /// recorded and scanning resumes at the next character, so every error
+    /// in the source is reported at once.
+    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, Vec<LoxError>> {
+        let (tokens, errors) = self.scan_partial();
+        if errors.is_empty() {
+            Ok(tokens)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Scans as much of the source as possible, returning the tokens along
+    /// with the errors. The text an error is about is left out of the
+    /// tokens, so the parser can still look for errors in the rest.
+    pub fn scan_partial(&mut self) -> (Vec<Token>, Vec<LoxError>) {
+        // Skip a `#!/usr/bin/env rslox` line so scripts can be executable.
+        if self.source.starts_with("#!") {
+            while self.peek() != '\n' && !self.is_at_end() {
+                self.advance();
+            }
+            self.add_trivia(TriviaKind::Shebang);
+        }
+
         while !self.is_at_end() {
             self.start = self.current;
-            self.scan_token()?;
+            self.start_column = self.column;
+            if let Err(error) = self.scan_token() {
+                self.errors.push(error);
+            }
         }
 
+        if !self.interpolations.is_empty() {
+            self.errors.push(LoxError::scan(
+                self.line,
+                "L0004",
+                "Unterminated string interpolation".to_string(),
+            ));
+        }
         self.tokens.push(Token {
             token_type: TokenType::Eof,
-            lexeme: String::new(),
+            lexeme: intern(""),
             literal: None,
             line: self.line,
+            span: Span {
+                start: self.current,
+                len: 0,
+                column: self.column,
+            },
+            leading_trivia: std::mem::take(&mut self.trivia),
         });
 
-        Ok(self.tokens.clone())
+        (
+            std::mem::take(&mut self.tokens),
+            std::mem::take(&mut self.errors),
+        )
     }
 
     fn is_at_end(&self) -> bool {
@@ -122,14 +324,62 @@ impl Scanner {
         match c {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
-            '{' => self.add_token(TokenType::LeftBrace),
-            '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace)
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string()
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.add_token(TokenType::RightBrace)
+                }
+                None => self.add_token(TokenType::RightBrace),
+            },
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
-            '+' => self.add_token(TokenType::Plus),
+            '-' => {
+                if self.match_char('-') {
+                    self.add_token(TokenType::MinusMinus)
+                } else if self.match_char('=') {
+                    self.add_token(TokenType::MinusEqual)
+                } else {
+                    self.add_token(TokenType::Minus)
+                }
+            }
+            '+' => {
+                if self.match_char('+') {
+                    self.add_token(TokenType::PlusPlus)
+                } else if self.match_char('=') {
+                    self.add_token(TokenType::PlusEqual)
+                } else {
+                    self.add_token(TokenType::Plus)
+                }
+            }
             ';' => self.add_token(TokenType::Semicolon),
-            '*' => self.add_token(TokenType::Star),
+            '*' => {
+                if self.match_char('*') {
+                    self.add_token(TokenType::StarStar)
+                } else if self.match_char('=') {
+                    self.add_token(TokenType::StarEqual)
+                } else {
+                    self.add_token(TokenType::Star)
+                }
+            }
+            '%' => self.add_token(TokenType::Percent),
+            '&' => self.add_token(TokenType::Ampersand),
+            '|' => self.add_token(TokenType::Pipe),
+            '^' => self.add_token(TokenType::Caret),
+            '~' => self.add_token(TokenType::Tilde),
+            '?' => self.add_token(TokenType::Question),
+            ':' => self.add_token(TokenType::Colon),
             '!' => {
                 if self.match_char('=') {
                     self.add_token(TokenType::BangEqual)
@@ -145,14 +395,18 @@ impl Scanner {
                 }
             }
             '<' => {
-                if self.match_char('=') {
+                if self.match_char('<') {
+                    self.add_token(TokenType::LessLess)
+                } else if self.match_char('=') {
                     self.add_token(TokenType::LessEqual)
                 } else {
                     self.add_token(TokenType::Less)
                 }
             }
             '>' => {
-                if self.match_char('=') {
+                if self.match_char('>') {
+                    self.add_token(TokenType::GreaterGreater)
+                } else if self.match_char('=') {
                     self.add_token(TokenType::GreaterEqual)
                 } else {
                     self.add_token(TokenType::Greater)
@@ -163,27 +417,46 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    self.add_trivia(TriviaKind::LineComment);
+                    Ok(())
+                } else if self.match_char('*') {
+                    self.block_comment()?;
+                    self.add_trivia(TriviaKind::BlockComment);
                     Ok(())
+                } else if self.match_char('=') {
+                    self.add_token(TokenType::SlashEqual)
                 } else {
                     self.add_token(TokenType::Slash)
                 }
             }
-            ' ' | '\r' | '\t' => Ok(()),
+            ' ' | '\r' | '\t' => {
+                while matches!(self.peek(), ' ' | '\r' | '\t') {
+                    self.advance();
+                }
+                self.add_trivia(TriviaKind::Whitespace);
+                Ok(())
+            }
             '\n' => {
                 self.line += 1;
+                self.add_trivia(TriviaKind::Newline);
                 Ok(())
             }
             '"' => self.string(),
             '0'..='9' => self.number(),
-            'a'..='z' | 'A'..='Z' | '_' => {
-                while self.peek().is_alphanumeric() {
+            c if c == '_' || is_xid_start(c) => {
+                while is_xid_continue(self.peek()) {
                     self.advance();
                 }
 
                 let text = &self.source[self.start..self.current];
                 match text {
                     "and" => self.add_token(TokenType::And),
+                    "break" => self.add_token(TokenType::Break),
+                    "case" => self.add_token(TokenType::Case),
                     "class" => self.add_token(TokenType::Class),
+                    "continue" => self.add_token(TokenType::Continue),
+                    "default" => self.add_token(TokenType::Default),
+                    "do" => self.add_token(TokenType::Do),
                     "else" => self.add_token(TokenType::Else),
                     "false" => self.add_token(TokenType::False),
                     "for" => self.add_token(TokenType::For),
@@ -194,6 +467,7 @@ impl Scanner {
                     "print" => self.add_token(TokenType::Print),
                     "return" => self.add_token(TokenType::Return),
                     "super" => self.add_token(TokenType::Super),
+                    "switch" => self.add_token(TokenType::Switch),
                     "this" => self.add_token(TokenType::This),
                     "true" => self.add_token(TokenType::True),
                     "var" => self.add_token(TokenType::Var),
@@ -201,17 +475,23 @@ impl Scanner {
                     _ => self.add_token(TokenType::Identifier),
                 }
             }
-            _ => Err(LoxError::new(self.line, "Unexpected character".to_string())),
+            _ => Err(
+                LoxError::scan(self.line, "L0001", "Unexpected character".to_string())
+                    .with_span(self.span()),
+            ),
         }
     }
 
     fn add_token(&mut self, token_type: TokenType) -> Result<(), LoxError> {
-        Ok(self.tokens.push(Token {
-            token_type: token_type,
-            lexeme: self.source[self.start..self.current].to_string(),
+        self.tokens.push(Token {
+            token_type,
+            lexeme: intern(&self.source[self.start..self.current]),
             literal: None,
             line: self.line,
-        }))
+            span: self.span(),
+            leading_trivia: std::mem::take(&mut self.trivia),
+        });
+        Ok(())
     }
 
     fn add_token_literal(
@@ -219,81 +499,348 @@ impl Scanner {
         token_type: TokenType,
         literal: Literal,
     ) -> Result<(), LoxError> {
-        Ok(self.tokens.push(Token {
-            token_type: token_type,
-            lexeme: self.source[self.start..self.current].to_string(),
+        self.tokens.push(Token {
+            token_type,
+            lexeme: intern(&self.source[self.start..self.current]),
             literal: Some(literal),
             line: self.line,
-        }))
+            span: self.span(),
+            leading_trivia: std::mem::take(&mut self.trivia),
+        });
+        Ok(())
+    }
+
+    fn add_trivia(&mut self, kind: TriviaKind) {
+        if self.keep_trivia {
+            self.trivia.push(Trivia {
+                kind,
+                text: self.source[self.start..self.current].to_string(),
+                span: self.span(),
+            });
+        }
     }
 
+    /// Span of the token currently being scanned.
+    fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            len: self.current - self.start,
+            column: self.start_column,
+        }
+    }
+
+    /// The unconsumed part of the source. Slicing at a byte offset is
+    /// constant time, which keeps scanning linear in the source length.
+    fn rest(&self) -> std::str::Chars<'_> {
+        self.source[self.current..].chars()
+    }
+
+    /// Consumes the next character. At the end of the source there is none,
+    /// and like [`peek`](Scanner::peek) it returns `'\0'`.
     fn advance(&mut self) -> char {
-        let char = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
+        let Some(char) = self.rest().next() else {
+            return '\0';
+        };
+        self.current += char.len_utf8();
+        if char == '\n' {
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         char
     }
 
     fn match_char(&mut self, arg: char) -> bool {
-        if let Some(c) = self.source.chars().nth(self.current) {
-            if c == arg {
-                self.current += 1;
-                return true;
-            }
+        if self.rest().next() == Some(arg) {
+            self.advance();
+            return true;
         }
         false
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
+        self.rest().next().unwrap_or('\0')
+    }
+
+    /// Skips a `/* ... */` comment whose opening `/*` has been consumed.
+    /// Block comments nest, so each `/*` needs its own matching `*/`.
+    fn block_comment(&mut self) -> Result<(), LoxError> {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                return Err(LoxError::scan(
+                    self.line,
+                    "L0003",
+                    "Unterminated block comment".to_string(),
+                )
+                .with_span(self.span()));
+            }
+
+            match self.advance() {
+                '/' if self.match_char('*') => depth += 1,
+                '*' if self.match_char('/') => depth -= 1,
+                '\n' => self.line += 1,
+                _ => {}
+            }
         }
-        self.source.chars().nth(self.current).unwrap()
+        Ok(())
     }
 
+    /// Scans a string literal up to its closing quote or its next `${`. Also
+    /// resumes a literal after the `}` that ends an embedded expression.
     fn string(&mut self) -> Result<(), LoxError> {
+        let mut value = String::new();
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            match self.advance() {
+                // A bad escape is recorded but doesn't end the literal, so
+                // the rest of it isn't misread as code.
+                '\\' if !self.is_at_end() => match self.escape() {
+                    Ok(c) => value.push(c),
+                    Err(error) => self.errors.push(error),
+                },
+                '$' if self.match_char('{') => {
+                    self.interpolations.push(0);
+                    return self
+                        .add_token_literal(TokenType::Interpolation, Literal::String(value));
+                }
+                '\n' => {
+                    self.line += 1;
+                    value.push('\n');
+                }
+                c => value.push(c),
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            return Err(LoxError::new(self.line, "Unterminated string".to_string()));
+            return Err(
+                LoxError::scan(self.line, "L0002", "Unterminated string".to_string())
+                    .with_span(self.span()),
+            );
         }
 
         self.advance();
 
-        let value = self.source[self.start + 1..self.current - 1].to_string();
         self.add_token_literal(TokenType::String, Literal::String(value))
     }
 
-    fn number(&mut self) -> Result<(), LoxError> {
-        while self.peek().is_digit(10) {
+    /// Decodes the escape sequence following a `\` in a string literal.
+    fn escape(&mut self) -> Result<char, LoxError> {
+        let escape_start = self.current - 1;
+        let escape_column = self.column - 1;
+        let escape_line = self.line;
+
+        let decoded = match self.advance() {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            '$' => Some('$'),
+            'u' => self.unicode_escape(),
+            '\n' => {
+                self.line += 1;
+                None
+            }
+            _ => None,
+        };
+
+        decoded.ok_or_else(|| {
+            let sequence = &self.source[escape_start..self.current];
+            LoxError::scan(
+                escape_line,
+                "L0005",
+                format!("Invalid escape sequence '{}' in string", sequence),
+            )
+            .with_span(Span {
+                start: escape_start,
+                len: sequence.len(),
+                column: escape_column,
+            })
+        })
+    }
+
+    /// Decodes the `{XXXX}` part of a `\u{XXXX}` escape: one to six hex
+    /// digits naming a Unicode scalar value.
+    fn unicode_escape(&mut self) -> Option<char> {
+        if !self.match_char('{') {
+            return None;
+        }
+
+        let digits_start = self.current;
+        while self.peek().is_ascii_hexdigit() {
             self.advance();
         }
+        let digits_end = self.current;
+        if !(1..=6).contains(&(digits_end - digits_start)) || !self.match_char('}') {
+            return None;
+        }
+
+        u32::from_str_radix(&self.source[digits_start..digits_end], 16)
+            .ok()
+            .and_then(char::from_u32)
+    }
+
+    fn number(&mut self) -> Result<(), LoxError> {
+        if self.source[self.start..].starts_with('0') {
+            let radix = match self.peek() {
+                'x' | 'X' => Some(16),
+                'o' | 'O' => Some(8),
+                'b' | 'B' => Some(2),
+                _ => None,
+            };
+            if let Some(radix) = radix {
+                self.advance();
+                return self.radix_number(radix);
+            }
+        }
 
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        self.digits();
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             self.advance();
-            while self.peek().is_digit(10) {
+            self.digits();
+        }
+        if matches!(self.peek(), 'e' | 'E') {
+            let sign = matches!(self.peek_next(), '+' | '-') as usize;
+            if self
+                .rest()
+                .nth(1 + sign)
+                .is_some_and(|c| c.is_ascii_digit())
+            {
                 self.advance();
+                if sign == 1 {
+                    self.advance();
+                }
+                self.digits();
             }
         }
 
-        self.add_token_literal(
-            TokenType::Number,
-            Literal::Number(
-                self.source[self.start..self.current]
-                    .parse::<f64>()
-                    .unwrap(),
-            ),
-        )
+        let text = &self.source[self.start..self.current];
+        self.check_separators(text, 10)?;
+        let value = text
+            .replace('_', "")
+            .parse::<f64>()
+            .map_err(|_| self.number_error(format!("Invalid number literal '{}'", text)))?;
+        self.add_token_literal(TokenType::Number, Literal::Number(value))
     }
 
-    fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
+    /// Scans the digits of a `0x`, `0o`, or `0b` literal, whose prefix has
+    /// been consumed. Any letters or digits running on from the literal are
+    /// taken as part of it, so `0b102` is an error rather than two tokens.
+    fn radix_number(&mut self, radix: u32) -> Result<(), LoxError> {
+        let digits_start = self.current;
+        while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
+            self.advance();
+        }
+
+        let prefix = &self.source[self.start..digits_start];
+        let digits = &self.source[digits_start..self.current];
+        if digits.is_empty() {
+            return Err(self.number_error(format!("Missing digits after '{}'", prefix)));
         }
-        self.source.chars().nth(self.current + 1).unwrap()
+        if let Some(digit) = digits.chars().find(|c| *c != '_' && !c.is_digit(radix)) {
+            return Err(self.number_error(format!(
+                "Invalid digit '{}' in number literal '{}'",
+                digit,
+                &self.source[self.start..self.current]
+            )));
+        }
+        self.check_separators(digits, radix)?;
+
+        let value = digits
+            .chars()
+            .filter_map(|c| c.to_digit(radix))
+            .fold(0.0, |value, digit| value * radix as f64 + digit as f64);
+        self.add_token_literal(TokenType::Number, Literal::Number(value))
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_ascii_digit() || self.peek() == '_' {
+            self.advance();
+        }
+    }
+
+    /// Digit separators are only allowed between two digits.
+    fn check_separators(&self, text: &str, radix: u32) -> Result<(), LoxError> {
+        let chars: Vec<char> = text.chars().collect();
+        let misplaced = chars.iter().enumerate().any(|(i, c)| {
+            *c == '_'
+                && !(i > 0
+                    && chars[i - 1].is_digit(radix)
+                    && chars.get(i + 1).is_some_and(|next| next.is_digit(radix)))
+        });
+        if misplaced {
+            return Err(self.number_error(format!(
+                "Misplaced digit separator in number literal '{}'",
+                &self.source[self.start..self.current]
+            )));
+        }
+        Ok(())
+    }
+
+    fn number_error(&self, message: String) -> LoxError {
+        LoxError::scan(self.line, "L0006", message).with_span(self.span())
+    }
+
+    fn peek_next(&self) -> char {
+        self.rest().nth(1).unwrap_or('\0')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(source: &str) -> Result<Vec<Token>, Vec<LoxError>> {
+        Scanner::new(source.to_string()).scan_tokens()
+    }
+
+    #[test]
+    fn strings_hold_emoji_and_cjk() {
+        let tokens = scan("\"héllo 🌍, 世界\" + \"👨‍👩‍👧\"").unwrap();
+        let kinds: Vec<_> = tokens.iter().map(Token::kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                TokenType::String,
+                TokenType::Plus,
+                TokenType::String,
+                TokenType::Eof
+            ]
+        );
+        assert_eq!(
+            tokens[0].literal(),
+            Some(&Literal::String("héllo 🌍, 世界".to_string()))
+        );
+        assert_eq!(
+            tokens[2].literal(),
+            Some(&Literal::String("👨‍👩‍👧".to_string()))
+        );
+        // Spans are in bytes, columns in characters.
+        assert_eq!(tokens[0].span().len, "\"héllo 🌍, 世界\"".len());
+        assert_eq!(tokens[1].column(), 15);
+    }
+
+    #[test]
+    fn cjk_identifiers() {
+        let tokens = scan("var 名前 = 1; print 名前;").unwrap();
+        assert_eq!(tokens[1].kind(), TokenType::Identifier);
+        assert_eq!(tokens[1].lexeme(), "名前");
+        assert_eq!(tokens[6].kind(), TokenType::Identifier);
+        assert_eq!(tokens[6].lexeme(), "名前");
+    }
+
+    #[test]
+    fn emoji_is_not_an_identifier() {
+        let errors = scan("var 🌍 = 1;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "L0001");
+    }
+
+    #[test]
+    fn unterminated_string_ending_in_cjk() {
+        let errors = scan("print \"世界").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "L0002");
     }
 }
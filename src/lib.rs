@@ -1,2 +1,32 @@
+pub mod ast_printer;
+pub mod callable;
+pub mod class;
+pub mod codes;
+pub mod diagnostic;
+pub mod environment;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod formatter;
+pub mod function;
+pub mod intern;
+pub mod interpreter;
+pub mod introspect;
 pub mod lexer;
+pub mod list;
+pub mod lox;
+pub mod map;
+pub mod native;
+pub mod parser;
+pub mod resolver;
+pub mod string;
+pub mod suggest;
+#[cfg(test)]
+mod testing;
+pub mod value;
+pub mod visitor;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use lox::Lox;
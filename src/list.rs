@@ -0,0 +1,186 @@
+//! Lists, `[1, 2, 3]`: growable arrays of any values, shared by reference
+//! like instances. Both backends give them the same methods and the same
+//! checks on indices, so those are written once here, over whichever
+//! values the backend has.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    error::{Fault, LoxError},
+    lexer::Token,
+    native::NativeFunction,
+    suggest,
+    value::{format_number, Value},
+};
+
+/// A method every list has, called as in `xs.push(4)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListMethod {
+    /// `length()`: how many elements there are.
+    Length,
+    /// `push(value)`: adds `value` to the end.
+    Push,
+    /// `pop()`: removes the last element and returns it.
+    Pop,
+    /// `insert(index, value)`: puts `value` at `index`, moving the
+    /// elements from there on up one. `index` may be the length, to add it
+    /// to the end.
+    Insert,
+    /// `remove(index)`: removes the element at `index` and returns it.
+    Remove,
+    /// `slice(start, end)`: a new list of the elements from `start` up to
+    /// but not including `end`.
+    Slice,
+}
+
+/// What a list method returns, in terms of the backend's values.
+pub enum Returned<T> {
+    Nil,
+    Number(f64),
+    Element(T),
+    List(Vec<T>),
+}
+
+impl ListMethod {
+    /// Sorted by name.
+    pub const ALL: [ListMethod; 6] = [
+        ListMethod::Insert,
+        ListMethod::Length,
+        ListMethod::Pop,
+        ListMethod::Push,
+        ListMethod::Remove,
+        ListMethod::Slice,
+    ];
+
+    pub fn named(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|method| method.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ListMethod::Length => "length",
+            ListMethod::Push => "push",
+            ListMethod::Pop => "pop",
+            ListMethod::Insert => "insert",
+            ListMethod::Remove => "remove",
+            ListMethod::Slice => "slice",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            ListMethod::Length | ListMethod::Pop => 0,
+            ListMethod::Push | ListMethod::Remove => 1,
+            ListMethod::Insert | ListMethod::Slice => 2,
+        }
+    }
+
+    /// Runs the method on `list`. The caller has checked the number of
+    /// `arguments` against its [`arity`](ListMethod::arity); `number` reads
+    /// one as a number, if it is one.
+    pub fn call<T: Clone>(
+        self,
+        list: &mut Vec<T>,
+        arguments: &[T],
+        number: impl Fn(&T) -> Option<f64>,
+    ) -> Result<Returned<T>, Fault> {
+        match self {
+            ListMethod::Length => Ok(Returned::Number(list.len() as f64)),
+            ListMethod::Push => {
+                list.push(arguments[0].clone());
+                Ok(Returned::Nil)
+            }
+            ListMethod::Pop => list
+                .pop()
+                .map(Returned::Element)
+                .ok_or_else(|| Fault::new("R0017", "Can't pop from an empty list.")),
+            ListMethod::Insert => {
+                let index = position(number(&arguments[0]), list.len())?;
+                list.insert(index, arguments[1].clone());
+                Ok(Returned::Nil)
+            }
+            ListMethod::Remove => {
+                let index = element(number(&arguments[0]), list.len())?;
+                Ok(Returned::Element(list.remove(index)))
+            }
+            ListMethod::Slice => {
+                let (start, end) = (number(&arguments[0]), number(&arguments[1]));
+                Ok(Returned::List(slice(list, start, end)?))
+            }
+        }
+    }
+}
+
+/// `list[start:end]`: the elements from `start` up to but not including
+/// `end`, given each if it is a number at all.
+pub fn slice<T: Clone>(list: &[T], start: Option<f64>, end: Option<f64>) -> Result<Vec<T>, Fault> {
+    let start = position(start, list.len())?;
+    let end = position(end, list.len())?;
+    if start > end {
+        return Err(Fault::new("R0017", "Slice start is after its end."));
+    }
+    Ok(list[start..end].to_vec())
+}
+
+/// The error for slicing something that isn't a list or a string.
+pub fn not_sliceable() -> Fault {
+    Fault::new("R0018", "Can only slice lists and strings.")
+}
+
+/// The element `index` refers to in a list of length `len`, given the
+/// index if it is a number at all.
+pub fn element(index: Option<f64>, len: usize) -> Result<usize, Fault> {
+    match checked(index, len)? {
+        index if index < len => Ok(index),
+        index => Err(out_of_bounds(index as f64, len)),
+    }
+}
+
+/// Like [`element`], but `len` itself is allowed too, for the position
+/// after the last element.
+pub fn position(index: Option<f64>, len: usize) -> Result<usize, Fault> {
+    checked(index, len)
+}
+
+fn checked(index: Option<f64>, len: usize) -> Result<usize, Fault> {
+    let Some(index) = index.filter(|index| index.fract() == 0.0) else {
+        return Err(Fault::new("R0017", "List index must be an integer."));
+    };
+    if index < 0.0 || index > len as f64 {
+        return Err(out_of_bounds(index, len));
+    }
+    Ok(index as usize)
+}
+
+fn out_of_bounds(index: f64, len: usize) -> Fault {
+    let message = format!(
+        "List index {} is out of bounds for a list of length {}.",
+        format_number(index),
+        len
+    );
+    Fault::new("R0017", message)
+}
+
+/// Reads method `name` of `list` in the tree-walking interpreter, as a
+/// native bound to the list.
+pub fn get(list: &Rc<RefCell<Vec<Value>>>, name: &Token) -> Result<Value, LoxError> {
+    let Some(method) = ListMethod::named(&name.lexeme) else {
+        let names = ListMethod::ALL.map(ListMethod::name);
+        return Err(LoxError::runtime_at(
+            name,
+            "R0005",
+            suggest::undefined("property", &name.lexeme, names),
+        ));
+    };
+    let list = list.clone();
+    let native = NativeFunction::with_faults(method.name(), method.arity(), move |arguments| {
+        let returned = method.call(&mut list.borrow_mut(), arguments, Value::as_number)?;
+        Ok(match returned {
+            Returned::Nil => Value::Nil,
+            Returned::Number(n) => Value::Number(n),
+            Returned::Element(element) => element,
+            Returned::List(elements) => Value::List(Rc::new(RefCell::new(elements))),
+        })
+    });
+    Ok(Value::Native(Rc::new(native)))
+}
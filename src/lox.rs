@@ -0,0 +1,110 @@
+use std::{
+    io::{BufRead, Write},
+    rc::Rc,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use crate::{
+    error::LoxError,
+    interpreter::{Interpreter, InterpreterOptions},
+    native::TypedNative,
+    parser::parse_source,
+    resolver::Resolver,
+    value::{ToLox, Value},
+};
+
+/// The entry point for embedding rslox in another program. Each call to
+/// [`Lox::run`] runs on the same interpreter, so the globals one script
+/// defines are still there for the next. Scripts run on the caller's
+/// thread, and recursing too deep is an error before it takes more than
+/// [`InterpreterOptions::max_stack`] of that thread's stack.
+pub struct Lox {
+    interpreter: Interpreter,
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Self::with_options(InterpreterOptions::default())
+    }
+
+    pub fn with_options(options: InterpreterOptions) -> Self {
+        Self {
+            interpreter: Interpreter::with_options(options),
+        }
+    }
+
+    /// Sends `print` output to `stdout` and error reports to `stderr`
+    /// instead of the process's own.
+    pub fn set_output(&mut self, stdout: Box<dyn Write>, stderr: Box<dyn Write>) {
+        self.interpreter.set_output(stdout, stderr);
+    }
+
+    /// Has `readLine` and `readNumber` read from `stdin` instead of the
+    /// process's own.
+    pub fn set_input(&mut self, stdin: Box<dyn BufRead>) {
+        self.interpreter.set_input(stdin);
+    }
+
+    /// Stops the script running at the time with an "Interrupted." error
+    /// once `flag` is set, e.g. from another thread or a signal handler.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interpreter.set_interrupt(flag);
+    }
+
+    /// Writes `error` and its backtrace to the stderr given to
+    /// [`Lox::set_output`].
+    pub fn report(&mut self, error: &LoxError) {
+        self.interpreter.report(error);
+    }
+
+    /// Defines a global function `name` that scripts can call with `arity`
+    /// arguments to run `function`, e.g.
+    /// `lox.register_native("double", 1, |args| Ok(f64::from_lox(&args[0])? * 2.0))`.
+    /// See [`Interpreter::register_native`].
+    pub fn register_native<R: ToLox>(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<R, String> + 'static,
+    ) {
+        self.interpreter.register_native(name, arity, function);
+    }
+
+    /// Defines a global function `name` whose arguments are converted to
+    /// the types of `function`'s parameters, e.g.
+    /// `lox.register_typed_native("double", |n: f64| Ok(n * 2.0))`.
+    /// See [`Interpreter::register_typed_native`].
+    pub fn register_typed_native<Args, F: TypedNative<Args> + 'static>(
+        &mut self,
+        name: &str,
+        function: F,
+    ) {
+        self.interpreter.register_typed_native(name, function);
+    }
+
+    /// Scans, parses, resolves, and runs `source` on the tree-walking
+    /// interpreter. Returns the value of the final statement if it is an
+    /// expression statement, e.g. `3` for `1 + 2;`, or otherwise nil.
+    ///
+    /// Of several syntax errors, only the first is returned.
+    pub fn run(&mut self, source: &str) -> Result<Value, LoxError> {
+        let ast = parse_source(source.to_string(), false).map_err(first_error)?;
+        let locals = Resolver::new().resolve(&ast).map_err(first_error)?;
+
+        self.interpreter.resolve(locals);
+        self.interpreter.run(&Rc::new(ast))
+    }
+}
+
+fn first_error(errors: Vec<LoxError>) -> LoxError {
+    errors
+        .into_iter()
+        .next()
+        .expect("failed without reporting an error")
+}
@@ -1,72 +1,832 @@
 use std::{
-    fs,
-    io::{self, Write},
+    collections::HashMap,
+    env, fs,
+    io::{self, BufRead, IsTerminal, Read, Write},
+    path::Path,
+    process,
+    rc::Rc,
+    sync::{atomic::AtomicBool, Arc},
+    thread,
+    time::Duration,
 };
 
-use clap::Parser;
-use rslox::{error::LoxError, lexer::Scanner};
+mod repl;
+mod test_runner;
 
-/// Simple program to greet a person
+use clap::{Parser, Subcommand, ValueEnum};
+use rslox::{
+    ast_printer, codes,
+    diagnostic::{Diagnostic, Severity, SourceFile},
+    error::{LoxError, Warning},
+    formatter,
+    interpreter::{Interpreter, InterpreterOptions, DEFAULT_MAX_CALL_DEPTH},
+    lexer::{Literal, Scanner, Token},
+    parser::{parse_source, Ast, ExprId, Parser as LoxParser},
+    resolver::Resolver,
+    vm::{heap::GcConfig, loxc, Vm, VmOptions},
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Backend {
+    /// Tree-walking interpreter
+    Tree,
+    /// Bytecode compiler and stack VM
+    Vm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ErrorFormat {
+    /// Messages that quote the source, for people
+    Human,
+    /// One JSON object per line, for editors and CI
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a script, or a `.loxc` file on the VM
+    Run {
+        /// Script to run, or `-` to read it from stdin
+        #[arg(default_value = STDIN)]
+        file: String,
+
+        /// Print the token stream as JSON instead of running the program
+        #[arg(long, conflicts_with = "ast")]
+        tokens: bool,
+
+        /// Print the parsed syntax tree instead of running the program
+        #[arg(long)]
+        ast: bool,
+
+        /// Run the script again each time the file changes
+        #[arg(short, long, conflicts_with_all = ["tokens", "ast"])]
+        watch: bool,
+    },
+    /// Start the interactive prompt
+    Repl,
+    /// Check a script for errors without running it
+    Check { file: String },
+    /// Reformat a script, printing the result
+    Fmt {
+        file: String,
+
+        /// Overwrite the file instead of printing it
+        #[arg(short, long)]
+        write: bool,
+    },
+    /// Print the bytecode a script compiles to
+    Disasm { file: String },
+    /// Run test scripts annotated with `// expect: ...` comments
+    Test {
+        /// A test script, or a directory to run every `.lox` file under
+        #[arg(default_value = "test")]
+        path: String,
+    },
+    /// Compile a script to bytecode and save it as a `.loxc` file
+    Compile {
+        file: String,
+
+        /// Where to write the bytecode [default: FILE with a .loxc extension]
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Explain an error or warning code, like P0012, or list them all
+    Explain { code: Option<String> },
+}
+
+/// A Lox interpreter, with a tree-walking and a bytecode backend. Without a
+/// subcommand, runs SCRIPT, or starts the REPL if there is nothing to run.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Name of the person to greet
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Script to run, or `-` to read it from stdin. With none, the script is
+    /// read from stdin if that is piped, and otherwise the REPL starts
+    script: Option<String>,
+
+    /// Script to run, as an alternative to giving it as SCRIPT
+    #[arg(short, long, conflicts_with = "script")]
     file: Option<String>,
 
+    /// Start the interactive prompt
     #[arg(short, long)]
     repl: bool,
+
+    /// Run CODE instead of a script, printing its value if it is a lone
+    /// expression, as the REPL does
+    #[arg(short, long, value_name = "CODE", conflicts_with_all = ["script", "file", "repl"])]
+    eval: Option<String>,
+
+    /// Which execution engine runs the program
+    #[arg(long, global = true, value_enum, default_value_t = Backend::Tree)]
+    backend: Backend,
+
+    /// Run the VM's garbage collector before every allocation
+    #[arg(long, global = true)]
+    gc_stress: bool,
+
+    /// Bytes the VM allocates before its first garbage collection
+    #[arg(long, global = true, value_name = "BYTES")]
+    gc_threshold: Option<usize>,
+
+    /// Bytes the VM's heap may hold before the program stops with an out
+    /// of memory error (VM backend only)
+    #[arg(long, global = true, value_name = "BYTES")]
+    max_heap: Option<usize>,
+
+    /// Make reading a variable declared without a value an error rather
+    /// than nil (tree backend only)
+    #[arg(long, global = true)]
+    error_on_uninitialized: bool,
+
+    /// Print each instruction the VM executes, with the stack, to stderr
+    /// (VM backend only)
+    #[arg(long, global = true)]
+    trace_execution: bool,
+
+    /// How deeply calls may nest before the program stops with a stack
+    /// overflow error. Past a few thousand, the tree backend may run out of
+    /// native stack first
+    #[arg(long, global = true, value_name = "CALLS", default_value_t = DEFAULT_MAX_CALL_DEPTH)]
+    max_call_depth: usize,
+
+    /// Stop the program after this many statements (tree backend) or
+    /// instructions (VM)
+    #[arg(long, global = true, value_name = "STEPS")]
+    max_steps: Option<u64>,
+
+    /// Stop the program after it has run for this many milliseconds
+    #[arg(long, global = true, value_name = "MS")]
+    timeout: Option<u64>,
+
+    /// Leave out the natives that touch files, the network, or other
+    /// processes
+    #[arg(long, global = true)]
+    sandbox: bool,
+
+    /// Don't color the REPL's input or error reports, as when NO_COLOR is set
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Treat warnings as errors, so a script with any doesn't run
+    #[arg(long, global = true)]
+    deny_warnings: bool,
+
+    /// How errors and warnings are written to stderr
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Human)]
+    error_format: ErrorFormat,
 }
 
+/// Rust stack to set aside for each Lox call the tree-walker makes, which
+/// recurses through several functions per call. Debug builds need the most.
+const STACK_BYTES_PER_CALL: usize = 128 * 1024;
+
+/// The most stack the interpreter thread asks for, however deep calls may
+/// go; beyond that the OS may refuse to create it.
+const MAX_STACK_BYTES: usize = 1024 * 1024 * 1024;
+
+/// Stack the interpreter thread keeps back from Lox calls, for the code
+/// that runs them and the statement a call is made from.
+const STACK_MARGIN: usize = 1024 * 1024;
+
 fn main() {
     let args = Args::parse();
 
-    // Alterantively run repl if no flag is passed
-    if args.repl {
-        run_prompt();
-        return;
-    } else if let Some(file_name) = args.file {
-        run_file(file_name);
-        return;
+    // Run on a thread with a big enough stack for the tree-walker to reach
+    // the maximum call depth, so deep recursion is a Lox error and not a
+    // crash.
+    let stack_size = args.stack_bytes();
+    let max_call_depth = args.max_call_depth;
+    let interpreter = thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || run_command(&args).map_err(|failure| failure.exit_code()));
+    let interpreter = match interpreter {
+        Ok(interpreter) => interpreter,
+        Err(error) => {
+            eprintln!(
+                "Error: Can't reserve the stack for a call depth of {}: {}",
+                max_call_depth, error
+            );
+            // EX_OSERR
+            process::exit(71);
+        }
+    };
+    match interpreter.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(code)) => process::exit(code),
+        Err(_) => process::exit(101),
+    }
+}
+
+/// How a program failed, which decides the exit status.
+enum Failure {
+    /// Errors found before it could start: while scanning, parsing,
+    /// resolving, compiling, or loading bytecode.
+    Compile(Vec<LoxError>),
+    /// An error that stopped it partway through.
+    Runtime(LoxError),
+    /// Some of the scripts `rslox test` ran didn't do what they expect.
+    Tests,
+    /// rslox was asked for something that doesn't exist, like the
+    /// explanation of an unknown code.
+    Usage,
+    /// A file couldn't be read.
+    Read,
+    /// A file couldn't be written.
+    Write,
+}
+
+impl Failure {
+    /// The errors, in the order they were found.
+    fn errors(&self) -> &[LoxError] {
+        match self {
+            Failure::Compile(errors) => errors,
+            Failure::Runtime(error) => std::slice::from_ref(error),
+            Failure::Tests | Failure::Usage | Failure::Read | Failure::Write => &[],
+        }
+    }
+
+    /// `EX_DATAERR` for bad input and `EX_SOFTWARE` for a program that
+    /// crashed, from BSD's sysexits.h, as the reference implementation uses,
+    /// and the codes from there for the other failures too.
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Compile(_) => 65,
+            Failure::Runtime(_) => 70,
+            Failure::Tests => 1,
+            Failure::Usage => 64,
+            Failure::Read => 66,
+            Failure::Write => 73,
+        }
+    }
+}
+
+fn run_command(args: &Args) -> Result<(), Failure> {
+    args.check_flags_for(args.backend)?;
+    match &args.command {
+        Some(Command::Run {
+            file,
+            tokens,
+            ast,
+            watch,
+        }) => {
+            if *watch {
+                return watch_file(file, args);
+            } else if *tokens {
+                return dump_tokens(file, args);
+            } else if *ast {
+                return dump_ast(file, args);
+            } else {
+                return run_file(file, args);
+            }
+        }
+        Some(Command::Repl) => {
+            repl::run_prompt(args);
+            return Ok(());
+        }
+        Some(Command::Check { file }) => return check_file(file, args),
+        Some(Command::Fmt { file, write }) => return format_file(file, *write, args),
+        Some(Command::Disasm { file }) => return disassemble_file(file, args),
+        Some(Command::Test { path }) => return test_runner::run_tests(path, args),
+        Some(Command::Explain { code }) => return explain(code.as_deref()),
+        Some(Command::Compile { file, output }) => {
+            return compile_file(file, output.as_deref(), args)
+        }
+        None => {}
+    }
+    if let Some(source) = &args.eval {
+        return run_source(&mut Session::new(args), source.clone(), EVAL, true, args);
+    }
+
+    let file_name = match args.script.as_deref().or(args.file.as_deref()) {
+        Some(file_name) if !args.repl => file_name,
+        None if !args.repl && !io::stdin().is_terminal() => STDIN,
+        _ => {
+            repl::run_prompt(args);
+            return Ok(());
+        }
+    };
+    run_file(file_name, args)
+}
+
+/// The file name that stands for standard input.
+const STDIN: &str = "-";
+
+/// What reports call the code given to `-e`.
+const EVAL: &str = "<eval>";
+
+/// Reads the file `file_name`, or stdin for [`STDIN`], reporting it if it
+/// can't.
+fn read_bytes(file_name: &str) -> Result<Vec<u8>, Failure> {
+    let result = if file_name == STDIN {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes).map(|_| bytes)
     } else {
-        println!("Error: No file or REPL flag passed");
+        fs::read(file_name)
+    };
+    result.map_err(|error| {
+        eprintln!("Error reading '{}': {}", file_name, error);
+        Failure::Read
+    })
+}
+
+/// Reads the script `file_name`, or stdin for [`STDIN`], which has to be
+/// UTF-8.
+fn read_source(file_name: &str) -> Result<String, Failure> {
+    source_text(file_name, read_bytes(file_name)?)
+}
+
+fn source_text(file_name: &str, bytes: Vec<u8>) -> Result<String, Failure> {
+    String::from_utf8(bytes).map_err(|error| {
+        eprintln!("Error reading '{}': {}", file_name, error);
+        // Like a syntax error, it's the script that's wrong.
+        Failure::Compile(Vec::new())
+    })
+}
+
+/// Writes `contents` to the file `file_name`, reporting it if it can't.
+fn write_file(file_name: &Path, contents: impl AsRef<[u8]>) -> Result<(), Failure> {
+    fs::write(file_name, contents).map_err(|error| {
+        eprintln!("Error writing '{}': {}", file_name.display(), error);
+        Failure::Write
+    })
+}
+
+impl Args {
+    /// Whether to color the REPL and error reports, unless `--no-color` or
+    /// the NO_COLOR environment variable says not to.
+    fn color(&self) -> bool {
+        !self.no_color && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+    }
+
+    /// Fails with a usage error if a flag was given that `backend` doesn't
+    /// have, rather than running without it.
+    fn check_flags_for(&self, backend: Backend) -> Result<(), Failure> {
+        let (flags, other) = match backend {
+            Backend::Tree => (
+                vec![
+                    ("--gc-stress", self.gc_stress),
+                    ("--gc-threshold", self.gc_threshold.is_some()),
+                    ("--max-heap", self.max_heap.is_some()),
+                    ("--trace-execution", self.trace_execution),
+                ],
+                "vm",
+            ),
+            Backend::Vm => (
+                vec![("--error-on-uninitialized", self.error_on_uninitialized)],
+                "tree",
+            ),
+        };
+        let Some((flag, _)) = flags.into_iter().find(|&(_, given)| given) else {
+            return Ok(());
+        };
+        eprintln!("Error: {} only works with --backend {}.", flag, other);
+        Err(Failure::Usage)
+    }
+
+    fn vm_options(&self) -> VmOptions {
+        let default = GcConfig::default();
+        VmOptions {
+            gc: GcConfig {
+                initial_threshold: self.gc_threshold.unwrap_or(default.initial_threshold),
+                stress: self.gc_stress,
+                max_heap: self.max_heap,
+                ..default
+            },
+            max_call_depth: self.max_call_depth,
+            trace_execution: self.trace_execution,
+            max_steps: self.max_steps,
+            timeout: self.timeout.map(Duration::from_millis),
+            allow_fs: !self.sandbox,
+            allow_net: !self.sandbox,
+            allow_process: !self.sandbox,
+        }
     }
+
+    fn interpreter_options(&self) -> InterpreterOptions {
+        InterpreterOptions {
+            error_on_uninitialized: self.error_on_uninitialized,
+            max_call_depth: self.max_call_depth,
+            max_stack: self.stack_bytes() - STACK_MARGIN,
+            max_steps: self.max_steps,
+            timeout: self.timeout.map(Duration::from_millis),
+            allow_fs: !self.sandbox,
+            allow_net: !self.sandbox,
+            allow_process: !self.sandbox,
+        }
+    }
+
+    /// How big a stack to run on for the tree-walker to reach the maximum
+    /// call depth.
+    fn stack_bytes(&self) -> usize {
+        self.max_call_depth
+            .saturating_mul(STACK_BYTES_PER_CALL)
+            .clamp(8 * 1024 * 1024, MAX_STACK_BYTES)
+    }
+}
+
+fn run_file(file_name: &str, args: &Args) -> Result<(), Failure> {
+    let bytes = read_bytes(file_name)?;
+    if loxc::is_bytecode(&bytes) {
+        return run_bytecode(file_name, &bytes, args);
+    }
+
+    let file_string = source_text(file_name, bytes)?;
+
+    let mut session = Session::new(args);
+    run_source(&mut session, file_string, file_name, args.repl, args)
 }
 
-fn run_prompt() {
-    // Loop and ask the users for input
+/// How often `run --watch` looks at the file's modification time.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runs the script `file_name`, then runs it again each time it is saved,
+/// clearing the terminal first, until the process is stopped.
+fn watch_file(file_name: &str, args: &Args) -> Result<(), Failure> {
+    if file_name == STDIN {
+        eprintln!("Error: Can't watch stdin.");
+        return Err(Failure::Usage);
+    }
+    let modified = || {
+        fs::metadata(file_name)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let mut last_run = None;
     loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
-        let mut input = String::new();
-        std::io::stdin()
-            .read_line(&mut input)
-            .expect("Error reading input");
+        // Editors that save by renaming a new file into place leave a
+        // moment with no file at all, so that isn't a change yet.
+        let current = modified();
+        if current.is_some() && current != last_run {
+            last_run = current;
+            if io::stdout().is_terminal() {
+                print!("\x1b[2J\x1b[H");
+            }
+            let _ = run_file(file_name, args);
+            eprintln!(
+                "[Watching {} for changes. Press Ctrl-C to stop.]",
+                file_name
+            );
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
 
-        let result = run(input);
-        if let Err(e) = result {
-            e.report();
+/// Runs a precompiled `.loxc` file, which only the VM can do.
+fn run_bytecode(file_name: &str, bytes: &[u8], args: &Args) -> Result<(), Failure> {
+    // Compiled files always run on the VM, whichever backend was chosen.
+    args.check_flags_for(Backend::Vm)?;
+    let mut vm = Vm::with_options(args.vm_options());
+    match vm.load_bytecode(bytes) {
+        // There's no source to quote, only the lines the compiler recorded.
+        Ok(script) => vm.run_script(script).map_err(|error| {
+            report(std::slice::from_ref(&error), file_name, "", args);
+            Failure::Runtime(error)
+        }),
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            Err(Failure::Compile(Vec::new()))
         }
-        println!("");
     }
 }
 
-fn run_file(file_name: String) {
-    // read file name as string
-    let file_string = fs::read_to_string(file_name).expect("Error reading file");
+fn compile_file(file_name: &str, output: Option<&str>, args: &Args) -> Result<(), Failure> {
+    let file_string = read_source(file_name)?;
 
-    if let Err(e) = run(file_string) {
-        e.report();
+    let result = parse(file_string.clone(), false).and_then(|program| {
+        Vm::new()
+            .compile_to_bytecode(&program.ast)
+            .map_err(|e| vec![e])
+    });
+    match result {
+        Ok(bytecode) => {
+            let output = match output {
+                Some(output) => output.into(),
+                None => Path::new(file_name).with_extension("loxc"),
+            };
+            write_file(&output, bytecode)
+        }
+        Err(errors) => {
+            report(&errors, file_name, &file_string, args);
+            Err(Failure::Compile(errors))
+        }
     }
 }
 
-fn run(source: String) -> Result<(), LoxError> {
-    let mut lexer = Scanner::new(source);
-    let tokens = lexer.scan_tokens()?;
+/// Scans, parses, and resolves the script `file_name`, and compiles it if
+/// it is for the VM, without running it.
+fn check_file(file_name: &str, args: &Args) -> Result<(), Failure> {
+    let source = read_source(file_name)?;
+    let result = parse(source.clone(), false)
+        .map_err(Failure::Compile)
+        .and_then(|program| {
+            warn(&program.warnings, file_name, &source, args)?;
+            match args.backend {
+                Backend::Tree => Ok(()),
+                Backend::Vm => Vm::new()
+                    .compile(&program.ast)
+                    .map(|_| ())
+                    .map_err(|error| Failure::Compile(vec![error])),
+            }
+        });
+    result.inspect_err(|failure| report(failure.errors(), file_name, &source, args))
+}
 
-    for token in tokens {
-        println!("{}", token);
+/// Prints the script `file_name` formatted, or with `write` saves it back.
+fn format_file(file_name: &str, write: bool, args: &Args) -> Result<(), Failure> {
+    let source = read_source(file_name)?;
+    let formatted = formatter::format(&source)
+        .map_err(Failure::Compile)
+        .inspect_err(|failure| report(failure.errors(), file_name, &source, args))?;
+    if write {
+        write_file(Path::new(file_name), formatted)?;
+    } else {
+        print!("{}", formatted);
     }
+    Ok(())
+}
 
+fn disassemble_file(file_name: &str, args: &Args) -> Result<(), Failure> {
+    let file_string = read_source(file_name)?;
+
+    let result = parse(file_string.clone(), false)
+        .and_then(|program| Vm::new().disassemble(&program.ast).map_err(|e| vec![e]));
+    match result {
+        Ok(listing) => {
+            print!("{}", listing);
+            Ok(())
+        }
+        Err(errors) => {
+            report(&errors, file_name, &file_string, args);
+            Err(Failure::Compile(errors))
+        }
+    }
+}
+
+fn dump_tokens(file_name: &str, args: &Args) -> Result<(), Failure> {
+    let file_string = read_source(file_name)?;
+    print_tokens(&file_string, file_name, args)
+}
+
+/// Prints the token stream of `source`, read from `file_name`, as JSON.
+fn print_tokens(source: &str, file_name: &str, args: &Args) -> Result<(), Failure> {
+    match Scanner::new(source.to_string()).scan_tokens() {
+        Ok(tokens) => {
+            let tokens: Vec<_> = tokens.iter().map(token_json).collect();
+            println!("{}", serde_json::Value::Array(tokens));
+            Ok(())
+        }
+        Err(errors) => {
+            report(&errors, file_name, source, args);
+            Err(Failure::Compile(errors))
+        }
+    }
+}
+
+fn dump_ast(file_name: &str, args: &Args) -> Result<(), Failure> {
+    let file_string = read_source(file_name)?;
+    print_ast(&file_string, file_name, false, args)
+}
+
+/// Prints the syntax tree of `source`, read from `file_name`, parsed as a
+/// line typed at the REPL if `repl` is set.
+fn print_ast(source: &str, file_name: &str, repl: bool, args: &Args) -> Result<(), Failure> {
+    // Only scan and parse, so the tree can be inspected even when the
+    // resolver would reject it. Syntax errors still print what did parse.
+    let errors = match Scanner::new(source.to_string()).scan_tokens() {
+        Ok(tokens) => {
+            let mut parser = LoxParser::new(tokens);
+            let (ast, errors) = if repl {
+                parser.parse_repl_partial()
+            } else {
+                parser.parse_partial()
+            };
+            print!("{}", ast_printer::print_program(&ast));
+            errors
+        }
+        Err(errors) => errors,
+    };
+    if errors.is_empty() {
+        return Ok(());
+    }
+    report(&errors, file_name, source, args);
+    Err(Failure::Compile(errors))
+}
+
+/// Prints the explanation of the diagnostic code `id`, or a list of every
+/// code without one.
+fn explain(id: Option<&str>) -> Result<(), Failure> {
+    let Some(id) = id else {
+        for code in codes::CODES {
+            println!("{}  {}", code.id, code.summary());
+        }
+        return Ok(());
+    };
+    match codes::lookup(id) {
+        Some(code) => {
+            print!("{}: {}", code.id, code.explanation);
+            Ok(())
+        }
+        None => {
+            eprintln!("Error: No diagnostic has the code '{}'.", id);
+            Err(Failure::Usage)
+        }
+    }
+}
+
+/// Reports `errors` to stderr, quoting `source`, which was read from
+/// `file_name`.
+fn report(errors: &[LoxError], file_name: &str, source: &str, args: &Args) {
+    render(errors.iter().map(Diagnostic::from), file_name, source, args);
+}
+
+/// Reports `warnings` like [`report`] does errors. With `--deny-warnings`
+/// they are reported as errors, and fail the program.
+fn warn(warnings: &[Warning], file_name: &str, source: &str, args: &Args) -> Result<(), Failure> {
+    let diagnostics = warnings.iter().map(|warning| Diagnostic {
+        severity: match args.deny_warnings {
+            true => Severity::Error,
+            false => Severity::Warning,
+        },
+        ..Diagnostic::from(warning)
+    });
+    render(diagnostics, file_name, source, args);
+    if args.deny_warnings && !warnings.is_empty() {
+        // They have been reported already.
+        return Err(Failure::Compile(Vec::new()));
+    }
     Ok(())
 }
+
+fn render(
+    diagnostics: impl Iterator<Item = Diagnostic>,
+    file_name: &str,
+    source: &str,
+    args: &Args,
+) {
+    let file = SourceFile {
+        name: if file_name == STDIN {
+            "<stdin>"
+        } else {
+            file_name
+        },
+        text: source,
+    };
+    let mut stderr = io::stderr().lock();
+    if args.error_format == ErrorFormat::Json {
+        for diagnostic in diagnostics {
+            let _ = writeln!(stderr, "{}", diagnostic.to_json(&file));
+        }
+        return;
+    }
+    let color = args.color() && io::stderr().is_terminal();
+    let mut explainable = None;
+    for diagnostic in diagnostics {
+        if diagnostic.severity == Severity::Error {
+            explainable = explainable.or(diagnostic.code);
+        }
+        // Like `eprintln!`, except that a closed stderr isn't worth a panic.
+        let _ = diagnostic.render(&file, color, &mut stderr);
+    }
+    if let Some(code) = explainable {
+        let _ = writeln!(
+            stderr,
+            "For more information about an error, try `rslox explain {}`.",
+            code
+        );
+    }
+}
+
+fn token_json(token: &Token) -> serde_json::Value {
+    let literal = match token.literal() {
+        Some(Literal::String(s)) => serde_json::json!(s),
+        Some(Literal::Number(n)) => serde_json::json!(n),
+        None => serde_json::Value::Null,
+    };
+    let span = token.span();
+    serde_json::json!({
+        "kind": format!("{:?}", token.kind()),
+        "lexeme": token.lexeme(),
+        "literal": literal,
+        "line": token.line(),
+        "column": span.column,
+        "span": { "start": span.start, "len": span.len },
+    })
+}
+
+/// A parsed program along with what the resolver found out about it.
+struct Program {
+    ast: Rc<Ast>,
+    /// The scope depth of each local variable reference.
+    locals: HashMap<ExprId, usize>,
+    warnings: Vec<Warning>,
+}
+
+/// Scans, parses, and resolves `source`. In the REPL a bare expression is
+/// echoed.
+fn parse(source: String, repl: bool) -> Result<Program, Vec<LoxError>> {
+    let ast = parse_source(source, repl)?;
+    let mut resolver = Resolver::new();
+    let locals = resolver.resolve(&ast)?;
+    Ok(Program {
+        ast: Rc::new(ast),
+        locals,
+        warnings: resolver.warnings().to_vec(),
+    })
+}
+
+/// Runs `source`, read from `file_name`, on `session`, reporting its
+/// warnings before it runs and any errors it fails with.
+fn run_source(
+    session: &mut Session,
+    source: String,
+    file_name: &str,
+    repl: bool,
+    args: &Args,
+) -> Result<(), Failure> {
+    let result = parse(source.clone(), repl)
+        .map_err(Failure::Compile)
+        .and_then(|program| {
+            warn(&program.warnings, file_name, &source, args)?;
+            session.execute(program)
+        });
+    result.inspect_err(|failure| report(failure.errors(), file_name, &source, args))
+}
+
+/// The backend programs run on, kept across the lines of a REPL session so
+/// that what one line defines the next can use.
+enum Session {
+    Tree(Box<Interpreter>),
+    Vm(Box<Vm>),
+}
+
+impl Session {
+    fn new(args: &Args) -> Self {
+        match args.backend {
+            Backend::Tree => Session::Tree(Box::new(Interpreter::with_options(
+                args.interpreter_options(),
+            ))),
+            Backend::Vm => Session::Vm(Box::new(Vm::with_options(args.vm_options()))),
+        }
+    }
+
+    /// Runs `source`, ignoring any warnings.
+    fn run(&mut self, source: String, repl: bool) -> Result<(), Failure> {
+        let program = parse(source, repl).map_err(Failure::Compile)?;
+        self.execute(program)
+    }
+
+    fn execute(&mut self, program: Program) -> Result<(), Failure> {
+        match self {
+            Session::Tree(interpreter) => {
+                interpreter.resolve(program.locals);
+                interpreter
+                    .interpret(&program.ast)
+                    .map_err(Failure::Runtime)
+            }
+            Session::Vm(vm) => {
+                let script = vm
+                    .compile(&program.ast)
+                    .map_err(|error| Failure::Compile(vec![error]))?;
+                vm.run_script(script).map_err(Failure::Runtime)
+            }
+        }
+    }
+
+    fn set_output(&mut self, stdout: Box<dyn Write>, stderr: Box<dyn Write>) {
+        match self {
+            Session::Tree(interpreter) => interpreter.set_output(stdout, stderr),
+            Session::Vm(vm) => vm.set_output(stdout, stderr),
+        }
+    }
+
+    fn set_input(&mut self, stdin: Box<dyn BufRead>) {
+        match self {
+            Session::Tree(interpreter) => interpreter.set_input(stdin),
+            Session::Vm(vm) => vm.set_input(stdin),
+        }
+    }
+
+    fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        match self {
+            Session::Tree(interpreter) => interpreter.set_interrupt(flag),
+            Session::Vm(vm) => vm.set_interrupt(flag),
+        }
+    }
+
+    fn members(&self, name: &str) -> Vec<String> {
+        match self {
+            Session::Tree(interpreter) => interpreter.members(name),
+            Session::Vm(vm) => vm.members(name),
+        }
+    }
+
+    fn globals(&self) -> Vec<(String, String)> {
+        match self {
+            Session::Tree(interpreter) => interpreter.globals(),
+            Session::Vm(vm) => vm.globals(),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Maps, `{"name": "lox", 1: true}`: tables from strings and numbers to
+//! any values, shared by reference like lists. Entries stay in the order
+//! they were first added in, which is the order they print and `keys()`
+//! lists them in. As with [lists](crate::list), the table and its methods
+//! are written once here, over whichever strings and values the backend
+//! has.
+
+use std::{cell::RefCell, collections::HashMap, hash::Hash, mem::size_of, rc::Rc};
+
+use crate::{
+    error::{Fault, LoxError},
+    intern::Symbol,
+    lexer::Token,
+    native::NativeFunction,
+    suggest,
+    value::{format_number, Value},
+};
+
+/// A key a map can hold: a number, by its bits, or a string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapKey<S> {
+    Number(u64),
+    String(S),
+}
+
+impl<S> MapKey<S> {
+    /// The key for a value that is `number` or `string`, if it is either.
+    pub fn new(number: Option<f64>, string: Option<S>) -> Result<Self, Fault> {
+        match (number, string) {
+            (Some(n), _) => {
+                // `-0` and `0` are the same key, and so are all NaNs.
+                let n = if n == 0.0 {
+                    0.0
+                } else if n.is_nan() {
+                    f64::NAN
+                } else {
+                    n
+                };
+                Ok(MapKey::Number(n.to_bits()))
+            }
+            (None, Some(s)) => Ok(MapKey::String(s)),
+            (None, None) => Err(Fault::new("R0019", "Map keys must be strings or numbers.")),
+        }
+    }
+
+    /// How the key appears in error messages, given the text of a string.
+    pub fn describe(&self, text: impl FnOnce(&S) -> String) -> String {
+        match self {
+            MapKey::Number(bits) => format_number(f64::from_bits(*bits)),
+            MapKey::String(s) => format!("'{}'", text(s)),
+        }
+    }
+}
+
+/// The error for reading `key`, as [described](MapKey::describe), from a
+/// map that doesn't have it.
+pub fn missing_key(key: &str) -> Fault {
+    Fault::new("R0019", format!("Map has no key {}.", key))
+}
+
+/// The entries of a map, in the order they were added.
+#[derive(Debug)]
+pub struct LoxMap<S, V> {
+    entries: Vec<(MapKey<S>, V)>,
+    /// Each key's index in `entries`.
+    slots: HashMap<MapKey<S>, usize>,
+}
+
+impl<S, V> Default for LoxMap<S, V> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            slots: HashMap::new(),
+        }
+    }
+}
+
+impl<S: Clone + Eq + Hash, V> LoxMap<S, V> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey<S>) -> Option<&V> {
+        self.slots.get(key).map(|&slot| &self.entries[slot].1)
+    }
+
+    pub fn contains(&self, key: &MapKey<S>) -> bool {
+        self.slots.contains_key(key)
+    }
+
+    /// Sets `key` to `value`. A key that is already there keeps its place.
+    pub fn insert(&mut self, key: MapKey<S>, value: V) {
+        match self.slots.get(&key) {
+            Some(&slot) => self.entries[slot].1 = value,
+            None => {
+                self.slots.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    /// Removes `key`, returning its value if it was there.
+    pub fn remove(&mut self, key: &MapKey<S>) -> Option<V> {
+        let slot = self.slots.remove(key)?;
+        let (_, value) = self.entries.remove(slot);
+        for (key, _) in &self.entries[slot..] {
+            if let Some(later) = self.slots.get_mut(key) {
+                *later -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &(MapKey<S>, V)> {
+        self.entries.iter()
+    }
+
+    /// Approximate number of bytes the map owns, besides itself.
+    pub fn capacity_bytes(&self) -> usize {
+        self.entries.capacity() * size_of::<(MapKey<S>, V)>()
+            + self.slots.capacity() * size_of::<(MapKey<S>, usize)>()
+    }
+}
+
+/// A method every map has, called as in `m.has("key")`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapMethod {
+    /// `delete(key)`: removes `key`, returning whether it was there.
+    Delete,
+    /// `has(key)`: whether `key` is there.
+    Has,
+    /// `keys()`: a list of the keys.
+    Keys,
+    /// `length()`: how many entries there are.
+    Length,
+    /// `values()`: a list of the values, in the same order as the keys.
+    Values,
+}
+
+impl MapMethod {
+    /// Sorted by name.
+    pub const ALL: [MapMethod; 5] = [
+        MapMethod::Delete,
+        MapMethod::Has,
+        MapMethod::Keys,
+        MapMethod::Length,
+        MapMethod::Values,
+    ];
+
+    pub fn named(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|method| method.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MapMethod::Delete => "delete",
+            MapMethod::Has => "has",
+            MapMethod::Keys => "keys",
+            MapMethod::Length => "length",
+            MapMethod::Values => "values",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            MapMethod::Keys | MapMethod::Length | MapMethod::Values => 0,
+            MapMethod::Delete | MapMethod::Has => 1,
+        }
+    }
+}
+
+/// The tree-walking interpreter's key for `value`.
+pub fn key(value: &Value) -> Result<MapKey<Symbol>, Fault> {
+    let string = match value {
+        Value::String(s) => Some(s.clone()),
+        _ => None,
+    };
+    MapKey::new(value.as_number(), string)
+}
+
+/// The tree-walking interpreter's value for `key`.
+pub fn key_value(key: &MapKey<Symbol>) -> Value {
+    match key {
+        MapKey::Number(bits) => Value::Number(f64::from_bits(*bits)),
+        MapKey::String(s) => Value::String(s.clone()),
+    }
+}
+
+/// Reads method `name` of `map` in the tree-walking interpreter, as a
+/// native bound to the map.
+pub fn get(map: &Rc<RefCell<LoxMap<Symbol, Value>>>, name: &Token) -> Result<Value, LoxError> {
+    let Some(method) = MapMethod::named(&name.lexeme) else {
+        let names = MapMethod::ALL.map(MapMethod::name);
+        return Err(LoxError::runtime_at(
+            name,
+            "R0005",
+            suggest::undefined("property", &name.lexeme, names),
+        ));
+    };
+    let map = map.clone();
+    let native = NativeFunction::with_faults(method.name(), method.arity(), move |arguments| {
+        let list = |elements: Vec<Value>| Value::List(Rc::new(RefCell::new(elements)));
+        Ok(match method {
+            MapMethod::Delete => {
+                Value::Bool(map.borrow_mut().remove(&key(&arguments[0])?).is_some())
+            }
+            MapMethod::Has => Value::Bool(map.borrow().contains(&key(&arguments[0])?)),
+            MapMethod::Keys => list(
+                map.borrow()
+                    .entries()
+                    .map(|(key, _)| key_value(key))
+                    .collect(),
+            ),
+            MapMethod::Length => Value::Number(map.borrow().len() as f64),
+            MapMethod::Values => list(
+                map.borrow()
+                    .entries()
+                    .map(|(_, value)| value.clone())
+                    .collect(),
+            ),
+        })
+    });
+    Ok(Value::Native(Rc::new(native)))
+}
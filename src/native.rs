@@ -0,0 +1,590 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::hash_map::RandomState,
+    fmt::Debug,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+    time::Instant,
+};
+
+use crate::{
+    callable::LoxCallable,
+    error::{Fault, LoxError},
+    interpreter::Interpreter,
+    lexer::Scanner,
+    value::{FromLox, ToLox, Value},
+};
+
+/// The Rust side of a [`NativeFunction`].
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, Fault>;
+
+/// A built-in function implemented in Rust, either one of [`natives`] or one
+/// an embedder registered.
+///
+/// Natives only deal in values both backends share: nil, booleans, numbers,
+/// and strings, and they may also return lists of those. An `Err` is
+/// reported as a runtime error at the call site.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: Rc<str>,
+    pub arity: usize,
+    pub function: Rc<NativeFn>,
+    /// What the native reaches outside the program for, if anything.
+    pub capability: Option<Capability>,
+}
+
+/// Access to the outside world a native may need, which a sandboxed
+/// interpreter or VM withholds by leaving out the natives that need it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Reading and writing files.
+    Fs,
+    /// Making network connections.
+    Net,
+    /// Starting other processes, reading the environment, or exiting.
+    Process,
+}
+
+impl NativeFunction {
+    pub fn new<R: ToLox>(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<R, String> + 'static,
+    ) -> Self {
+        Self::with_faults(name, arity, move |arguments| {
+            function(arguments).map_err(Fault::from)
+        })
+    }
+
+    /// Like [`new`](NativeFunction::new), for a native whose errors have
+    /// codes of their own, like a failed `assert`.
+    pub fn with_faults<R: ToLox>(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<R, Fault> + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            arity,
+            function: Rc::new(move |arguments| function(arguments).map(ToLox::to_lox)),
+            capability: None,
+        }
+    }
+
+    /// Like [`new`](NativeFunction::new), for a `function` that takes its
+    /// arguments already converted, one parameter each, e.g.
+    /// `|text: String, times: i64| Ok(text.repeat(times.max(0) as usize))`.
+    /// Its arity is its number of parameters, and an argument that doesn't
+    /// convert fails the call with the [`FromLox`] error.
+    pub fn typed<Args, F: TypedNative<Args> + 'static>(name: &str, function: F) -> Self {
+        Self::new(name, F::ARITY, move |arguments| function.call(arguments))
+    }
+
+    /// Marks the native as needing `capability`, so it is only defined
+    /// where that is allowed.
+    pub fn requires(mut self, capability: Capability) -> Self {
+        self.capability = Some(capability);
+        self
+    }
+}
+
+/// A Rust function of up to three [`FromLox`] parameters that returns a
+/// [`ToLox`] result, which [`NativeFunction::typed`] can call with a
+/// script's arguments. `Args` is the tuple of the parameter types.
+pub trait TypedNative<Args> {
+    const ARITY: usize;
+
+    /// Converts `arguments`, which there are [`ARITY`](TypedNative::ARITY)
+    /// of, and calls the function with them.
+    fn call(&self, arguments: &[Value]) -> Result<Value, String>;
+}
+
+macro_rules! typed_native {
+    ($arity:literal $(, $param:ident)*) => {
+        impl<F, R, $($param),*> TypedNative<($($param,)*)> for F
+        where
+            F: Fn($($param),*) -> Result<R, String>,
+            R: ToLox,
+            $($param: FromLox,)*
+        {
+            const ARITY: usize = $arity;
+
+            #[allow(unused_variables, unused_mut)]
+            fn call(&self, arguments: &[Value]) -> Result<Value, String> {
+                let mut arguments = arguments.iter();
+                self($($param::from_lox(arguments.next().expect("called with its arity"))?),*)
+                    .map(ToLox::to_lox)
+            }
+        }
+    };
+}
+
+typed_native!(0);
+typed_native!(1, A);
+typed_native!(2, A, B);
+typed_native!(3, A, B, C);
+
+/// Where `readLine` and `readNumber` read from, shared by those natives and
+/// the interpreter or VM that defines them: the process's stdin, or the
+/// stream it was given in its place.
+pub type Input = Rc<RefCell<Option<Box<dyn BufRead>>>>;
+
+/// When the run in progress has to stop by, given a timeout, shared by
+/// `sleep` and the interpreter or VM that defines it so that a sleep can't
+/// outlast the run.
+pub type Deadline = Rc<Cell<Option<Instant>>>;
+
+/// The natives every program starts with, defined as globals by both the
+/// tree-walking interpreter and the VM. Those needing a capability are left
+/// out unless `allowed` says it may be used.
+pub fn natives(
+    allowed: impl Fn(Capability) -> bool,
+    input: &Input,
+    deadline: &Deadline,
+) -> Vec<NativeFunction> {
+    time::start();
+    let rng = Rc::new(Rng::new());
+    let natives = vec![
+        NativeFunction::with_faults("assert", 2, assert),
+        NativeFunction::typed("chr", chr),
+        NativeFunction::typed("clock", clock),
+        NativeFunction::typed("now", now),
+        NativeFunction::typed("num", num),
+        NativeFunction::typed("ord", ord),
+        NativeFunction::typed("panic", panic),
+        sleep(deadline.clone()),
+        math("abs", f64::abs),
+        math("ceil", f64::ceil),
+        math("cos", f64::cos),
+        math("floor", f64::floor),
+        math("log", f64::ln),
+        math2("max", f64::max),
+        math2("min", f64::min),
+        math2("pow", f64::powf),
+        math("round", f64::round),
+        math("sin", f64::sin),
+        math("sqrt", f64::sqrt),
+        random(rng.clone()),
+        random_int(rng.clone()),
+        seed_random(rng),
+        read_line(input.clone()),
+        read_number(input.clone()),
+        NativeFunction::typed("appendFile", append_file).requires(Capability::Fs),
+        NativeFunction::typed("deleteFile", delete_file).requires(Capability::Fs),
+        NativeFunction::typed("fileExists", file_exists).requires(Capability::Fs),
+        NativeFunction::typed("listDir", list_dir).requires(Capability::Fs),
+        NativeFunction::typed("readFile", read_file).requires(Capability::Fs),
+        NativeFunction::typed("writeFile", write_file).requires(Capability::Fs),
+    ];
+    natives
+        .into_iter()
+        .filter(|native| native.capability.is_none_or(&allowed))
+        .collect()
+}
+
+/// The constants every program starts with, defined as globals alongside
+/// the [`natives`].
+pub const CONSTANTS: [(&str, f64); 2] = [("E", std::f64::consts::E), ("PI", std::f64::consts::PI)];
+
+/// A native applying `function` to its one number, like `sqrt(2)`. `round`
+/// rounds halves away from zero, and `log` is the natural logarithm.
+fn math(name: &str, function: fn(f64) -> f64) -> NativeFunction {
+    NativeFunction::typed(name, move |n: f64| Ok(function(n)))
+}
+
+/// A native applying `function` to its two numbers, like `pow(2, 10)`.
+fn math2(name: &str, function: fn(f64, f64) -> f64) -> NativeFunction {
+    NativeFunction::typed(name, move |a: f64, b: f64| Ok(function(a, b)))
+}
+
+/// The generator behind the random natives: SplitMix64, which is small,
+/// fast, and fine for games and simulations, but not for secrets. Each
+/// interpreter or VM has its own.
+struct Rng {
+    state: Cell<u64>,
+}
+
+impl Rng {
+    /// A generator seeded differently on every run.
+    fn new() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(time::millis_since_epoch().unwrap_or_default().to_bits());
+        Self {
+            state: Cell::new(hasher.finish()),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        let state = self.state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.state.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// `random()`: a number from 0 up to but not including 1.
+fn random(rng: Rc<Rng>) -> NativeFunction {
+    NativeFunction::new("random", 0, move |_arguments| {
+        Ok((rng.next() >> 11) as f64 / (1u64 << 53) as f64)
+    })
+}
+
+/// `randomInt(lo, hi)`: a whole number from `lo` to `hi`, both included.
+fn random_int(rng: Rc<Rng>) -> NativeFunction {
+    NativeFunction::typed("randomInt", move |lo: i64, hi: i64| {
+        if lo > hi {
+            return Err("Random range is empty.".to_string());
+        }
+        let span = hi.abs_diff(lo) as u128 + 1;
+        let offset = (rng.next() as u128 * span) >> 64;
+        Ok((lo as i128 + offset as i128) as f64)
+    })
+}
+
+/// `seedRandom(n)`: restarts the random numbers from `n`, so a run can be
+/// repeated exactly.
+fn seed_random(rng: Rc<Rng>) -> NativeFunction {
+    NativeFunction::typed("seedRandom", move |seed: f64| {
+        rng.state.set(seed.to_bits());
+        Ok(())
+    })
+}
+
+/// `readLine()`: the next line of input, without its line break, or nil at
+/// the end of it.
+fn read_line(input: Input) -> NativeFunction {
+    NativeFunction::new("readLine", 0, move |_arguments| next_line(&input))
+}
+
+/// `readNumber()`: the number on the next line of input, or nil at the end
+/// of it or if the line isn't a number.
+fn read_number(input: Input) -> NativeFunction {
+    NativeFunction::new("readNumber", 0, move |_arguments| {
+        Ok(next_line(&input)?.and_then(|line| parse_number(&line)))
+    })
+}
+
+/// The number `text` spells, if it is written the way a number literal in a
+/// script would be, like `0x10` or `1_000`, possibly after a `-`. Whitespace
+/// around it is ignored.
+fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    match text.strip_prefix('-') {
+        Some(magnitude) => Scanner::number_literal(magnitude).map(|n| -n),
+        None => Scanner::number_literal(text),
+    }
+}
+
+fn next_line(input: &Input) -> Result<Option<String>, String> {
+    let mut line = String::new();
+    let read = match &mut *input.borrow_mut() {
+        Some(input) => input.read_line(&mut line),
+        None => io::stdin().read_line(&mut line),
+    };
+    if read.map_err(|error| format!("Couldn't read input: {}.", error))? == 0 {
+        return Ok(None);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Some(line))
+}
+
+/// `readFile(path)`: the text of the file at `path`.
+fn read_file(path: String) -> Result<String, String> {
+    fs::read_to_string(&path).map_err(|error| file_error("read", &path, error))
+}
+
+/// `writeFile(path, text)`: replaces the file at `path` with `text`,
+/// creating it if need be.
+fn write_file(path: String, text: String) -> Result<(), String> {
+    fs::write(&path, text).map_err(|error| file_error("write", &path, error))
+}
+
+/// `appendFile(path, text)`: adds `text` to the end of the file at `path`,
+/// creating it if need be.
+fn append_file(path: String, text: String) -> Result<(), String> {
+    fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(|error| file_error("append to", &path, error))
+}
+
+/// `fileExists(path)`: whether there is a file or directory at `path`.
+fn file_exists(path: String) -> Result<bool, String> {
+    Ok(Path::new(&path).exists())
+}
+
+/// `listDir(path)`: the names of the entries in the directory at `path`,
+/// sorted.
+fn list_dir(path: String) -> Result<Vec<String>, String> {
+    let entries = fs::read_dir(&path).and_then(|entries| {
+        entries
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<_>>>()
+    });
+    let mut names = entries.map_err(|error| file_error("list", &path, error))?;
+    names.sort();
+    Ok(names)
+}
+
+/// `deleteFile(path)`: removes the file at `path`.
+fn delete_file(path: String) -> Result<(), String> {
+    fs::remove_file(&path).map_err(|error| file_error("delete", &path, error))
+}
+
+fn file_error(action: &str, path: &str, error: io::Error) -> String {
+    format!("Couldn't {} '{}': {}.", action, path, error)
+}
+
+/// `assert(condition, message)`: fails with `message` unless `condition`
+/// is truthy.
+fn assert(arguments: &[Value]) -> Result<(), Fault> {
+    let message = String::from_lox(&arguments[1])?;
+    if arguments[0].is_truthy() {
+        Ok(())
+    } else {
+        Err(Fault::new(
+            "R0021",
+            format!("Assertion failed: {}", message),
+        ))
+    }
+}
+
+/// `panic(message)`: fails with `message`.
+fn panic(message: String) -> Result<(), String> {
+    Err(message)
+}
+
+/// `num(text)`: the number `text` spells as a number literal would, like
+/// `"0x10"` or `"-1_000"`, or nil if it isn't one.
+fn num(text: String) -> Result<Option<f64>, String> {
+    Ok(parse_number(&text))
+}
+
+/// `chr(code)`: the character with Unicode code point `code`, as a string.
+fn chr(code: i64) -> Result<String, String> {
+    u32::try_from(code)
+        .ok()
+        .and_then(char::from_u32)
+        .map(String::from)
+        .ok_or_else(|| format!("{} is not a valid character code.", code))
+}
+
+/// `ord(character)`: the Unicode code point of the one character in
+/// `character`.
+fn ord(text: String) -> Result<f64, String> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(u32::from(c) as f64),
+        _ => Err("Argument must be a single character.".to_string()),
+    }
+}
+
+/// `clock()`: seconds since startup, as a fraction, for timing code.
+fn clock() -> Result<f64, String> {
+    Ok(time::seconds_since_start())
+}
+
+/// `now()`: milliseconds since the Unix epoch.
+fn now() -> Result<f64, String> {
+    time::millis_since_epoch()
+}
+
+/// `sleep(ms)`: pauses the program for `ms` milliseconds, or until the
+/// run's deadline if that comes first, which then stops the program.
+fn sleep(deadline: Deadline) -> NativeFunction {
+    NativeFunction::new("sleep", 1, move |arguments| match arguments[0] {
+        Value::Number(ms) if ms >= 0.0 && ms.is_finite() => {
+            time::sleep(ms, deadline.get())?;
+            Ok(Value::Nil)
+        }
+        _ => Err("Sleep duration must be a non-negative number of milliseconds.".to_string()),
+    })
+}
+
+/// The clocks the time natives read.
+#[cfg(not(all(target_arch = "wasm32", feature = "wasm")))]
+mod time {
+    use std::{
+        sync::OnceLock,
+        thread,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    /// When the first interpreter or VM was created, which `clock()` counts
+    /// from.
+    static START: OnceLock<Instant> = OnceLock::new();
+
+    pub fn start() {
+        START.get_or_init(Instant::now);
+    }
+
+    pub fn seconds_since_start() -> f64 {
+        START.get_or_init(Instant::now).elapsed().as_secs_f64()
+    }
+
+    pub fn millis_since_epoch() -> Result<f64, String> {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| "System clock is before the Unix epoch.".to_string())?;
+        Ok(since_epoch.as_millis() as f64)
+    }
+
+    pub fn sleep(ms: f64, deadline: Option<Instant>) -> Result<(), String> {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let duration = match (Duration::try_from_secs_f64(ms / 1000.0), remaining) {
+            (Ok(duration), Some(remaining)) => duration.min(remaining),
+            (Ok(duration), None) => duration,
+            (Err(_), Some(remaining)) => remaining,
+            (Err(_), None) => return Err("Sleep duration is too long.".to_string()),
+        };
+        thread::sleep(duration);
+        Ok(())
+    }
+}
+
+/// The clocks the time natives read in the browser, where `Instant` and
+/// `SystemTime` panic, so the time comes from JavaScript's `Date.now()`.
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+mod time {
+    use std::{sync::OnceLock, time::Instant};
+
+    use crate::wasm::date_now;
+
+    static START: OnceLock<f64> = OnceLock::new();
+
+    pub fn start() {
+        START.get_or_init(date_now);
+    }
+
+    pub fn seconds_since_start() -> f64 {
+        (date_now() - *START.get_or_init(date_now)) / 1000.0
+    }
+
+    pub fn millis_since_epoch() -> Result<f64, String> {
+        Ok(date_now())
+    }
+
+    /// The page can't be blocked, so there is no sleeping.
+    pub fn sleep(_ms: f64, _deadline: Option<Instant>) -> Result<(), String> {
+        Err("Sleep isn't supported in the browser.".to_string())
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, LoxError> {
+        let result = (self.function)(&arguments).map_err(|fault| interpreter.call_fault(fault))?;
+        interpreter.check_deadline()?;
+        Ok(result)
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        interpreter::InterpreterOptions,
+        parser::parse_source,
+        testing::{run_tree, run_vm},
+        vm::{Vm, VmOptions},
+        Lox,
+    };
+
+    #[test]
+    fn sleep_stops_at_the_timeout() {
+        let source = "sleep(1e12); print \"woke\";";
+        let timeout = Some(Duration::from_millis(50));
+        let start = Instant::now();
+        let tree = run_tree(
+            source,
+            InterpreterOptions {
+                timeout,
+                ..InterpreterOptions::sandboxed()
+            },
+        );
+        let vm = run_vm(
+            source,
+            VmOptions {
+                timeout,
+                ..VmOptions::sandboxed()
+            },
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        for outcome in [tree, vm] {
+            assert_eq!(outcome.output, "");
+            assert_eq!(outcome.error, Some("R0013"));
+        }
+    }
+
+    #[test]
+    fn typed_natives_convert_their_arguments() {
+        let repeat = |text: String, times: i64| Ok(text.repeat(times.max(0) as usize));
+        let mut lox = Lox::new();
+        lox.register_typed_native("repeat", repeat);
+        let value = lox.run("repeat(\"ab\", 3);").unwrap();
+        assert_eq!(value.to_lox_string(), "ababab");
+
+        let error = lox.run("repeat(\"ab\", \"3\");").unwrap_err();
+        assert_eq!(error.code(), "R0016");
+        assert_eq!(error.message(), "Argument must be an integer.");
+        let error = lox.run("repeat(\"ab\");").unwrap_err();
+        assert_eq!(error.message(), "Expected 2 arguments but got 1.");
+
+        let mut vm = Vm::new();
+        vm.register_typed_native("repeat", repeat);
+        let ast = parse_source("repeat(1, 2);".to_string(), false).unwrap();
+        let error = vm.interpret(&ast).unwrap_err();
+        assert_eq!(error.code(), "R0016");
+        assert_eq!(error.message(), "Argument must be a string.");
+    }
+
+    #[test]
+    fn numbers_are_read_like_literals() {
+        let accepted = [
+            ("42", 42.0),
+            (" 2.5\n", 2.5),
+            ("-3", -3.0),
+            ("1_000", 1000.0),
+            ("0x10", 16.0),
+            ("0b101", 5.0),
+            ("1e3", 1000.0),
+        ];
+        for (text, number) in accepted {
+            assert_eq!(parse_number(text), Some(number), "{:?}", text);
+        }
+        let rejected = [
+            "", "inf", "-inf", "NaN", "infinity", "+1", ".5", "5.", "1_", "1__0", "0x", "1 2",
+            "--1",
+        ];
+        for text in rejected {
+            assert_eq!(parse_number(text), None, "{:?}", text);
+        }
+    }
+}
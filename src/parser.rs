@@ -0,0 +1,1786 @@
+use std::{
+    ops::Index,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    error::LoxError,
+    intern::{intern, Symbol},
+    lexer::{Literal, Scanner, Token, TokenType},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiteralValue {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Symbol),
+}
+
+/// Identifies a variable-referencing expression so the resolver can record its
+/// scope depth. Ids are unique across parses, so resolutions from separate
+/// REPL lines never collide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(usize);
+
+impl ExprId {
+    fn next() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Handle to an expression stored in an [`Ast`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprRef(u32);
+
+/// A parsed program. Expressions live in one arena and point at their
+/// operands by [`ExprRef`], which saves an allocation per node and keeps a
+/// tree's nodes next to each other in memory.
+#[derive(Debug, Default)]
+pub struct Ast {
+    exprs: Vec<Expr>,
+    /// How many links the [chain](Ast::chain) each expression ends has.
+    chain_lengths: Vec<u32>,
+    /// The line each expression starts on.
+    lines: Vec<u32>,
+    statements: Vec<Stmt>,
+}
+
+impl Ast {
+    /// The program's top-level statements.
+    pub fn statements(&self) -> &[Stmt] {
+        &self.statements
+    }
+
+    /// Splits the chain `expr` ends, like `a + b + c` or `a.b(c)[d]`, into
+    /// the expression it starts from, `a`, and its links, innermost first
+    /// and ending with `expr`. The parser builds chains with loops rather
+    /// than recursion, so they can be far longer than anything else nests,
+    /// and the passes over the tree walk them with this instead of
+    /// recursing down them. An expression that isn't a link is a chain of
+    /// its own, with none.
+    pub fn chain(&self, expr: ExprRef) -> (ExprRef, Vec<ExprRef>) {
+        let mut links = Vec::new();
+        let mut start = expr;
+        while let Some(continued) = self.continues(start) {
+            links.push(start);
+            start = continued;
+        }
+        links.reverse();
+        (start, links)
+    }
+
+    /// The expression the link `expr` of a [chain](Ast::chain) continues:
+    /// the left operand of a binary or logical operator, or the object or
+    /// callee of a property, index, slice, or call. `None` if `expr` isn't a
+    /// link.
+    pub fn continues(&self, expr: ExprRef) -> Option<ExprRef> {
+        self[expr].continues()
+    }
+
+    /// How many links the [chain](Ast::chain) `expr` ends has, without
+    /// walking it: none if `expr` isn't a link.
+    pub fn chain_length(&self, expr: ExprRef) -> usize {
+        self.chain_lengths[expr.0 as usize] as usize
+    }
+
+    /// The line `expr` starts on.
+    pub fn line(&self, expr: ExprRef) -> usize {
+        self.lines[expr.0 as usize] as usize
+    }
+
+    /// Adds `expr`, which the parser finished on `line`. That is the line
+    /// it starts on too if it has no token or operand to go by, as a
+    /// literal doesn't.
+    fn add(&mut self, expr: Expr, line: usize) -> ExprRef {
+        let index = u32::try_from(self.exprs.len()).expect("too many expressions");
+        let length = expr
+            .continues()
+            .map_or(0, |continued| self.chain_lengths[continued.0 as usize] + 1);
+        let line = match &expr {
+            Expr::Assign { name, .. } | Expr::Variable { name, .. } => name.line,
+            Expr::Super { keyword, .. } | Expr::This { keyword, .. } => keyword.line,
+            Expr::Unary { operator, .. } => operator.line,
+            Expr::List { bracket, .. } => bracket.line,
+            Expr::Map { brace, .. } => brace.line,
+            Expr::Lambda { declaration } => declaration.name.line,
+            Expr::Conditional {
+                condition: first, ..
+            }
+            | Expr::Grouping { expression: first }
+            | Expr::Set { object: first, .. }
+            | Expr::SetIndex { object: first, .. } => self.line(*first),
+            _ => expr
+                .continues()
+                .map_or(line, |continued| self.line(continued)),
+        };
+        self.chain_lengths.push(length);
+        self.lines.push(line as u32);
+        self.exprs.push(expr);
+        ExprRef(index)
+    }
+}
+
+impl Index<ExprRef> for Ast {
+    type Output = Expr;
+
+    fn index(&self, expr: ExprRef) -> &Expr {
+        &self.exprs[expr.0 as usize]
+    }
+}
+
+impl Expr {
+    /// See [`Ast::continues`].
+    fn continues(&self) -> Option<ExprRef> {
+        match self {
+            Expr::Binary { left, .. } | Expr::Logical { left, .. } => Some(*left),
+            Expr::Call { callee, .. } => Some(*callee),
+            Expr::Get { object, .. } | Expr::Index { object, .. } | Expr::Slice { object, .. } => {
+                Some(*object)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Assign {
+        id: ExprId,
+        name: Token,
+        value: ExprRef,
+    },
+    Binary {
+        left: ExprRef,
+        operator: Token,
+        right: ExprRef,
+    },
+    Call {
+        callee: ExprRef,
+        paren: Token,
+        arguments: Vec<ExprRef>,
+    },
+    Conditional {
+        condition: ExprRef,
+        then_branch: ExprRef,
+        else_branch: ExprRef,
+    },
+    Get {
+        object: ExprRef,
+        name: Token,
+    },
+    Grouping {
+        expression: ExprRef,
+    },
+    /// `object[index]`.
+    Index {
+        object: ExprRef,
+        bracket: Token,
+        index: ExprRef,
+    },
+    /// An anonymous function, named `lambda` after the fact.
+    Lambda {
+        declaration: Rc<FunctionDecl>,
+    },
+    /// A list literal, `[a, b, c]`.
+    List {
+        bracket: Token,
+        elements: Vec<ExprRef>,
+    },
+    Literal {
+        value: LiteralValue,
+    },
+    Logical {
+        left: ExprRef,
+        operator: Token,
+        right: ExprRef,
+    },
+    /// A map literal, `{key: value, ...}`, with each entry's key and value.
+    Map {
+        brace: Token,
+        entries: Vec<(ExprRef, ExprRef)>,
+    },
+    Set {
+        object: ExprRef,
+        name: Token,
+        /// For `object.name op= value`, the operator combining the field's
+        /// current value with `value`.
+        operator: Option<Token>,
+        value: ExprRef,
+    },
+    /// `object[index] = value`.
+    SetIndex {
+        object: ExprRef,
+        bracket: Token,
+        index: ExprRef,
+        /// For `object[index] op= value`, the operator combining the
+        /// element's current value with `value`.
+        operator: Option<Token>,
+        value: ExprRef,
+    },
+    /// `object[start:end]`, where either bound may be left out.
+    Slice {
+        object: ExprRef,
+        bracket: Token,
+        start: Option<ExprRef>,
+        end: Option<ExprRef>,
+    },
+    Super {
+        id: ExprId,
+        keyword: Token,
+        method: Token,
+    },
+    This {
+        id: ExprId,
+        keyword: Token,
+    },
+    Unary {
+        operator: Token,
+        right: ExprRef,
+    },
+    Variable {
+        id: ExprId,
+        name: Token,
+    },
+}
+
+#[derive(Debug)]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Block {
+        statements: Vec<Stmt>,
+    },
+    Break {
+        keyword: Token,
+    },
+    Class {
+        name: Token,
+        superclass: Option<ExprRef>,
+        methods: Vec<Rc<FunctionDecl>>,
+        /// Methods declared with `class`, called on the class itself.
+        class_methods: Vec<Rc<FunctionDecl>>,
+    },
+    Continue {
+        keyword: Token,
+    },
+    Expression {
+        expression: ExprRef,
+    },
+    Function(Rc<FunctionDecl>),
+    If {
+        condition: ExprRef,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    Print {
+        expression: ExprRef,
+    },
+    Return {
+        keyword: Token,
+        value: Option<ExprRef>,
+    },
+    Var {
+        name: Token,
+        initializer: Option<ExprRef>,
+    },
+    While {
+        condition: ExprRef,
+        body: Box<Stmt>,
+        /// A `for` loop's increment clause, run after the body and after a
+        /// `continue`.
+        increment: Option<ExprRef>,
+    },
+}
+
+impl Stmt {
+    /// The line the statement starts on, unless it's a block, which has
+    /// nothing of its own to go by.
+    pub fn line(&self, ast: &Ast) -> Option<usize> {
+        match self {
+            Stmt::Block { .. } => None,
+            Stmt::Break { keyword } | Stmt::Continue { keyword } | Stmt::Return { keyword, .. } => {
+                Some(keyword.line)
+            }
+            Stmt::Class { name, .. } | Stmt::Var { name, .. } => Some(name.line),
+            Stmt::Function(declaration) => Some(declaration.name.line),
+            Stmt::Expression { expression } | Stmt::Print { expression } => {
+                Some(ast.line(*expression))
+            }
+            Stmt::If { condition, .. } | Stmt::While { condition, .. } => {
+                Some(ast.line(*condition))
+            }
+        }
+    }
+}
+
+/// Upper bound on function parameters and call arguments.
+pub(crate) const MAX_ARGUMENTS: usize = 255;
+
+/// How deeply the parser may recurse into nested statements and
+/// expressions. Every pass over the tree recurses into it too, so this
+/// keeps programs like `((((...))))` thousands deep from overflowing the
+/// stack. Chains like `1 + 2 + ...` don't nest; see [`Ast::chain`].
+const MAX_NESTING: usize = 256;
+
+/// How many links a [chain](Ast::chain) may have for a pass to recurse
+/// down them, which is quicker than listing them first.
+pub const SHORT_CHAIN: usize = 16;
+
+fn too_deep(token: &Token) -> LoxError {
+    LoxError::parse(token, "P0014", "Too deeply nested.".to_string())
+}
+
+/// Scans and parses `source`, as a line typed at the REPL if `repl` is set.
+/// Tokens the scanner rejects don't stop the parse, so every lexical and
+/// syntax error in the source is returned, in the order they appear.
+pub fn parse_source(source: String, repl: bool) -> Result<Ast, Vec<LoxError>> {
+    let (tokens, mut errors) = Scanner::new(source).scan_partial();
+    let mut parser = Parser::new(tokens);
+    let parse_errors = match (repl, errors.is_empty()) {
+        (true, true) => return parser.parse_repl(),
+        (false, true) => return parser.parse(),
+        (_, false) => parser.parse_partial().1,
+    };
+
+    // Text the scanner couldn't make sense of leaves a hole the parser is
+    // likely to trip over, but that's the same mistake again.
+    let scan_errors = errors.len();
+    for error in parse_errors {
+        let echoes = errors[..scan_errors]
+            .iter()
+            .any(|scan_error| overlaps(scan_error, &error));
+        if !echoes {
+            errors.push(error);
+        }
+    }
+    errors.sort_by_key(|error| (error.line(), error.span().map(|span| span.start)));
+    Err(errors)
+}
+
+/// For a compound assignment `equals` like `+=` or `++`, the binary
+/// operator it applies, like `+`.
+fn compound_operator(equals: &Token) -> Option<Token> {
+    let token_type = match equals.token_type {
+        TokenType::Equal => return None,
+        TokenType::PlusEqual | TokenType::PlusPlus => TokenType::Plus,
+        TokenType::MinusEqual | TokenType::MinusMinus => TokenType::Minus,
+        TokenType::StarEqual => TokenType::Star,
+        TokenType::SlashEqual => TokenType::Slash,
+        _ => unreachable!("not an assignment operator"),
+    };
+    Some(Token {
+        token_type,
+        lexeme: intern(&equals.lexeme[..1]),
+        ..equals.clone()
+    })
+}
+
+/// Whether the parse error `error` is in the text the scan error
+/// `scan_error` covers, or after it on the same line.
+fn overlaps(scan_error: &LoxError, error: &LoxError) -> bool {
+    match (scan_error.span(), error.span()) {
+        (Some(scanned), Some(parsed)) => {
+            let same_line = scan_error.line() == error.line();
+            parsed.start >= scanned.start && (same_line || parsed.start <= scanned.end())
+        }
+        _ => scan_error.line() == error.line(),
+    }
+}
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+    errors: Vec<LoxError>,
+    ast: Ast,
+    /// How many statements and expressions the one being parsed is inside.
+    depth: usize,
+    /// Whether the statement being parsed is in a `switch` case, with no
+    /// loop or function between it and the `switch`.
+    in_switch: bool,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            current: 0,
+            errors: Vec::new(),
+            ast: Ast::default(),
+            depth: 0,
+            in_switch: false,
+        }
+    }
+
+    /// Parses the whole program, reporting every syntax error found.
+    pub fn parse(&mut self) -> Result<Ast, Vec<LoxError>> {
+        let (ast, errors) = self.parse_partial();
+        if errors.is_empty() {
+            Ok(ast)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses a line typed at the REPL. A lone expression without a trailing
+    /// `;` is stored in the global `_`, for the next line to build on, and
+    /// then printed, so its value is echoed back.
+    pub fn parse_repl(&mut self) -> Result<Ast, Vec<LoxError>> {
+        let (ast, errors) = self.parse_repl_partial();
+        if errors.is_empty() {
+            Ok(ast)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parses a line typed at the REPL as much as possible, as
+    /// [`parse_partial`](Parser::parse_partial) does a program.
+    pub fn parse_repl_partial(&mut self) -> (Ast, Vec<LoxError>) {
+        if let Ok(expression) = self.expression() {
+            if self.is_at_end() && self.errors.is_empty() {
+                let name = Token {
+                    token_type: TokenType::Identifier,
+                    lexeme: intern("_"),
+                    literal: None,
+                    ..self.previous().clone()
+                };
+                let result = self.add(Expr::Variable {
+                    id: ExprId::next(),
+                    name: name.clone(),
+                });
+                let mut ast = std::mem::take(&mut self.ast);
+                ast.statements = vec![
+                    Stmt::Var {
+                        name,
+                        initializer: Some(expression),
+                    },
+                    Stmt::Print { expression: result },
+                ];
+                return (ast, Vec::new());
+            }
+        }
+
+        // Not a bare expression, so start over and parse statements.
+        self.current = 0;
+        self.errors.clear();
+        self.ast = Ast::default();
+        self.parse_partial()
+    }
+
+    /// Parses as much of the program as possible. After a syntax error the
+    /// parser skips to the next statement boundary and carries on, so the
+    /// returned tree is missing only the statements that failed to parse.
+    pub fn parse_partial(&mut self) -> (Ast, Vec<LoxError>) {
+        let mut statements = Vec::new();
+        while !self.is_at_end() {
+            match self.declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    self.errors.push(error);
+                    self.synchronize();
+                }
+            }
+        }
+        let mut ast = std::mem::take(&mut self.ast);
+        ast.statements = statements;
+        (ast, std::mem::take(&mut self.errors))
+    }
+
+    fn add(&mut self, expr: Expr) -> ExprRef {
+        self.ast.add(expr, self.previous().line)
+    }
+
+    /// Runs `parse` one level of nesting deeper, failing instead if that
+    /// would be past [`MAX_NESTING`].
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, LoxError>,
+    ) -> Result<T, LoxError> {
+        if self.depth == MAX_NESTING {
+            return Err(too_deep(self.peek()));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Discards tokens until the likely start of the next statement.
+    fn synchronize(&mut self) {
+        self.advance();
+        while !self.is_at_end() {
+            if self.previous().token_type == TokenType::Semicolon {
+                return;
+            }
+            match self.peek().token_type {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::Do
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Switch => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    fn declaration(&mut self) -> Result<Stmt, LoxError> {
+        if self.match_token(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+        // `fun (` starts a lambda, which belongs to an expression statement.
+        if !self.check_next(&TokenType::LeftParen) && self.match_token(&[TokenType::Fun]) {
+            return Ok(Stmt::Function(self.function("function")?));
+        }
+        if self.match_token(&[TokenType::Var]) {
+            return self.var_declaration();
+        }
+        self.statement()
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, LoxError> {
+        let name = self
+            .consume(TokenType::Identifier, "Expect class name.")?
+            .clone();
+
+        let superclass = if self.match_token(&[TokenType::Less]) {
+            let name = self
+                .consume(TokenType::Identifier, "Expect superclass name.")?
+                .clone();
+            Some(self.add(Expr::Variable {
+                id: ExprId::next(),
+                name,
+            }))
+        } else {
+            None
+        };
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods = Vec::new();
+        let mut class_methods = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if self.match_token(&[TokenType::Class]) {
+                class_methods.push(self.function("method")?);
+            } else {
+                methods.push(self.function("method")?);
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            methods,
+            class_methods,
+        })
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Rc<FunctionDecl>, LoxError> {
+        let name = self
+            .consume(TokenType::Identifier, &format!("Expect {} name.", kind))?
+            .clone();
+        self.function_body(kind, name)
+    }
+
+    /// Parses the parameter list and body of a function called `name`.
+    fn function_body(&mut self, kind: &str, name: Token) -> Result<Rc<FunctionDecl>, LoxError> {
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if params.len() == MAX_ARGUMENTS {
+                    // Reported without unwinding: the parser isn't confused.
+                    self.errors.push(LoxError::parse(
+                        self.peek(),
+                        "P0004",
+                        format!("Can't have more than {} parameters.", MAX_ARGUMENTS),
+                    ));
+                }
+                params.push(
+                    self.consume(TokenType::Identifier, "Expect parameter name.")?
+                        .clone(),
+                );
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        let body = self.outside_switch(Self::block)?;
+
+        Ok(Rc::new(FunctionDecl { name, params, body }))
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, LoxError> {
+        let name = self
+            .consume(TokenType::Identifier, "Expect variable name.")?
+            .clone();
+
+        let initializer = if self.match_token(&[TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::Var { name, initializer })
+    }
+
+    fn statement(&mut self) -> Result<Stmt, LoxError> {
+        if self.match_token(&[TokenType::Break]) {
+            let keyword = self.previous().clone();
+            self.check_not_in_switch(&keyword);
+            self.consume(TokenType::Semicolon, "Expect ';' after 'break'.")?;
+            return Ok(Stmt::Break { keyword });
+        }
+        if self.match_token(&[TokenType::Continue]) {
+            let keyword = self.previous().clone();
+            self.check_not_in_switch(&keyword);
+            self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.")?;
+            return Ok(Stmt::Continue { keyword });
+        }
+        if self.match_token(&[TokenType::Do]) {
+            return self.do_while_statement();
+        }
+        if self.match_token(&[TokenType::For]) {
+            return self.for_statement();
+        }
+        if self.match_token(&[TokenType::If]) {
+            return self.if_statement();
+        }
+        if self.match_token(&[TokenType::Print]) {
+            return self.print_statement();
+        }
+        if self.match_token(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+        if self.match_token(&[TokenType::Switch]) {
+            return self.switch_statement();
+        }
+        if self.match_token(&[TokenType::While]) {
+            return self.while_statement();
+        }
+        if self.match_token(&[TokenType::LeftBrace]) {
+            return Ok(Stmt::Block {
+                statements: self.block()?,
+            });
+        }
+        self.expression_statement()
+    }
+
+    /// Desugars a `for` loop into a `while` loop wrapped in a block.
+    /// Reports a `break` or `continue` directly inside a `switch` case. Cases
+    /// never fall through, so it would otherwise leave the loop around the
+    /// `switch` rather than the `switch` itself.
+    fn check_not_in_switch(&mut self, keyword: &Token) {
+        if self.in_switch {
+            // Reported without unwinding: the parser isn't confused.
+            self.errors.push(LoxError::parse(
+                keyword,
+                "P0016",
+                format!("Can't use '{}' inside a switch case.", keyword.lexeme),
+            ));
+        }
+    }
+
+    /// Parses a loop or function body, where `break` and `continue` belong
+    /// to that body even if it is inside a `switch`.
+    fn outside_switch<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, LoxError>,
+    ) -> Result<T, LoxError> {
+        let enclosing = std::mem::replace(&mut self.in_switch, false);
+        let result = parse(self);
+        self.in_switch = enclosing;
+        result
+    }
+
+    fn for_statement(&mut self) -> Result<Stmt, LoxError> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        let initializer = if self.match_token(&[TokenType::Semicolon]) {
+            None
+        } else if self.match_token(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if !self.check(&TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if !self.check(&TokenType::RightParen) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = self.outside_switch(|parser| parser.nested(Self::statement))?;
+
+        let condition = match condition {
+            Some(condition) => condition,
+            None => self.add(Expr::Literal {
+                value: LiteralValue::Bool(true),
+            }),
+        };
+        let mut body = Stmt::While {
+            condition,
+            body: Box::new(body),
+            increment,
+        };
+
+        if let Some(initializer) = initializer {
+            body = Stmt::Block {
+                statements: vec![initializer, body],
+            };
+        }
+
+        Ok(body)
+    }
+
+    /// Desugars `do body while (condition);` into a `while` loop guarded by a
+    /// hidden first-iteration flag, as if it were
+    /// `for (var first = true; first or condition; first = false) body`.
+    /// That way `continue` still checks the condition before looping.
+    fn do_while_statement(&mut self) -> Result<Stmt, LoxError> {
+        let keyword = self.previous().clone();
+        let body = self.outside_switch(|parser| parser.nested(Self::statement))?;
+        self.consume(TokenType::While, "Expect 'while' after do-while body.")?;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        self.consume(TokenType::Semicolon, "Expect ';' after do-while loop.")?;
+
+        // `do` is a keyword, so no user variable can shadow this one.
+        let first = Token {
+            token_type: TokenType::Identifier,
+            ..keyword.clone()
+        };
+        let initializer = self.add(Expr::Literal {
+            value: LiteralValue::Bool(true),
+        });
+        let is_first = self.add(Expr::Variable {
+            id: ExprId::next(),
+            name: first.clone(),
+        });
+        let condition = self.add(Expr::Logical {
+            left: is_first,
+            operator: Token {
+                token_type: TokenType::Or,
+                lexeme: intern("or"),
+                ..keyword
+            },
+            right: condition,
+        });
+        let cleared = self.add(Expr::Literal {
+            value: LiteralValue::Bool(false),
+        });
+        let increment = self.add(Expr::Assign {
+            id: ExprId::next(),
+            name: first.clone(),
+            value: cleared,
+        });
+
+        Ok(Stmt::Block {
+            statements: vec![
+                Stmt::Var {
+                    name: first,
+                    initializer: Some(initializer),
+                },
+                Stmt::While {
+                    condition,
+                    body: Box::new(body),
+                    increment: Some(increment),
+                },
+            ],
+        })
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, LoxError> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = Box::new(self.nested(Self::statement)?);
+        let else_branch = if self.match_token(&[TokenType::Else]) {
+            Some(Box::new(self.nested(Self::statement)?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    /// Desugars a `switch` into a block that stores the subject in a hidden
+    /// local, followed by an `if`/`else` chain comparing it against each
+    /// case's values with `==`. Only the first matching case runs; there is
+    /// no fallthrough, so `break` and `continue` aren't allowed in a case.
+    fn switch_statement(&mut self) -> Result<Stmt, LoxError> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'switch'.")?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after switch subject.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before switch body.")?;
+
+        // `switch` is a keyword, so no user variable can shadow this one.
+        let hidden = Token {
+            token_type: TokenType::Identifier,
+            ..keyword.clone()
+        };
+
+        let mut cases = Vec::new();
+        while self.match_token(&[TokenType::Case]) {
+            let case = self.previous().clone();
+            let mut condition = None;
+            loop {
+                let value = self.assignment()?;
+                let subject = self.add(Expr::Variable {
+                    id: ExprId::next(),
+                    name: hidden.clone(),
+                });
+                let test = self.add(Expr::Binary {
+                    left: subject,
+                    operator: Token {
+                        token_type: TokenType::EqualEqual,
+                        lexeme: intern("=="),
+                        ..case.clone()
+                    },
+                    right: value,
+                });
+                condition = Some(match condition {
+                    Some(left) => self.add(Expr::Logical {
+                        left,
+                        operator: Token {
+                            token_type: TokenType::Or,
+                            lexeme: intern("or"),
+                            ..case.clone()
+                        },
+                        right: test,
+                    }),
+                    None => test,
+                });
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenType::Colon, "Expect ':' after case value.")?;
+            let condition = condition.expect("a case has at least one value");
+            cases.push((condition, self.case_body()?));
+        }
+
+        let mut otherwise = if self.match_token(&[TokenType::Default]) {
+            self.consume(TokenType::Colon, "Expect ':' after 'default'.")?;
+            Some(Box::new(self.case_body()?))
+        } else {
+            None
+        };
+        self.consume(TokenType::RightBrace, "Expect '}' after switch body.")?;
+
+        for (condition, body) in cases.into_iter().rev() {
+            otherwise = Some(Box::new(Stmt::If {
+                condition,
+                then_branch: Box::new(body),
+                else_branch: otherwise,
+            }));
+        }
+
+        let mut statements = vec![Stmt::Var {
+            name: hidden,
+            initializer: Some(subject),
+        }];
+        statements.extend(otherwise.map(|chain| *chain));
+        Ok(Stmt::Block { statements })
+    }
+
+    /// Parses the statements of one `case` or `default` clause as a block.
+    fn case_body(&mut self) -> Result<Stmt, LoxError> {
+        let mut statements = Vec::new();
+        while !self.check(&TokenType::Case)
+            && !self.check(&TokenType::Default)
+            && !self.check(&TokenType::RightBrace)
+            && !self.is_at_end()
+        {
+            let enclosing = std::mem::replace(&mut self.in_switch, true);
+            let statement = self.declaration();
+            self.in_switch = enclosing;
+            statements.push(statement?);
+        }
+        Ok(Stmt::Block { statements })
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, LoxError> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = Box::new(self.outside_switch(|parser| parser.nested(Self::statement))?);
+
+        Ok(Stmt::While {
+            condition,
+            body,
+            increment: None,
+        })
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, LoxError> {
+        let expression = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(Stmt::Print { expression })
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, LoxError> {
+        let keyword = self.previous().clone();
+        let value = if !self.check(&TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return { keyword, value })
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, LoxError> {
+        let expression = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        Ok(Stmt::Expression { expression })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, LoxError> {
+        self.nested(|parser| {
+            let mut statements = Vec::new();
+            while !parser.check(&TokenType::RightBrace) && !parser.is_at_end() {
+                statements.push(parser.declaration()?);
+            }
+            parser.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+            Ok(statements)
+        })
+    }
+
+    fn expression(&mut self) -> Result<ExprRef, LoxError> {
+        self.comma()
+    }
+
+    fn comma(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.assignment()?;
+
+        while self.match_token(&[TokenType::Comma]) {
+            let operator = self.previous().clone();
+            let right = self.assignment()?;
+            expr = self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn assignment(&mut self) -> Result<ExprRef, LoxError> {
+        let expr = self.conditional()?;
+
+        if self.match_token(&[
+            TokenType::Equal,
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+        ]) {
+            let equals = self.previous().clone();
+            let value = self.nested(Self::assignment)?;
+            return Ok(self.assign(expr, &equals, value));
+        }
+
+        Ok(expr)
+    }
+
+    /// Turns `target` into an assignment of `value`, or reports it and hands
+    /// it back unchanged if it isn't a variable, property, or element. For a
+    /// compound assignment like `+=`, a property or element keeps the
+    /// operator, so its object and index are evaluated once, to read and
+    /// write it. Reading a variable has no side effects, so `a += b` is just
+    /// `a = a + b`.
+    fn assign(&mut self, target: ExprRef, equals: &Token, value: ExprRef) -> ExprRef {
+        let operator = compound_operator(equals);
+        let assignment = match self.ast[target].clone() {
+            Expr::Variable { name, .. } => {
+                let value = match operator {
+                    Some(operator) => self.add(Expr::Binary {
+                        left: target,
+                        operator,
+                        right: value,
+                    }),
+                    None => value,
+                };
+                Expr::Assign {
+                    id: ExprId::next(),
+                    name,
+                    value,
+                }
+            }
+            Expr::Get { object, name } => Expr::Set {
+                object,
+                name,
+                operator,
+                value,
+            },
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                operator,
+                value,
+            },
+            _ => {
+                self.errors.push(LoxError::parse(
+                    equals,
+                    "P0002",
+                    "Invalid assignment target.".to_string(),
+                ));
+                return target;
+            }
+        };
+        self.add(assignment)
+    }
+
+    /// Desugars `++target` and `--target` into `target += 1` and
+    /// `target -= 1`.
+    fn increment(&mut self, target: ExprRef, operator: &Token) -> ExprRef {
+        let one = self.add(Expr::Literal {
+            value: LiteralValue::Number(1.0),
+        });
+        self.assign(target, operator, one)
+    }
+
+    fn conditional(&mut self) -> Result<ExprRef, LoxError> {
+        let condition = self.or()?;
+
+        if self.match_token(&[TokenType::Question]) {
+            let then_branch = self.nested(Self::expression)?;
+            self.consume(
+                TokenType::Colon,
+                "Expect ':' after then branch of conditional expression.",
+            )?;
+            // Recursing makes `a ? b : c ? d : e` group as `a ? b : (c ? d : e)`.
+            let else_branch = self.nested(Self::conditional)?;
+            return Ok(self.add(Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            }));
+        }
+
+        Ok(condition)
+    }
+
+    fn or(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.and()?;
+
+        while self.match_token(&[TokenType::Or]) {
+            let operator = self.previous().clone();
+            let right = self.and()?;
+            expr = self.add(Expr::Logical {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.equality()?;
+
+        while self.match_token(&[TokenType::And]) {
+            let operator = self.previous().clone();
+            let right = self.equality()?;
+            expr = self.add(Expr::Logical {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.comparison()?;
+
+        while self.match_token(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            let operator = self.previous().clone();
+            let right = self.comparison()?;
+            expr = self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.bit_or()?;
+
+        while self.match_token(&[
+            TokenType::Greater,
+            TokenType::GreaterEqual,
+            TokenType::Less,
+            TokenType::LessEqual,
+        ]) {
+            let operator = self.previous().clone();
+            let right = self.bit_or()?;
+            expr = self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    // The bitwise operators bind tighter than comparisons, so `flags & 1 == 1`
+    // tests the masked bit rather than masking a boolean as it would in C.
+    fn bit_or(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.bit_xor()?;
+
+        while self.match_token(&[TokenType::Pipe]) {
+            let operator = self.previous().clone();
+            let right = self.bit_xor()?;
+            expr = self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_xor(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.bit_and()?;
+
+        while self.match_token(&[TokenType::Caret]) {
+            let operator = self.previous().clone();
+            let right = self.bit_and()?;
+            expr = self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_and(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.shift()?;
+
+        while self.match_token(&[TokenType::Ampersand]) {
+            let operator = self.previous().clone();
+            let right = self.shift()?;
+            expr = self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn shift(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.term()?;
+
+        while self.match_token(&[TokenType::LessLess, TokenType::GreaterGreater]) {
+            let operator = self.previous().clone();
+            let right = self.term()?;
+            expr = self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.factor()?;
+
+        while self.match_token(&[TokenType::Minus, TokenType::Plus]) {
+            let operator = self.previous().clone();
+            let right = self.factor()?;
+            expr = self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.unary()?;
+
+        while self.match_token(&[TokenType::Slash, TokenType::Star, TokenType::Percent]) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            expr = self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<ExprRef, LoxError> {
+        self.nested(|parser| {
+            if parser.match_token(&[TokenType::Bang, TokenType::Minus, TokenType::Tilde]) {
+                let operator = parser.previous().clone();
+                let right = parser.unary()?;
+                return Ok(parser.add(Expr::Unary { operator, right }));
+            }
+
+            if parser.match_token(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+                let operator = parser.previous().clone();
+                let target = parser.unary()?;
+                return Ok(parser.increment(target, &operator));
+            }
+
+            parser.power()
+        })
+    }
+
+    /// `**` binds tighter than unary operators on its left, so `-2 ** 2` is
+    /// `-(2 ** 2)`, and recursing through `unary` for the right operand makes
+    /// it right-associative.
+    fn power(&mut self) -> Result<ExprRef, LoxError> {
+        let expr = self.postfix()?;
+
+        if self.match_token(&[TokenType::StarStar]) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            return Ok(self.add(Expr::Binary {
+                left: expr,
+                operator,
+                right,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    /// Desugars `target++` into `(target = target + 1) - 1`, which yields the
+    /// old value without needing a temporary, and `target--` likewise.
+    fn postfix(&mut self) -> Result<ExprRef, LoxError> {
+        let expr = self.call()?;
+
+        if self.match_token(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let operator = self.previous().clone();
+            let (token_type, lexeme) = match operator.token_type {
+                TokenType::PlusPlus => (TokenType::Minus, "-"),
+                _ => (TokenType::Plus, "+"),
+            };
+            let left = self.increment(expr, &operator);
+            let right = self.add(Expr::Literal {
+                value: LiteralValue::Number(1.0),
+            });
+            return Ok(self.add(Expr::Binary {
+                left,
+                operator: Token {
+                    token_type,
+                    lexeme: intern(lexeme),
+                    ..operator
+                },
+                right,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    fn call(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.match_token(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_token(&[TokenType::Dot]) {
+                let name = self
+                    .consume(TokenType::Identifier, "Expect property name after '.'.")?
+                    .clone();
+                expr = self.add(Expr::Get { object: expr, name });
+            } else if self.match_token(&[TokenType::LeftBracket]) {
+                expr = self.index(expr)?;
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// The rest of `object[index]` or `object[start:end]`, after the `[`.
+    fn index(&mut self, object: ExprRef) -> Result<ExprRef, LoxError> {
+        let start = if self.match_token(&[TokenType::Colon]) {
+            None
+        } else {
+            let index = self.expression()?;
+            if !self.match_token(&[TokenType::Colon]) {
+                let bracket = self
+                    .consume(TokenType::RightBracket, "Expect ']' after index.")?
+                    .clone();
+                return Ok(self.add(Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                }));
+            }
+            Some(index)
+        };
+        let end = if self.check(&TokenType::RightBracket) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        let bracket = self
+            .consume(TokenType::RightBracket, "Expect ']' after slice.")?
+            .clone();
+        Ok(self.add(Expr::Slice {
+            object,
+            bracket,
+            start,
+            end,
+        }))
+    }
+
+    fn finish_call(&mut self, callee: ExprRef) -> Result<ExprRef, LoxError> {
+        let mut arguments = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() == MAX_ARGUMENTS {
+                    self.errors.push(LoxError::parse(
+                        self.peek(),
+                        "P0004",
+                        format!("Can't have more than {} arguments.", MAX_ARGUMENTS),
+                    ));
+                }
+                // Skip the comma operator so commas keep separating arguments.
+                arguments.push(self.assignment()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self
+            .consume(TokenType::RightParen, "Expect ')' after arguments.")?
+            .clone();
+
+        Ok(self.add(Expr::Call {
+            callee,
+            paren,
+            arguments,
+        }))
+    }
+
+    /// Desugars an interpolated string into a chain of `+`. Each embedded
+    /// expression is wrapped in a unary operator keyed by its `${` token,
+    /// which converts the value to a string the same way `print` would.
+    fn interpolation(&mut self) -> Result<ExprRef, LoxError> {
+        let mut expr = self.string_segment(self.previous().clone());
+        loop {
+            let start = self.previous().clone();
+            let right = self.expression()?;
+            let embedded = self.add(Expr::Unary {
+                operator: start.clone(),
+                right,
+            });
+            expr = self.concatenate(expr, embedded, &start);
+
+            let closing = !self.match_token(&[TokenType::Interpolation]);
+            if closing {
+                self.consume(
+                    TokenType::String,
+                    "Expect '}' after interpolated expression.",
+                )?;
+            }
+
+            let segment = self.previous().clone();
+            if !matches!(&segment.literal, Some(Literal::String(s)) if s.is_empty()) {
+                let right = self.string_segment(segment.clone());
+                expr = self.concatenate(expr, right, &segment);
+            }
+            if closing {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn string_segment(&mut self, token: Token) -> ExprRef {
+        let value = match &token.literal {
+            Some(Literal::String(s)) => intern(s),
+            _ => intern(""),
+        };
+        self.add(Expr::Literal {
+            value: LiteralValue::String(value),
+        })
+    }
+
+    fn concatenate(&mut self, left: ExprRef, right: ExprRef, at: &Token) -> ExprRef {
+        self.add(Expr::Binary {
+            left,
+            operator: Token {
+                token_type: TokenType::Plus,
+                lexeme: intern("+"),
+                literal: None,
+                line: at.line,
+                span: at.span,
+                leading_trivia: Vec::new(),
+            },
+            right,
+        })
+    }
+
+    fn primary(&mut self) -> Result<ExprRef, LoxError> {
+        if self.match_token(&[TokenType::False]) {
+            return Ok(self.add(Expr::Literal {
+                value: LiteralValue::Bool(false),
+            }));
+        }
+        if self.match_token(&[TokenType::True]) {
+            return Ok(self.add(Expr::Literal {
+                value: LiteralValue::Bool(true),
+            }));
+        }
+        if self.match_token(&[TokenType::Nil]) {
+            return Ok(self.add(Expr::Literal {
+                value: LiteralValue::Nil,
+            }));
+        }
+
+        if self.match_token(&[TokenType::Number, TokenType::String]) {
+            let value = match &self.previous().literal {
+                Some(Literal::Number(n)) => LiteralValue::Number(*n),
+                Some(Literal::String(s)) => LiteralValue::String(intern(s)),
+                None => LiteralValue::Nil,
+            };
+            return Ok(self.add(Expr::Literal { value }));
+        }
+
+        if self.match_token(&[TokenType::Interpolation]) {
+            return self.interpolation();
+        }
+
+        if self.match_token(&[TokenType::Fun]) {
+            let name = Token {
+                lexeme: intern("lambda"),
+                ..self.previous().clone()
+            };
+            let declaration = self.function_body("lambda", name)?;
+            return Ok(self.add(Expr::Lambda { declaration }));
+        }
+
+        if self.match_token(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = self
+                .consume(TokenType::Identifier, "Expect superclass method name.")?
+                .clone();
+            return Ok(self.add(Expr::Super {
+                id: ExprId::next(),
+                keyword,
+                method,
+            }));
+        }
+
+        if self.match_token(&[TokenType::This]) {
+            let keyword = self.previous().clone();
+            return Ok(self.add(Expr::This {
+                id: ExprId::next(),
+                keyword,
+            }));
+        }
+
+        if self.match_token(&[TokenType::Identifier]) {
+            let name = self.previous().clone();
+            return Ok(self.add(Expr::Variable {
+                id: ExprId::next(),
+                name,
+            }));
+        }
+
+        if self.match_token(&[TokenType::LeftParen]) {
+            let expression = self.expression()?;
+            self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
+            return Ok(self.add(Expr::Grouping { expression }));
+        }
+
+        if self.match_token(&[TokenType::LeftBracket]) {
+            return self.list();
+        }
+
+        // A `{` starting a statement is a block, so this is only reached
+        // for one within an expression.
+        if self.match_token(&[TokenType::LeftBrace]) {
+            return self.map();
+        }
+
+        if let Some(expr) = self.missing_left_operand()? {
+            return Ok(expr);
+        }
+
+        Err(LoxError::parse(
+            self.peek(),
+            "P0001",
+            "Expect expression.".to_string(),
+        ))
+    }
+
+    /// The rest of a list literal after its `[`. A trailing comma is
+    /// allowed, so a list written one element per line can end with one.
+    fn list(&mut self) -> Result<ExprRef, LoxError> {
+        let bracket = self.previous().clone();
+        let mut elements = Vec::new();
+        while !self.check(&TokenType::RightBracket) {
+            if elements.len() == MAX_ARGUMENTS {
+                self.errors.push(LoxError::parse(
+                    self.peek(),
+                    "P0004",
+                    format!("Can't have more than {} elements.", MAX_ARGUMENTS),
+                ));
+            }
+            // Skip the comma operator so commas keep separating elements.
+            elements.push(self.assignment()?);
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+        Ok(self.add(Expr::List { bracket, elements }))
+    }
+
+    /// The rest of a map literal after its `{`. As in a list, a trailing
+    /// comma is allowed.
+    fn map(&mut self) -> Result<ExprRef, LoxError> {
+        let brace = self.previous().clone();
+        let mut entries = Vec::new();
+        while !self.check(&TokenType::RightBrace) {
+            if entries.len() == MAX_ARGUMENTS {
+                self.errors.push(LoxError::parse(
+                    self.peek(),
+                    "P0004",
+                    format!("Can't have more than {} entries.", MAX_ARGUMENTS),
+                ));
+            }
+            let key = self.assignment()?;
+            self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+            let value = self.assignment()?;
+            entries.push((key, value));
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+        Ok(self.add(Expr::Map { brace, entries }))
+    }
+
+    /// Error production for a binary operator with nothing on its left, as
+    /// in `+ 3`. The error is recorded and the right operand parsed at the
+    /// operator's precedence, so parsing carries on as if it were a unary.
+    fn missing_left_operand(&mut self) -> Result<Option<ExprRef>, LoxError> {
+        let right_operand: fn(&mut Self) -> Result<ExprRef, LoxError> = match self.peek().token_type
+        {
+            TokenType::Comma => Self::assignment,
+            TokenType::Or => Self::and,
+            TokenType::And => Self::equality,
+            TokenType::BangEqual | TokenType::EqualEqual => Self::comparison,
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => Self::bit_or,
+            TokenType::Pipe => Self::bit_xor,
+            TokenType::Caret => Self::bit_and,
+            TokenType::Ampersand => Self::shift,
+            TokenType::LessLess | TokenType::GreaterGreater => Self::term,
+            TokenType::Plus => Self::factor,
+            TokenType::Slash | TokenType::Star | TokenType::Percent | TokenType::StarStar => {
+                Self::unary
+            }
+            _ => return Ok(None),
+        };
+
+        let operator = self.advance().clone();
+        self.errors.push(LoxError::parse(
+            &operator,
+            "P0003",
+            "Binary operator without left-hand operand.".to_string(),
+        ));
+        right_operand(self).map(Some)
+    }
+
+    fn match_token(&mut self, types: &[TokenType]) -> bool {
+        for token_type in types {
+            if self.check(token_type) {
+                self.advance();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token, LoxError> {
+        if self.check(&token_type) {
+            return Ok(self.advance());
+        }
+        let code = match token_type {
+            TokenType::LeftParen => "P0006",
+            TokenType::RightParen => "P0007",
+            TokenType::LeftBrace => "P0008",
+            // An interpolation's closing `}` starts the string after it.
+            TokenType::RightBrace | TokenType::String => "P0009",
+            TokenType::Colon => "P0010",
+            TokenType::Dot => "P0011",
+            TokenType::Semicolon => "P0012",
+            TokenType::While => "P0013",
+            TokenType::RightBracket => "P0015",
+            // The name of a variable, class, or something else.
+            _ => "P0005",
+        };
+        Err(LoxError::parse(self.peek(), code, message.to_string()))
+    }
+
+    fn check(&self, token_type: &TokenType) -> bool {
+        if self.is_at_end() {
+            return false;
+        }
+        &self.peek().token_type == token_type
+    }
+
+    fn check_next(&self, token_type: &TokenType) -> bool {
+        self.tokens
+            .get(self.current + 1)
+            .is_some_and(|token| &token.token_type == token_type)
+    }
+
+    fn advance(&mut self) -> &Token {
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+        self.previous()
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.peek().token_type == TokenType::Eof
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current - 1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_printer::print_program;
+
+    fn parse(source: &str) -> Result<String, Vec<&'static str>> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        match Parser::new(tokens).parse() {
+            Ok(ast) => Ok(print_program(&ast)),
+            Err(errors) => Err(errors.iter().map(LoxError::code).collect()),
+        }
+    }
+
+    #[test]
+    fn compound_assignment_binds_looser_than_its_operand() {
+        assert_eq!(parse("x *= y + 1;").unwrap(), "(; (= x (* x (+ y 1))))\n");
+        assert_eq!(parse("x -= y or z;").unwrap(), "(; (= x (- x (or y z))))\n");
+    }
+
+    #[test]
+    fn chained_assignment_is_right_associative() {
+        assert_eq!(
+            parse("a = b += c * 2;").unwrap(),
+            "(; (= a (= b (+ b (* c 2)))))\n"
+        );
+        assert_eq!(
+            parse("a += b -= c;").unwrap(),
+            "(; (= a (+ a (= b (- b c)))))\n"
+        );
+        assert_eq!(parse("o.x /= y = 2;").unwrap(), "(; (set/ o x (= y 2)))\n");
+    }
+
+    #[test]
+    fn compound_assignment_needs_a_target() {
+        assert_eq!(parse("a + b += c;").unwrap_err(), ["P0002"]);
+        assert_eq!(parse("x += 1 += 2;").unwrap_err(), ["P0002"]);
+    }
+
+    #[test]
+    fn break_and_continue_are_rejected_in_a_switch_case() {
+        let in_loop = "while (true) { switch (1) { case 1: break; default: continue; } }";
+        assert_eq!(parse(in_loop).unwrap_err(), ["P0016", "P0016"]);
+        assert_eq!(
+            parse("switch (1) { case 1: break; }").unwrap_err(),
+            ["P0016"]
+        );
+    }
+
+    #[test]
+    fn loops_and_functions_in_a_switch_case_can_break() {
+        assert!(parse("switch (1) { case 1: while (true) break; }").is_ok());
+        assert!(parse("switch (1) { default: for (;;) { continue; } }").is_ok());
+        assert!(parse("switch (1) { case 1: fun f() { while (true) break; } }").is_ok());
+    }
+}
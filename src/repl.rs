@@ -0,0 +1,428 @@
+//! The interactive prompt started by `--repl`.
+
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    env, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use rslox::{
+    lexer::{Scanner, TokenType, KEYWORDS},
+    parser::Parser as LoxParser,
+};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, CompletionType, Config, Context, Editor, Helper,
+};
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+use crate::{print_ast, print_tokens, run_source, Args, Session};
+
+/// What error reports call the code typed at the prompt.
+const REPL: &str = "<repl>";
+
+const KEYWORD: &str = "\x1b[35m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[33m";
+const COMMENT: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+/// Set by the SIGINT handler, and checked by the session as it runs.
+static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+const HELP: &str = "\
+:help     Show this list
+:env      List the global variables and their values
+:ast      Print the syntax tree of the last input
+:tokens   Print the tokens of the last input
+:load F   Run the file F, keeping what it defines
+:save F   Write what has run without errors so far to the file F
+:reset    Forget everything defined so far
+:quit     Leave the REPL, as does Ctrl-D";
+
+/// What the REPL does after a meta-command.
+enum Flow {
+    Continue,
+    Quit,
+}
+
+pub fn run_prompt(args: &Args) {
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut editor: Editor<LoxHelper, DefaultHistory> = match Editor::with_config(config) {
+        Ok(editor) => editor,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            return;
+        }
+    };
+    let history = history_path();
+    if let Some(history) = &history {
+        // There is none yet the first time.
+        let _ = editor.load_history(history);
+    }
+
+    let color = args.color();
+    let interrupt = catch_interrupts();
+    let mut repl = Repl {
+        args,
+        session: Rc::new(RefCell::new(new_session(args, &interrupt))),
+        interrupt,
+        last_input: None,
+        entered: Vec::new(),
+    };
+    editor.set_helper(Some(LoxHelper {
+        session: repl.session.clone(),
+        color,
+    }));
+    // The lines of a statement still being typed.
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "> " } else { ".. " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C throws away what is being typed.
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            // Ctrl-D quits.
+            Err(ReadlineError::Eof) => break,
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                break;
+            }
+        };
+        if input.is_empty() && line.trim_start().starts_with(':') {
+            let _ = editor.add_history_entry(line.trim());
+            match repl.meta_command(line.trim()) {
+                Flow::Continue => continue,
+                Flow::Quit => break,
+            }
+        }
+        // A blank line runs what there is so far, to get out of waiting
+        // for the rest of a statement that was mistyped.
+        let give_up = line.trim().is_empty() && !input.is_empty();
+        input.push_str(&line);
+        input.push('\n');
+        if !give_up && is_incomplete(&input) {
+            continue;
+        }
+        let source = std::mem::take(&mut input);
+        let _ = editor.add_history_entry(source.trim_end());
+        repl.last_input = Some(source.clone());
+        repl.run(source, REPL, true);
+        println!();
+    }
+
+    if let Some(history) = &history {
+        let saved = history
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .map_err(ReadlineError::from)
+            .and_then(|()| editor.save_history(history));
+        if let Err(error) = saved {
+            eprintln!("Error saving history: {}", error);
+        }
+    }
+}
+
+fn new_session(args: &Args, interrupt: &Arc<AtomicBool>) -> Session {
+    let mut session = Session::new(args);
+    session.set_interrupt(interrupt.clone());
+    session
+}
+
+/// Makes Ctrl-C while a line runs stop it with an "Interrupted." error and
+/// go back to the prompt, instead of killing the REPL. While a line is
+/// being typed the line editor sees Ctrl-C as a key press instead. Only
+/// done on Unix; elsewhere the returned flag is never set.
+fn catch_interrupts() -> Arc<AtomicBool> {
+    let flag = INTERRUPT.get_or_init(Arc::default).clone();
+    #[cfg(unix)]
+    {
+        extern "C" fn on_sigint(_: libc::c_int) {
+            if let Some(flag) = INTERRUPT.get() {
+                flag.store(true, Ordering::Relaxed);
+            }
+        }
+        // SAFETY: the handler only does an atomic store.
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+    flag
+}
+
+/// Tab-completes keywords and global names, or after a `.` following a
+/// global, that value's properties, and colors the line being typed.
+struct LoxHelper {
+    session: Rc<RefCell<Session>>,
+    color: bool,
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = identifier_start(line, pos);
+        let session = self.session.borrow();
+        let mut names = match line[..start].strip_suffix('.') {
+            Some(before) => session.members(&before[identifier_start(before, before.len())..]),
+            None => KEYWORDS
+                .iter()
+                .map(|keyword| keyword.to_string())
+                .chain(session.globals().into_iter().map(|(name, _)| name))
+                .collect(),
+        };
+        names.retain(|name| name.starts_with(&line[start..pos]));
+        names.sort();
+        names.dedup();
+        Ok((start, names))
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if self.color {
+            Cow::Owned(highlight(line))
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        self.color
+    }
+}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+/// Colors the keywords, strings, numbers, and comments in `line`. Works a
+/// line at a time and, unlike the scanner, never fails, since what is being
+/// typed is usually unfinished.
+fn highlight(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let (len, color) = if rest.starts_with("//") {
+            (rest.len(), Some(COMMENT))
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            let len = body.find("*/").map_or(rest.len(), |end| end + 4);
+            (len, Some(COMMENT))
+        } else if c == '"' {
+            (string_len(rest), Some(STRING))
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            (len, Some(NUMBER))
+        } else if c == '_' || is_xid_start(c) {
+            let len = rest
+                .find(|c: char| !is_xid_continue(c))
+                .unwrap_or(rest.len());
+            let color = KEYWORDS.contains(&&rest[..len]).then_some(KEYWORD);
+            (len, color)
+        } else {
+            (c.len_utf8(), None)
+        };
+        match color {
+            Some(color) => {
+                out.push_str(color);
+                out.push_str(&rest[..len]);
+                out.push_str(RESET);
+            }
+            None => out.push_str(&rest[..len]),
+        }
+        rest = &rest[len..];
+    }
+    out
+}
+
+/// The length of the string literal `rest` starts with, up to its closing
+/// quote or the end of the line.
+fn string_len(rest: &str) -> usize {
+    let mut chars = rest.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return i + 1,
+            '\\' => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+/// Where the identifier that ends at byte `end` of `line` begins.
+fn identifier_start(line: &str, end: usize) -> usize {
+    line[..end]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_xid_continue(c))
+        .last()
+        .map_or(end, |(i, _)| i)
+}
+
+/// The state of a REPL session beyond its line editor.
+struct Repl<'a> {
+    args: &'a Args,
+    /// Shared with the [`LoxHelper`] so it can complete what is defined.
+    session: Rc<RefCell<Session>>,
+    interrupt: Arc<AtomicBool>,
+    last_input: Option<String>,
+    /// Every input that has run without errors, in order, for `:save`.
+    entered: Vec<String>,
+}
+
+impl Repl<'_> {
+    /// Runs `source`, printing the value of an expression statement if
+    /// `echo` is set, and reports any errors as being in the file `name`.
+    fn run(&mut self, source: String, name: &str, echo: bool) {
+        // A Ctrl-C that came after the last run stopped shouldn't stop this.
+        self.interrupt.store(false, Ordering::Relaxed);
+        let result = run_source(
+            &mut self.session.borrow_mut(),
+            source.clone(),
+            name,
+            echo,
+            self.args,
+        );
+        if result.is_ok() {
+            self.entered.push(as_script(source));
+        }
+    }
+
+    /// Runs one of the colon-prefixed commands listed in [`HELP`].
+    fn meta_command(&mut self, command: &str) -> Flow {
+        let (command, argument) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(command, argument)| {
+                (command, argument.trim())
+            });
+        match (command, argument) {
+            (":help", "") => println!("{}", HELP),
+            (":env", "") => {
+                for (name, value) in self.session.borrow().globals() {
+                    println!("{} = {}", name, value);
+                }
+            }
+            (":ast" | ":tokens", "") => match &self.last_input {
+                // Errors have been reported, and don't end the session.
+                Some(input) if command == ":ast" => {
+                    let _ = print_ast(input, REPL, true, self.args);
+                }
+                Some(input) => {
+                    let _ = print_tokens(input, REPL, self.args);
+                }
+                None => println!("Nothing has been entered yet."),
+            },
+            (":load", path) if !path.is_empty() => match fs::read_to_string(path) {
+                Ok(source) => self.run(source, path, false),
+                Err(error) => eprintln!("Error reading '{}': {}", path, error),
+            },
+            (":save", path) if !path.is_empty() => {
+                if let Err(error) = fs::write(path, self.entered.concat()) {
+                    eprintln!("Error writing '{}': {}", path, error);
+                }
+            }
+            (":reset", "") => {
+                *self.session.borrow_mut() = new_session(self.args, &self.interrupt);
+                self.entered.clear();
+            }
+            (":quit", "") => return Flow::Quit,
+            (":load" | ":save", _) => eprintln!("{} needs a file name.", command),
+            _ => eprintln!("Unknown command '{}'. Type :help for a list.", command),
+        }
+        Flow::Continue
+    }
+}
+
+/// `source` as it has to be written in a file: a bare expression, which the
+/// REPL takes without a `;`, is stored in `_` as it was at the prompt.
+fn as_script(source: String) -> String {
+    let Ok(tokens) = Scanner::new(source.clone()).scan_tokens() else {
+        return source;
+    };
+    if LoxParser::new(tokens.clone()).parse().is_ok() {
+        return source;
+    }
+    match tokens
+        .iter()
+        .rev()
+        .find(|token| token.kind() != TokenType::Eof)
+    {
+        Some(last) => {
+            let end = last.span().start + last.span().len;
+            format!("var _ = {};{}", &source[..end], &source[end..])
+        }
+        None => source,
+    }
+}
+
+/// Whether `source` stops partway through a statement, with a string, a
+/// comment, or brackets left open, or more tokens needed to parse it, so the
+/// REPL should read more lines before running it.
+fn is_incomplete(source: &str) -> bool {
+    let tokens = match Scanner::new(source.to_string()).scan_tokens() {
+        Ok(tokens) => tokens,
+        // A string, a string interpolation, or a block comment left open.
+        Err(errors) => {
+            return errors
+                .iter()
+                .any(|error| matches!(error.code(), "L0002" | "L0003" | "L0004"))
+        }
+    };
+    let depth: isize = tokens
+        .iter()
+        .map(|token| match token.kind() {
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => 1,
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => -1,
+            _ => 0,
+        })
+        .sum();
+    if depth > 0 {
+        return true;
+    }
+    match LoxParser::new(tokens).parse_repl() {
+        Ok(_) => false,
+        // Every error is about running out of input.
+        Err(errors) => errors.iter().all(|error| {
+            error
+                .token()
+                .is_some_and(|token| token.kind() == TokenType::Eof)
+        }),
+    }
+}
+
+/// Where the REPL keeps its history: `$XDG_DATA_HOME/rslox/history`, or
+/// `~/.local/share/rslox/history` if that isn't set.
+fn history_path() -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(data_home.join("rslox").join("history"))
+}
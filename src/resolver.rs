@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::{LoxError, Warning, WarningKind},
+    intern::{intern, Symbol},
+    lexer::{Token, KEYWORDS},
+    parser::{Ast, Expr, ExprId, ExprRef, FunctionDecl, Stmt, SHORT_CHAIN},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// What the resolver knows about a local variable.
+struct Local {
+    /// Whether its initializer has finished.
+    defined: bool,
+    /// Whether it's read anywhere.
+    used: bool,
+    /// Where a variable the program declared is declared. `this`,
+    /// `super`, and the parser's hidden variables have none.
+    name: Option<Token>,
+}
+
+/// Static pass run between parsing and interpretation. It records how many
+/// scopes away each local variable reference is, and rejects programs that
+/// misuse `return`, `this`, `super`, or local variable declarations. It
+/// also warns about code that is allowed but likely a mistake.
+pub struct Resolver {
+    scopes: Vec<HashMap<Symbol, Local>>,
+    locals: HashMap<ExprId, usize>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    /// How many loops enclose the current statement within its function.
+    loop_depth: usize,
+    /// Errors found so far. None of them stop the pass, so all of a
+    /// program's errors are reported together.
+    errors: Vec<LoxError>,
+    warnings: Vec<Warning>,
+    /// The link of a long [chain](Ast::chain) resolved last, which the next
+    /// link continues.
+    chained: Option<ExprRef>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: Vec::new(),
+            locals: HashMap::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loop_depth: 0,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            chained: None,
+        }
+    }
+
+    /// Resolves `ast`, returning the scope depth of every local variable
+    /// reference. References missing from the map are globals.
+    pub fn resolve(&mut self, ast: &Ast) -> Result<HashMap<ExprId, usize>, Vec<LoxError>> {
+        self.resolve_statements(ast, ast.statements());
+        self.warnings
+            .sort_by_key(|warning| (warning.token.line, warning.token.span.start));
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        Ok(std::mem::take(&mut self.locals))
+    }
+
+    /// The warnings about the program last resolved, in the order they
+    /// appear in it.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    fn error(&mut self, token: &Token, code: &'static str, message: &str) {
+        self.errors
+            .push(LoxError::resolve(token, code, message.to_string()));
+    }
+
+    fn warn(&mut self, kind: WarningKind, token: &Token, message: String) {
+        self.warnings.push(Warning {
+            kind,
+            token: token.clone(),
+            message,
+        });
+    }
+
+    fn resolve_statements(&mut self, ast: &Ast, statements: &[Stmt]) {
+        for (index, statement) in statements.iter().enumerate() {
+            self.resolve_stmt(ast, statement);
+            if let Stmt::Return { keyword, .. }
+            | Stmt::Break { keyword }
+            | Stmt::Continue { keyword } = statement
+            {
+                if index + 1 < statements.len() {
+                    let message = format!("Code after '{}' is never run.", keyword.lexeme);
+                    self.warn(WarningKind::UnreachableCode, keyword, message);
+                }
+            }
+        }
+    }
+
+    fn resolve_stmt(&mut self, ast: &Ast, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                self.resolve_statements(ast, statements);
+                self.end_scope();
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                class_methods,
+            } => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+                self.resolve_class(ast, name, *superclass, methods, class_methods);
+                self.current_class = enclosing_class;
+            }
+            Stmt::Break { keyword } | Stmt::Continue { keyword } => {
+                if self.loop_depth == 0 {
+                    self.error(
+                        keyword,
+                        "S0009",
+                        &format!("Can't use '{}' outside of a loop.", keyword.lexeme),
+                    );
+                }
+            }
+            Stmt::Expression { expression } => self.resolve_expr(ast, *expression),
+            Stmt::Function(declaration) => {
+                self.declare(&declaration.name);
+                self.define(&declaration.name);
+                self.resolve_function(ast, declaration, FunctionType::Function);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expr(ast, *condition);
+                self.resolve_stmt(ast, then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_stmt(ast, else_branch);
+                }
+            }
+            Stmt::Print { expression } => self.resolve_expr(ast, *expression),
+            Stmt::Return { keyword, value } => {
+                if self.current_function == FunctionType::None {
+                    self.error(keyword, "S0003", "Can't return from top-level code.");
+                }
+
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.error(
+                            keyword,
+                            "S0004",
+                            "Can't return a value from an initializer.",
+                        );
+                    }
+                    self.resolve_expr(ast, *value);
+                }
+            }
+            Stmt::Var { name, initializer } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expr(ast, *initializer);
+                }
+                self.define(name);
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                self.resolve_expr(ast, *condition);
+                self.loop_depth += 1;
+                self.resolve_stmt(ast, body);
+                self.loop_depth -= 1;
+                if let Some(increment) = increment {
+                    self.resolve_expr(ast, *increment);
+                }
+            }
+        }
+    }
+
+    fn resolve_class(
+        &mut self,
+        ast: &Ast,
+        name: &Token,
+        superclass: Option<ExprRef>,
+        methods: &[std::rc::Rc<FunctionDecl>],
+        class_methods: &[std::rc::Rc<FunctionDecl>],
+    ) {
+        self.declare(name);
+        self.define(name);
+
+        if let Some(superclass) = superclass {
+            if let Expr::Variable {
+                name: superclass_name,
+                ..
+            } = &ast[superclass]
+            {
+                if superclass_name.lexeme == name.lexeme {
+                    self.error(
+                        superclass_name,
+                        "S0008",
+                        "A class can't inherit from itself.",
+                    );
+                }
+            }
+
+            self.current_class = ClassType::Subclass;
+            self.resolve_expr(ast, superclass);
+
+            self.begin_scope();
+            self.scope_insert(intern("super"), true, None);
+        }
+
+        self.begin_scope();
+        self.scope_insert(intern("this"), true, None);
+
+        for method in methods {
+            let function_type = if method.name.lexeme == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(ast, method, function_type);
+        }
+        // In a class method `this` is the class itself, and `init` is just a
+        // name.
+        for method in class_methods {
+            self.resolve_function(ast, method, FunctionType::Method);
+        }
+
+        self.end_scope();
+        if superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    fn resolve_function(
+        &mut self,
+        ast: &Ast,
+        function: &FunctionDecl,
+        function_type: FunctionType,
+    ) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+        // A loop around a function body doesn't make `break` valid inside it.
+        let enclosing_loop_depth = std::mem::take(&mut self.loop_depth);
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+            // A function may have to take arguments it doesn't need, like a
+            // callback's.
+            self.mark_used(param);
+        }
+        self.resolve_statements(ast, &function.body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loop_depth;
+    }
+
+    /// Resolves a long [chain](Ast::chain), like `a + b + c + ...`, from the
+    /// innermost link out, each handing on to the next rather than
+    /// recursing down it.
+    fn resolve_chain(&mut self, ast: &Ast, expr: ExprRef) {
+        let (_, links) = ast.chain(expr);
+        for &link in &links[..links.len() - 1] {
+            self.resolve_expr(ast, link);
+            self.chained = Some(link);
+        }
+        self.resolve_expr(ast, expr);
+    }
+
+    fn resolve_expr(&mut self, ast: &Ast, expr: ExprRef) {
+        if self.chained.take_if(|link| *link == expr).is_some() {
+            return;
+        }
+        // While a link is being handed on, `expr` is the next link of a
+        // chain already being resolved.
+        if self.chained.is_none() && ast.chain_length(expr) > SHORT_CHAIN {
+            return self.resolve_chain(ast, expr);
+        }
+        match &ast[expr] {
+            Expr::Assign { id, name, value } => {
+                self.resolve_expr(ast, *value);
+                self.resolve_local(*id, name);
+            }
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.resolve_expr(ast, *left);
+                self.resolve_expr(ast, *right);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.resolve_expr(ast, *callee);
+                for argument in arguments {
+                    self.resolve_expr(ast, *argument);
+                }
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expr(ast, *condition);
+                self.resolve_expr(ast, *then_branch);
+                self.resolve_expr(ast, *else_branch);
+            }
+            Expr::Get { object, .. } => self.resolve_expr(ast, *object),
+            Expr::Grouping { expression } => self.resolve_expr(ast, *expression),
+            Expr::Index { object, index, .. } => {
+                self.resolve_expr(ast, *object);
+                self.resolve_expr(ast, *index);
+            }
+            Expr::Lambda { declaration } => {
+                self.resolve_function(ast, declaration, FunctionType::Function)
+            }
+            Expr::List { elements, .. } => {
+                for element in elements {
+                    self.resolve_expr(ast, *element);
+                }
+            }
+            Expr::Literal { .. } => {}
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.resolve_expr(ast, *key);
+                    self.resolve_expr(ast, *value);
+                }
+            }
+            Expr::Set { object, value, .. } => {
+                self.resolve_expr(ast, *value);
+                self.resolve_expr(ast, *object);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.resolve_expr(ast, *object);
+                self.resolve_expr(ast, *index);
+                self.resolve_expr(ast, *value);
+            }
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                self.resolve_expr(ast, *object);
+                for bound in [start, end].into_iter().flatten() {
+                    self.resolve_expr(ast, *bound);
+                }
+            }
+            Expr::Super { id, keyword, .. } => {
+                match self.current_class {
+                    ClassType::None => {
+                        self.error(keyword, "S0006", "Can't use 'super' outside of a class.")
+                    }
+                    ClassType::Class => self.error(
+                        keyword,
+                        "S0007",
+                        "Can't use 'super' in a class with no superclass.",
+                    ),
+                    ClassType::Subclass => {}
+                }
+                self.resolve_local(*id, keyword);
+            }
+            Expr::This { id, keyword } => {
+                if self.current_class == ClassType::None {
+                    self.error(keyword, "S0005", "Can't use 'this' outside of a class.");
+                }
+                self.resolve_local(*id, keyword);
+            }
+            Expr::Unary { right, .. } => self.resolve_expr(ast, *right),
+            Expr::Variable { id, name } => {
+                if let Some(scope) = self.scopes.last() {
+                    if scope.get(&name.lexeme).is_some_and(|local| !local.defined) {
+                        self.error(
+                            name,
+                            "S0002",
+                            "Can't read local variable in its own initializer.",
+                        );
+                    }
+                }
+                self.resolve_local(*id, name);
+                self.mark_used(name);
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for local in scope.into_values() {
+            if let Some(name) = local.name.filter(|_| !local.used) {
+                let message = format!("Local variable '{}' is never read.", name.lexeme);
+                self.warn(WarningKind::UnusedVariable, &name, message);
+            }
+        }
+    }
+
+    fn scope_insert(&mut self, name: Symbol, defined: bool, token: Option<&Token>) {
+        // The parser names the variables it adds after keywords, so they
+        // can't clash with the program's own.
+        let token = token.filter(|token| !KEYWORDS.contains(&&*token.lexeme));
+        if let Some(scope) = self.scopes.last_mut() {
+            let local = scope.entry(name).or_insert(Local {
+                defined,
+                // Names starting with `_` are for values that go unused on
+                // purpose.
+                used: token.is_none_or(|token| token.lexeme.starts_with('_')),
+                name: token.cloned(),
+            });
+            local.defined = defined;
+        }
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let Some((scope, enclosing)) = self.scopes.split_last() else {
+            return;
+        };
+        if scope.contains_key(&name.lexeme) {
+            self.error(
+                name,
+                "S0001",
+                "Already a variable with this name in this scope.",
+            );
+        } else if !name.lexeme.starts_with('_')
+            && enclosing.iter().any(|scope| {
+                scope
+                    .get(&name.lexeme)
+                    .is_some_and(|local| local.name.is_some())
+            })
+        {
+            let message = format!(
+                "'{}' shadows a variable in an enclosing scope.",
+                name.lexeme
+            );
+            self.warn(WarningKind::Shadowing, name, message);
+        }
+        self.scope_insert(name.lexeme.clone(), false, Some(name));
+    }
+
+    fn define(&mut self, name: &Token) {
+        self.scope_insert(name.lexeme.clone(), true, Some(name));
+    }
+
+    /// Records that the variable `name` refers to is read.
+    fn mark_used(&mut self, name: &Token) {
+        let local = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&name.lexeme));
+        if let Some(local) = local {
+            local.used = true;
+        }
+    }
+
+    fn resolve_local(&mut self, id: ExprId, name: &Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.contains_key(&name.lexeme) {
+                self.locals.insert(id, depth);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! The methods strings have, like `"lox".toUpperCase()`. Strings are
+//! immutable, so each method returns a new value rather than changing the
+//! string. Positions count characters, not bytes. As with
+//! [lists](crate::list), the methods are written once here, over whichever
+//! arguments the backend passes.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    error::{Fault, LoxError},
+    intern::{intern, Symbol},
+    lexer::Token,
+    native::NativeFunction,
+    suggest,
+    value::{format_number, Value},
+};
+
+/// A method every string has, called as in `name.trim()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringMethod {
+    /// `charAt(index)`: the character at `index`, as a string.
+    CharAt,
+    /// `indexOf(text)`: where `text` first appears, or -1 if it doesn't.
+    IndexOf,
+    /// `length()`: how many characters there are.
+    Length,
+    /// `replace(from, to)`: the string with every `from` replaced by `to`.
+    Replace,
+    /// `split(separator)`: a list of the parts between each `separator`,
+    /// or of the characters if it is empty.
+    Split,
+    /// `substring(start, end)`: the characters from `start` up to but not
+    /// including `end`.
+    Substring,
+    /// `toLowerCase()`: the string in lower case.
+    ToLowerCase,
+    /// `toUpperCase()`: the string in upper case.
+    ToUpperCase,
+    /// `trim()`: the string without whitespace at either end.
+    Trim,
+}
+
+/// An argument to a string method, in terms both backends can give.
+pub enum Argument<'a> {
+    Number(f64),
+    String(&'a str),
+    Other,
+}
+
+/// What a string method returns.
+pub enum Returned {
+    Number(f64),
+    String(String),
+    List(Vec<String>),
+}
+
+impl StringMethod {
+    /// Sorted by name.
+    pub const ALL: [StringMethod; 9] = [
+        StringMethod::CharAt,
+        StringMethod::IndexOf,
+        StringMethod::Length,
+        StringMethod::Replace,
+        StringMethod::Split,
+        StringMethod::Substring,
+        StringMethod::ToLowerCase,
+        StringMethod::ToUpperCase,
+        StringMethod::Trim,
+    ];
+
+    pub fn named(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|method| method.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StringMethod::CharAt => "charAt",
+            StringMethod::IndexOf => "indexOf",
+            StringMethod::Length => "length",
+            StringMethod::Replace => "replace",
+            StringMethod::Split => "split",
+            StringMethod::Substring => "substring",
+            StringMethod::ToLowerCase => "toLowerCase",
+            StringMethod::ToUpperCase => "toUpperCase",
+            StringMethod::Trim => "trim",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            StringMethod::Length
+            | StringMethod::ToLowerCase
+            | StringMethod::ToUpperCase
+            | StringMethod::Trim => 0,
+            StringMethod::CharAt | StringMethod::IndexOf | StringMethod::Split => 1,
+            StringMethod::Replace | StringMethod::Substring => 2,
+        }
+    }
+
+    /// Runs the method on `s`. The caller has checked the number of
+    /// `arguments` against its [`arity`](StringMethod::arity).
+    pub fn call(self, s: &str, arguments: &[Argument]) -> Result<Returned, Fault> {
+        match self {
+            StringMethod::CharAt => Ok(Returned::String(
+                char_at(s, number(&arguments[0]))?.to_string(),
+            )),
+            StringMethod::IndexOf => {
+                let found = s.find(text(&arguments[0])?);
+                let index = found.map_or(-1.0, |byte| s[..byte].chars().count() as f64);
+                Ok(Returned::Number(index))
+            }
+            StringMethod::Length => Ok(Returned::Number(s.chars().count() as f64)),
+            StringMethod::Replace => {
+                let from = text(&arguments[0])?;
+                let to = text(&arguments[1])?;
+                if from.is_empty() {
+                    return Err(Fault::new("R0016", "Can't replace an empty string."));
+                }
+                Ok(Returned::String(s.replace(from, to)))
+            }
+            StringMethod::Split => {
+                let parts: Vec<String> = match text(&arguments[0])? {
+                    "" => s.chars().map(String::from).collect(),
+                    separator => s.split(separator).map(String::from).collect(),
+                };
+                Ok(Returned::List(parts))
+            }
+            StringMethod::Substring => {
+                let (start, end) = (number(&arguments[0]), number(&arguments[1]));
+                Ok(Returned::String(substring(s, start, end)?.to_string()))
+            }
+            StringMethod::ToLowerCase => Ok(Returned::String(s.to_lowercase())),
+            StringMethod::ToUpperCase => Ok(Returned::String(s.to_uppercase())),
+            StringMethod::Trim => Ok(Returned::String(s.trim().to_string())),
+        }
+    }
+}
+
+fn number(argument: &Argument) -> Option<f64> {
+    match argument {
+        Argument::Number(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn text<'a>(argument: &Argument<'a>) -> Result<&'a str, Fault> {
+    match argument {
+        Argument::String(s) => Ok(s),
+        _ => Err(Fault::new("R0016", "Argument must be a string.")),
+    }
+}
+
+/// `s[index]`: the character at `index`, given the index if it is a
+/// number at all.
+pub fn char_at(s: &str, index: Option<f64>) -> Result<&str, Fault> {
+    let len = s.chars().count();
+    match position(index, len)? {
+        index if index < len => Ok(slice(s, index, index + 1)),
+        index => Err(out_of_bounds(index as f64, len)),
+    }
+}
+
+/// `s[start:end]`: the characters from `start` up to but not including
+/// `end`, given each if it is a number at all.
+pub fn substring(s: &str, start: Option<f64>, end: Option<f64>) -> Result<&str, Fault> {
+    let len = s.chars().count();
+    let start = position(start, len)?;
+    let end = position(end, len)?;
+    if start > end {
+        return Err(Fault::new("R0020", "Substring start is after its end."));
+    }
+    Ok(slice(s, start, end))
+}
+
+/// The characters of `s` from `start` up to `end`, which are in range.
+fn slice(s: &str, start: usize, end: usize) -> &str {
+    let byte = |index: usize| s.char_indices().nth(index).map_or(s.len(), |(i, _)| i);
+    &s[byte(start)..byte(end)]
+}
+
+/// A position in a string of length `len`, which may be `len` itself, for
+/// the position after the last character.
+fn position(index: Option<f64>, len: usize) -> Result<usize, Fault> {
+    let Some(index) = index.filter(|index| index.fract() == 0.0) else {
+        return Err(Fault::new("R0020", "String index must be an integer."));
+    };
+    if index < 0.0 || index > len as f64 {
+        return Err(out_of_bounds(index, len));
+    }
+    Ok(index as usize)
+}
+
+/// The error for assigning to `s[index]`.
+pub fn immutable() -> Fault {
+    Fault::new("R0020", "Can't assign to a character of a string.")
+}
+
+fn out_of_bounds(index: f64, len: usize) -> Fault {
+    let message = format!(
+        "String index {} is out of bounds for a string of length {}.",
+        format_number(index),
+        len
+    );
+    Fault::new("R0020", message)
+}
+
+/// Reads method `name` of `s` in the tree-walking interpreter, as a native
+/// bound to the string.
+pub fn get(s: &Symbol, name: &Token) -> Result<Value, LoxError> {
+    let Some(method) = StringMethod::named(&name.lexeme) else {
+        let names = StringMethod::ALL.map(StringMethod::name);
+        return Err(LoxError::runtime_at(
+            name,
+            "R0005",
+            suggest::undefined("property", &name.lexeme, names),
+        ));
+    };
+    let s = s.clone();
+    let native = NativeFunction::with_faults(method.name(), method.arity(), move |arguments| {
+        let arguments: Vec<Argument> = arguments
+            .iter()
+            .map(|argument| match argument {
+                Value::Number(n) => Argument::Number(*n),
+                Value::String(s) => Argument::String(s),
+                _ => Argument::Other,
+            })
+            .collect();
+        Ok(match method.call(&s, &arguments)? {
+            Returned::Number(n) => Value::Number(n),
+            Returned::String(s) => Value::String(intern(&s)),
+            Returned::List(parts) => {
+                let parts = parts.iter().map(|part| Value::String(intern(part)));
+                Value::List(Rc::new(RefCell::new(parts.collect())))
+            }
+        })
+    });
+    Ok(Value::Native(Rc::new(native)))
+}
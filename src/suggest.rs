@@ -0,0 +1,39 @@
+//! "Did you mean" hints for names that aren't defined, most often typos of
+//! ones that are.
+
+use crate::lexer::KEYWORDS;
+
+/// The message for an undefined `kind` of name, like a variable, that
+/// suggests the closest of `candidates` if one is close enough.
+pub fn undefined<'a>(
+    kind: &str,
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> String {
+    match closest(name, candidates) {
+        Some(suggestion) => format!(
+            "Undefined {} '{}'. Did you mean '{}'?",
+            kind, name, suggestion
+        ),
+        None => format!("Undefined {} '{}'.", kind, name),
+    }
+}
+
+/// The candidate `name` is most likely a misspelling of: the one fewest
+/// edits away, counting a swap of neighbouring characters as one. Short
+/// names allow one edit and longer ones one per three characters, so
+/// unrelated names aren't suggested; a single letter allows none.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let length = name.chars().count();
+    let allowed = (length / 3).max(1).min(length.saturating_sub(1));
+    candidates
+        .into_iter()
+        // The parser names the variables it adds after keywords.
+        .filter(|candidate| *candidate != name && !KEYWORDS.contains(candidate))
+        .map(|candidate| (strsim::damerau_levenshtein(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= allowed)
+        // Ties go to the first name alphabetically, so the suggestion
+        // doesn't depend on hash map order.
+        .min()
+        .map(|(_, candidate)| candidate)
+}
@@ -0,0 +1,187 @@
+//! `rslox test`: runs Lox scripts annotated the way the Crafting Interpreters
+//! test suite is, and checks what they print and the errors they stop with.
+//!
+//! - `// expect: TEXT` expects a line of output.
+//! - `// expect runtime error: MESSAGE` expects the script to stop with
+//!   that runtime error.
+//! - `// Error at 'x': MESSAGE`, or `// [line N] Error ...` for an error
+//!   reported on another line, expects a compile error.
+
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use rslox::error::LoxError;
+
+use crate::{read_source, Args, Failure, Session};
+
+/// Runs the test script `path`, or every `.lox` file under the directory
+/// `path`, printing the ones that fail and a count of both.
+pub fn run_tests(path: &str, args: &Args) -> Result<(), Failure> {
+    let mut files = Vec::new();
+    collect_scripts(Path::new(path), &mut files).map_err(|error| {
+        eprintln!("Error reading '{}': {}", path, error);
+        Failure::Read
+    })?;
+    files.sort();
+
+    let mut failed = 0;
+    for file in &files {
+        let problems = run_test(file, args);
+        if !problems.is_empty() {
+            failed += 1;
+            println!("FAIL {}", file.display());
+            for problem in problems {
+                println!("    {}", problem);
+            }
+        }
+    }
+    println!("{} passed, {} failed.", files.len() - failed, failed);
+    if failed > 0 {
+        return Err(Failure::Tests);
+    }
+    Ok(())
+}
+
+fn collect_scripts(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_scripts(&path, files)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// What a test script says it should do.
+#[derive(Default)]
+struct Expectations {
+    output: Vec<String>,
+    compile_errors: Vec<String>,
+    runtime_error: Option<String>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+        for (index, line) in source.lines().enumerate() {
+            let Some((_, comment)) = line.split_once("// ") else {
+                continue;
+            };
+            if let Some(output) = comment.strip_prefix("expect: ") {
+                expectations.output.push(output.to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expectations.runtime_error = Some(message.to_string());
+            } else if comment.starts_with("Error") {
+                let error = format!("[line {}] {}", index + 1, comment);
+                expectations.compile_errors.push(error);
+            } else if let Some(error) = comment.strip_prefix("[java line ") {
+                expectations.compile_errors.push(format!("[line {}", error));
+            } else if comment.starts_with("[line ") {
+                expectations.compile_errors.push(comment.to_string());
+            }
+        }
+        expectations
+    }
+}
+
+/// Runs the test script `path` and describes each way it did something
+/// other than what it expects.
+fn run_test(path: &Path, args: &Args) -> Vec<String> {
+    // Why it can't be read has been printed already.
+    let Ok(source) = read_source(&path.to_string_lossy()) else {
+        return vec!["Couldn't be read.".to_string()];
+    };
+    let expected = Expectations::parse(&source);
+
+    let output = SharedBuffer::default();
+    let mut session = Session::new(args);
+    session.set_output(Box::new(output.clone()), Box::new(io::sink()));
+    // A test that reads input gets none, rather than waiting on the
+    // terminal.
+    session.set_input(Box::new(io::empty()));
+    let result = session.run(source, false);
+
+    let mut problems = Vec::new();
+    let (compile_errors, runtime_error) = match &result {
+        Ok(()) => (Vec::new(), None),
+        Err(Failure::Runtime(error)) => (Vec::new(), Some(error.message().to_string())),
+        Err(failure) => {
+            let errors = failure.errors().iter().map(LoxError::to_string);
+            (errors.collect(), None)
+        }
+    };
+    if compile_errors != expected.compile_errors {
+        for error in &expected.compile_errors {
+            if !compile_errors.contains(error) {
+                problems.push(format!("Expected compile error '{}'.", error));
+            }
+        }
+        for error in &compile_errors {
+            if !expected.compile_errors.contains(error) {
+                problems.push(format!("Unexpected compile error '{}'.", error));
+            }
+        }
+    }
+    if runtime_error != expected.runtime_error {
+        problems.push(match (&expected.runtime_error, &runtime_error) {
+            (Some(expected), Some(got)) => {
+                format!("Expected runtime error '{}', got '{}'.", expected, got)
+            }
+            (Some(expected), None) => format!("Expected runtime error '{}'.", expected),
+            (None, got) => format!(
+                "Unexpected runtime error '{}'.",
+                got.as_deref().unwrap_or("")
+            ),
+        });
+    }
+
+    let output = output.0.borrow();
+    let lines: Vec<&str> = std::str::from_utf8(&output).unwrap_or("").lines().collect();
+    for (index, expected) in expected.output.iter().enumerate() {
+        match lines.get(index) {
+            Some(line) if line == expected => {}
+            Some(line) => problems.push(format!(
+                "Expected output '{}' on line {}, got '{}'.",
+                expected,
+                index + 1,
+                line
+            )),
+            None => problems.push(format!(
+                "Missing output '{}' on line {}.",
+                expected,
+                index + 1
+            )),
+        }
+    }
+    for line in lines.iter().skip(expected.output.len()) {
+        problems.push(format!("Unexpected output '{}'.", line));
+    }
+    problems
+}
+
+/// A byte buffer the session writes its output into, readable afterwards
+/// through another handle.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! Runs scripts on both backends, for tests that check what a script does
+//! and that the backends agree on it.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::{
+    error::LoxError,
+    interpreter::{Interpreter, InterpreterOptions},
+    parser::{parse_source, Ast, ExprId},
+    resolver::Resolver,
+    vm::{Vm, VmOptions},
+};
+
+/// What a script printed, and the code and message of the error it
+/// stopped with, if any.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub output: String,
+    pub error: Option<&'static str>,
+    pub message: Option<String>,
+}
+
+/// Runs `source` on both backends with their default options, checking
+/// that they agree.
+pub fn run(source: &str) -> Outcome {
+    let tree = run_tree(source, InterpreterOptions::default());
+    let vm = run_vm(source, VmOptions::default());
+    assert_eq!(tree, vm, "the backends disagree on {:?}", source);
+    tree
+}
+
+pub fn run_tree(source: &str, options: InterpreterOptions) -> Outcome {
+    let output = SharedBuffer::default();
+    let result = compile(source).and_then(|(ast, locals)| {
+        let mut interpreter = Interpreter::with_options(options);
+        interpreter.set_output(Box::new(output.clone()), Box::new(io::sink()));
+        interpreter.resolve(locals);
+        interpreter.interpret(&Rc::new(ast))
+    });
+    output.outcome(result)
+}
+
+pub fn run_vm(source: &str, options: VmOptions) -> Outcome {
+    let output = SharedBuffer::default();
+    let result = compile(source).and_then(|(ast, _)| {
+        let mut vm = Vm::with_options(options);
+        vm.set_output(Box::new(output.clone()), Box::new(io::sink()));
+        let script = vm.compile(&ast)?;
+        vm.run_script(script)
+    });
+    output.outcome(result)
+}
+
+/// Parses and resolves `source`, failing with the first error.
+fn compile(source: &str) -> Result<(Ast, HashMap<ExprId, usize>), LoxError> {
+    let first = |errors: Vec<LoxError>| errors.into_iter().next().unwrap();
+    let ast = parse_source(source.to_string(), false).map_err(first)?;
+    let locals = Resolver::new().resolve(&ast).map_err(first)?;
+    Ok((ast, locals))
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn outcome(&self, result: Result<(), LoxError>) -> Outcome {
+        Outcome {
+            output: String::from_utf8(self.0.borrow().clone()).unwrap(),
+            error: result.as_ref().err().map(LoxError::code),
+            message: result.err().map(|error| error.message().to_string()),
+        }
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
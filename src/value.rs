@@ -0,0 +1,405 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
+
+use crate::{
+    class::{LoxClass, LoxInstance},
+    function::LoxFunction,
+    intern::{intern, Symbol},
+    map::{key_value, LoxMap, MapKey},
+    native::NativeFunction,
+    parser::LiteralValue,
+};
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Symbol),
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap<Symbol, Value>>>),
+}
+
+impl Value {
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The text `print` and string interpolation produce for this value.
+    pub fn to_lox_string(&self) -> String {
+        match self {
+            Value::Nil => "nil".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => format_number(*n),
+            Value::String(s) => s.to_string(),
+            Value::Function(function) => format!("<fn {}>", function.name()),
+            Value::Native(_) => "<native fn>".to_string(),
+            Value::Class(class) => class.name.to_string(),
+            Value::Instance(instance) => format!("{} instance", instance.borrow().class.name),
+            Value::List(_) | Value::Map(_) => collection_to_string(self, &mut Vec::new()),
+        }
+    }
+}
+
+/// `[1, "a", 3]` or `{"a": 1, "b": 2}`, given the lists and maps `value`
+/// is inside of. Strings in it are quoted. One that contains itself shows as
+/// `[...]` or `{...}` where it comes round again.
+fn collection_to_string(value: &Value, enclosing: &mut Vec<*const ()>) -> String {
+    let (address, open, close) = match value {
+        Value::List(list) => (Rc::as_ptr(list) as *const (), "[", "]"),
+        Value::Map(map) => (Rc::as_ptr(map) as *const (), "{", "}"),
+        Value::String(s) => return quote(s),
+        value => return value.to_lox_string(),
+    };
+    if enclosing.contains(&address) {
+        return format!("{}...{}", open, close);
+    }
+    enclosing.push(address);
+    let items: Vec<String> = match value {
+        Value::List(list) => list
+            .borrow()
+            .iter()
+            .map(|element| collection_to_string(element, enclosing))
+            .collect(),
+        Value::Map(map) => map
+            .borrow()
+            .entries()
+            .map(|(key, value)| {
+                let key = collection_to_string(&key_value(key), enclosing);
+                format!("{}: {}", key, collection_to_string(value, enclosing))
+            })
+            .collect(),
+        _ => unreachable!("only lists and maps have elements"),
+    };
+    enclosing.pop();
+    format!("{}{}{}", open, items.join(", "), close)
+}
+
+/// `s` as a string literal that scans back to it: in quotes, with escapes
+/// for quotes, backslashes, control characters, and a `$` starting `${`.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\0' => quoted.push_str("\\0"),
+            '$' if chars.peek() == Some(&'{') => quoted.push_str("\\$"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Formats a number the way jlox does: Java's `Double.toString` minus a
+/// trailing `.0`, so `2` prints as `2` and `1e21` as `1.0E21`.
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        return "NaN".to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    // Java only uses plain notation for magnitudes in [10^-3, 10^7).
+    if n == 0.0 || (1e-3..1e7).contains(&n.abs()) {
+        return n.to_string();
+    }
+    let scientific = format!("{:e}", n);
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("scientific notation has an exponent");
+    if mantissa.contains('.') {
+        format!("{}E{}", mantissa, exponent)
+    } else {
+        format!("{}.0E{}", mantissa, exponent)
+    }
+}
+
+/// Converts a number to the integer the bitwise operators work on, or `None`
+/// if it has a fractional part or doesn't fit in an `i64`.
+pub fn to_integer(n: f64) -> Option<i64> {
+    (n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64).then_some(n as i64)
+}
+
+/// `l << r`, or `None` if the shift amount is negative or 64 or more.
+pub fn shift_left(l: i64, r: i64) -> Option<i64> {
+    u32::try_from(r).ok().and_then(|r| l.checked_shl(r))
+}
+
+/// `l >> r` with sign extension, or `None` if the shift amount is negative or
+/// 64 or more.
+pub fn shift_right(l: i64, r: i64) -> Option<i64> {
+    u32::try_from(r).ok().and_then(|r| l.checked_shr(r))
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl From<&LiteralValue> for Value {
+    fn from(literal: &LiteralValue) -> Self {
+        match literal {
+            LiteralValue::Nil => Value::Nil,
+            LiteralValue::Bool(b) => Value::Bool(*b),
+            LiteralValue::Number(n) => Value::Number(*n),
+            LiteralValue::String(s) => Value::String(s.clone()),
+        }
+    }
+}
+
+/// Rust values that can be handed to Lox code, e.g. as what a native
+/// function registered with [`Lox::register_native`](crate::Lox::register_native)
+/// returns.
+pub trait ToLox {
+    fn to_lox(self) -> Value;
+}
+
+/// Rust values a Lox value can be converted to, e.g. a native function's
+/// arguments. The error is a message for a runtime error.
+pub trait FromLox: Sized {
+    fn from_lox(value: &Value) -> Result<Self, String>;
+}
+
+impl ToLox for Value {
+    fn to_lox(self) -> Value {
+        self
+    }
+}
+
+impl ToLox for () {
+    fn to_lox(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl ToLox for bool {
+    fn to_lox(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl ToLox for f64 {
+    fn to_lox(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl ToLox for i32 {
+    fn to_lox(self) -> Value {
+        Value::Number(self.into())
+    }
+}
+
+impl ToLox for u32 {
+    fn to_lox(self) -> Value {
+        Value::Number(self.into())
+    }
+}
+
+impl ToLox for &str {
+    fn to_lox(self) -> Value {
+        Value::String(intern(self))
+    }
+}
+
+impl ToLox for String {
+    fn to_lox(self) -> Value {
+        Value::String(intern(&self))
+    }
+}
+
+impl<T: ToLox> ToLox for Vec<T> {
+    fn to_lox(self) -> Value {
+        let elements = self.into_iter().map(ToLox::to_lox).collect();
+        Value::List(Rc::new(RefCell::new(elements)))
+    }
+}
+
+/// A map with string keys. Its entries are added in key order, since a
+/// `HashMap` has none of its own.
+impl<V: ToLox> ToLox for HashMap<String, V> {
+    fn to_lox(self) -> Value {
+        let mut entries: Vec<_> = self.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut map = LoxMap::default();
+        for (key, value) in entries {
+            map.insert(MapKey::String(intern(&key)), value.to_lox());
+        }
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+}
+
+/// `None` becomes `nil`.
+impl<T: ToLox> ToLox for Option<T> {
+    fn to_lox(self) -> Value {
+        self.map_or(Value::Nil, ToLox::to_lox)
+    }
+}
+
+impl FromLox for Value {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Bool(b) => Ok(*b),
+            _ => Err("Argument must be a boolean.".to_string()),
+        }
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Number(n) => Ok(*n),
+            _ => Err("Argument must be a number.".to_string()),
+        }
+    }
+}
+
+/// Accepts only numbers without a fractional part, like the bitwise
+/// operators.
+impl FromLox for i64 {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Number(n) => {
+                to_integer(*n).ok_or_else(|| "Argument must be an integer.".to_string())
+            }
+            _ => Err("Argument must be an integer.".to_string()),
+        }
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::String(s) => Ok(s.to_string()),
+            _ => Err("Argument must be a string.".to_string()),
+        }
+    }
+}
+
+/// A list whose elements all convert to `T`.
+impl<T: FromLox> FromLox for Vec<T> {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::List(list) => list.borrow().iter().map(T::from_lox).collect(),
+            _ => Err("Argument must be a list.".to_string()),
+        }
+    }
+}
+
+/// A map whose keys are all strings and whose values all convert to `V`.
+impl<V: FromLox> FromLox for HashMap<String, V> {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        let Value::Map(map) = value else {
+            return Err("Argument must be a map.".to_string());
+        };
+        map.borrow()
+            .entries()
+            .map(|(key, value)| match key {
+                MapKey::String(key) => Ok((key.to_string(), V::from_lox(value)?)),
+                MapKey::Number(_) => Err("Map keys must be strings.".to_string()),
+            })
+            .collect()
+    }
+}
+
+/// `nil` becomes `None`.
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_lox(value).map(Some),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        b.to_lox()
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        n.to_lox()
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        s.to_lox()
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        s.to_lox()
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        bool::from_lox(&value)
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        f64::from_lox(&value)
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        String::from_lox(&value)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_lox_string())
+    }
+}
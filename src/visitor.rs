@@ -0,0 +1,176 @@
+use std::rc::Rc;
+
+use crate::{
+    lexer::Token,
+    parser::{Expr, ExprId, ExprRef, FunctionDecl, LiteralValue, Stmt},
+};
+
+/// One method per [`Expr`] variant, each receiving that variant's fields.
+/// Call [`Expr::accept`] to dispatch; visiting children is up to the
+/// implementation, by looking them up in the [`Ast`](crate::parser::Ast)
+/// and calling `accept` on them in turn.
+pub trait ExprVisitor<R> {
+    fn visit_assign(&mut self, id: ExprId, name: &Token, value: ExprRef) -> R;
+    fn visit_binary(&mut self, left: ExprRef, operator: &Token, right: ExprRef) -> R;
+    fn visit_call(&mut self, callee: ExprRef, paren: &Token, arguments: &[ExprRef]) -> R;
+    fn visit_conditional(
+        &mut self,
+        condition: ExprRef,
+        then_branch: ExprRef,
+        else_branch: ExprRef,
+    ) -> R;
+    fn visit_get(&mut self, object: ExprRef, name: &Token) -> R;
+    fn visit_grouping(&mut self, expression: ExprRef) -> R;
+    fn visit_index(&mut self, object: ExprRef, bracket: &Token, index: ExprRef) -> R;
+    fn visit_lambda(&mut self, declaration: &Rc<FunctionDecl>) -> R;
+    fn visit_list(&mut self, bracket: &Token, elements: &[ExprRef]) -> R;
+    fn visit_literal(&mut self, value: &LiteralValue) -> R;
+    fn visit_logical(&mut self, left: ExprRef, operator: &Token, right: ExprRef) -> R;
+    fn visit_map(&mut self, brace: &Token, entries: &[(ExprRef, ExprRef)]) -> R;
+    fn visit_set(
+        &mut self,
+        object: ExprRef,
+        name: &Token,
+        operator: Option<&Token>,
+        value: ExprRef,
+    ) -> R;
+    fn visit_set_index(
+        &mut self,
+        object: ExprRef,
+        bracket: &Token,
+        index: ExprRef,
+        operator: Option<&Token>,
+        value: ExprRef,
+    ) -> R;
+    fn visit_slice(
+        &mut self,
+        object: ExprRef,
+        bracket: &Token,
+        start: Option<ExprRef>,
+        end: Option<ExprRef>,
+    ) -> R;
+    fn visit_super(&mut self, id: ExprId, keyword: &Token, method: &Token) -> R;
+    fn visit_this(&mut self, id: ExprId, keyword: &Token) -> R;
+    fn visit_unary(&mut self, operator: &Token, right: ExprRef) -> R;
+    fn visit_variable(&mut self, id: ExprId, name: &Token) -> R;
+}
+
+/// One method per [`Stmt`] variant, each receiving that variant's fields.
+/// Call [`Stmt::accept`] to dispatch.
+pub trait StmtVisitor<R> {
+    fn visit_block(&mut self, statements: &[Stmt]) -> R;
+    fn visit_break(&mut self, keyword: &Token) -> R;
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<ExprRef>,
+        methods: &[Rc<FunctionDecl>],
+        class_methods: &[Rc<FunctionDecl>],
+    ) -> R;
+    fn visit_continue(&mut self, keyword: &Token) -> R;
+    fn visit_expression(&mut self, expression: ExprRef) -> R;
+    fn visit_function(&mut self, declaration: &Rc<FunctionDecl>) -> R;
+    fn visit_if(&mut self, condition: ExprRef, then_branch: &Stmt, else_branch: Option<&Stmt>)
+        -> R;
+    fn visit_print(&mut self, expression: ExprRef) -> R;
+    fn visit_return(&mut self, keyword: &Token, value: Option<ExprRef>) -> R;
+    fn visit_var(&mut self, name: &Token, initializer: Option<ExprRef>) -> R;
+    fn visit_while(&mut self, condition: ExprRef, body: &Stmt, increment: Option<ExprRef>) -> R;
+}
+
+impl Expr {
+    pub fn accept<R>(&self, visitor: &mut impl ExprVisitor<R>) -> R {
+        match self {
+            Expr::Assign { id, name, value } => visitor.visit_assign(*id, name, *value),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => visitor.visit_binary(*left, operator, *right),
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => visitor.visit_call(*callee, paren, arguments),
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => visitor.visit_conditional(*condition, *then_branch, *else_branch),
+            Expr::Get { object, name } => visitor.visit_get(*object, name),
+            Expr::Grouping { expression } => visitor.visit_grouping(*expression),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => visitor.visit_index(*object, bracket, *index),
+            Expr::Lambda { declaration } => visitor.visit_lambda(declaration),
+            Expr::List { bracket, elements } => visitor.visit_list(bracket, elements),
+            Expr::Literal { value } => visitor.visit_literal(value),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => visitor.visit_logical(*left, operator, *right),
+            Expr::Map { brace, entries } => visitor.visit_map(brace, entries),
+            Expr::Set {
+                object,
+                name,
+                operator,
+                value,
+            } => visitor.visit_set(*object, name, operator.as_ref(), *value),
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                operator,
+                value,
+            } => visitor.visit_set_index(*object, bracket, *index, operator.as_ref(), *value),
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+            } => visitor.visit_slice(*object, bracket, *start, *end),
+            Expr::Super {
+                id,
+                keyword,
+                method,
+            } => visitor.visit_super(*id, keyword, method),
+            Expr::This { id, keyword } => visitor.visit_this(*id, keyword),
+            Expr::Unary { operator, right } => visitor.visit_unary(operator, *right),
+            Expr::Variable { id, name } => visitor.visit_variable(*id, name),
+        }
+    }
+}
+
+impl Stmt {
+    pub fn accept<R>(&self, visitor: &mut impl StmtVisitor<R>) -> R {
+        match self {
+            Stmt::Block { statements } => visitor.visit_block(statements),
+            Stmt::Break { keyword } => visitor.visit_break(keyword),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                class_methods,
+            } => visitor.visit_class(name, *superclass, methods, class_methods),
+            Stmt::Continue { keyword } => visitor.visit_continue(keyword),
+            Stmt::Expression { expression } => visitor.visit_expression(*expression),
+            Stmt::Function(declaration) => visitor.visit_function(declaration),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => visitor.visit_if(*condition, then_branch, else_branch.as_deref()),
+            Stmt::Print { expression } => visitor.visit_print(*expression),
+            Stmt::Return { keyword, value } => visitor.visit_return(keyword, *value),
+            Stmt::Var { name, initializer } => visitor.visit_var(name, *initializer),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => visitor.visit_while(*condition, body, *increment),
+        }
+    }
+}
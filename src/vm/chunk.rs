@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use crate::{
+    lexer::Span,
+    vm::{heap::ObjRef, opcode::OpCode, value::Value},
+};
+
+/// Operands of `OP_CONSTANT_LONG` are this many bytes, big-endian.
+pub const LONG_OPERAND_BYTES: usize = 3;
+
+/// The inline cache operand of an instruction compiled after its chunk ran
+/// out of caches, which runs without one.
+pub const NO_CACHE: u16 = u16::MAX;
+
+/// A sequence of bytecode along with its constant pool and the source line
+/// and token of every byte.
+#[derive(Debug, Default, Clone)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    /// Source lines and tokens, run-length encoded: one entry for each run
+    /// of bytes compiled from the same token. See [`Chunk::line_for`].
+    pub lines: Vec<LineRun>,
+    /// Where each constant already in the pool is, so it is only stored once.
+    constant_indices: HashMap<ConstantKey, usize>,
+    /// One per instruction that has an inline cache, indexed by its last
+    /// operand, up to [`NO_CACHE`]. See [`OpCode::has_inline_cache`].
+    pub caches: Vec<InlineCache>,
+    /// For an instruction that reads or writes a global, by the offset just
+    /// past it, the local variable in scope whose name is closest to the
+    /// global's. It is suggested if the global turns out to be undefined.
+    pub near_locals: HashMap<usize, String>,
+}
+
+/// What an instruction learned the last time it ran, so that when it sees
+/// the same global or class again it can skip the hash lookups.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum InlineCache {
+    #[default]
+    Empty,
+    /// The global's slot in the VM's global values. Slots never move, so
+    /// this never goes stale.
+    Global(usize),
+    /// How the property resolves on instances of `class`: to the field in
+    /// `slot` where the instance has set it, else to `method`. With no
+    /// `slot`, a field added to the class since could now shadow `method`,
+    /// so the entry only holds while the class still has `field_count`.
+    Property {
+        class: ObjRef,
+        slot: Option<usize>,
+        method: Option<ObjRef>,
+        field_count: usize,
+    },
+}
+
+/// The bytes from `start` up to the next run's start all came from `line`,
+/// and from the token at `span` if they were for one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRun {
+    pub start: usize,
+    pub line: usize,
+    pub span: Option<Span>,
+}
+
+/// What makes two constants the same. Numbers go by their bits, keeping `0`
+/// and `-0` apart.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    Obj(ObjRef),
+}
+
+impl ConstantKey {
+    fn of(value: Value) -> Option<Self> {
+        match (value.as_number(), value.as_obj()) {
+            (Some(n), _) => Some(ConstantKey::Number(n.to_bits())),
+            (_, Some(obj)) => Some(ConstantKey::Obj(obj)),
+            _ => None,
+        }
+    }
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, byte: u8, line: usize, span: Option<Span>) {
+        if self
+            .lines
+            .last()
+            .is_none_or(|run| run.line != line || run.span != span)
+        {
+            self.lines.push(LineRun {
+                start: self.code.len(),
+                line,
+                span,
+            });
+        }
+        self.code.push(byte);
+    }
+
+    /// The source line the byte at `offset` was compiled from.
+    pub fn line_for(&self, offset: usize) -> usize {
+        self.run_for(offset).line
+    }
+
+    /// The token the byte at `offset` was compiled for, if any.
+    pub fn span_for(&self, offset: usize) -> Option<Span> {
+        self.run_for(offset).span
+    }
+
+    fn run_for(&self, offset: usize) -> &LineRun {
+        let run = self.lines.partition_point(|run| run.start <= offset);
+        &self.lines[run - 1]
+    }
+
+    pub fn write_op(&mut self, op: OpCode, line: usize, span: Option<Span>) {
+        self.write(op.into(), line, span);
+    }
+
+    /// Adds an empty inline cache and returns its index, or [`NO_CACHE`]
+    /// if the chunk has no more room for one.
+    pub fn add_cache(&mut self) -> u16 {
+        match u16::try_from(self.caches.len()) {
+            Ok(index) if index != NO_CACHE => {
+                self.caches.push(InlineCache::Empty);
+                index
+            }
+            _ => NO_CACHE,
+        }
+    }
+
+    /// Adds `value` to the constant pool, unless an identical constant is
+    /// already there, and returns its index.
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        let key = ConstantKey::of(value);
+        if let Some(index) = key.as_ref().and_then(|key| self.constant_indices.get(key)) {
+            return *index;
+        }
+        self.constants.push(value);
+        let index = self.constants.len() - 1;
+        if let Some(key) = key {
+            self.constant_indices.insert(key, index);
+        }
+        index
+    }
+}
@@ -0,0 +1,1046 @@
+use crate::{
+    error::LoxError,
+    intern::{intern, Symbol},
+    lexer::{Span, Token, TokenType},
+    parser::{Ast, Expr, ExprRef, FunctionDecl, LiteralValue, Stmt, SHORT_CHAIN},
+    suggest,
+    vm::{
+        chunk::LONG_OPERAND_BYTES,
+        heap::{Heap, ObjRef},
+        object::{Obj, ObjFunction},
+        opcode::OpCode,
+        value::Value,
+    },
+};
+
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+pub(crate) const MAX_UPVALUES: usize = u8::MAX as usize + 1;
+
+struct Local {
+    name: Symbol,
+    /// Scope depth, or `None` while the variable's initializer is running.
+    depth: Option<usize>,
+    /// Whether a nested function closes over this local, in which case it
+    /// must be hoisted onto the heap when it goes out of scope.
+    is_captured: bool,
+}
+
+/// Where a closure finds a captured variable when it is created: a local
+/// slot of the enclosing function, or one of the enclosing function's own
+/// upvalues.
+#[derive(Clone, Copy, PartialEq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+/// Pending jumps out of the innermost loop being compiled.
+struct LoopState {
+    /// Scope depth around the loop. Locals deeper than this are discarded
+    /// before jumping out of the body.
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// What is being compiled, which decides what slot zero holds and what
+/// falling off the end of the body returns.
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// Per-function compilation state; nested function declarations push a new one.
+struct FunctionState {
+    function: ObjFunction,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    loops: Vec<LoopState>,
+}
+
+impl FunctionState {
+    fn new(name: String, kind: FunctionKind) -> Self {
+        // Slot zero holds the function being called, or for a method, the
+        // receiver it is bound to.
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        Self {
+            function: ObjFunction {
+                name,
+                ..ObjFunction::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: Symbol::from(slot_zero),
+                depth: Some(0),
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+        }
+    }
+}
+
+/// Compiles a resolved AST into bytecode for the [`Vm`](super::Vm).
+pub struct Compiler<'a> {
+    heap: &'a mut Heap,
+    ast: &'a Ast,
+    states: Vec<FunctionState>,
+    /// Where the code being emitted comes from: the line, and the token
+    /// it is for, once there is one.
+    line: usize,
+    span: Option<Span>,
+    /// The link of a long [chain](Ast::chain) compiled last, whose value
+    /// the next link continues from.
+    chained: Option<ExprRef>,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(heap: &'a mut Heap, ast: &'a Ast) -> Self {
+        Self {
+            heap,
+            ast,
+            states: vec![FunctionState::new(String::new(), FunctionKind::Script)],
+            line: 1,
+            span: None,
+            chained: None,
+        }
+    }
+
+    /// Compiles a whole program into the implicit top-level script function.
+    pub fn compile(mut self) -> Result<ObjRef, LoxError> {
+        for statement in self.ast.statements() {
+            self.statement(statement)?;
+        }
+        let (function, _) = self.end_function();
+        Ok(self.heap.alloc(Obj::Function(function)))
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.states.last_mut().expect("compiler has no function")
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
+        match stmt {
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                self.end_scope();
+                Ok(())
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                class_methods,
+            } => {
+                let name_constant = self.identifier_constant(name)?;
+                let global = self.declare_variable(name)?;
+                self.emit_with_operand(OpCode::Class, name_constant);
+                self.mark_initialized();
+                self.define_variable(global);
+
+                // Methods of a subclass close over an extra scope binding `super`.
+                if let Some(superclass) = superclass {
+                    self.expression(*superclass)?;
+                    self.begin_scope();
+                    self.declare_variable(&Token {
+                        lexeme: intern("super"),
+                        ..name.clone()
+                    })?;
+                    self.mark_initialized();
+                    self.named_variable(name)?;
+                    self.emit_op(OpCode::Inherit);
+                }
+
+                // Keep the class on the stack while its methods are attached.
+                self.named_variable(name)?;
+                for method in methods {
+                    let kind = if &*method.name.lexeme == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    self.method(method, kind, OpCode::Method)?;
+                }
+                for method in class_methods {
+                    self.method(method, FunctionKind::Method, OpCode::ClassMethod)?;
+                }
+                self.emit_op(OpCode::Pop);
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                Ok(())
+            }
+            Stmt::Expression { expression } => {
+                self.expression(*expression)?;
+                self.emit_op(OpCode::Pop);
+                Ok(())
+            }
+            Stmt::Function(declaration) => {
+                let global = self.declare_variable(&declaration.name)?;
+                self.mark_initialized();
+                self.function(declaration, FunctionKind::Function)?;
+                self.define_variable(global);
+                Ok(())
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(*condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(then_branch)?;
+
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump)?;
+                self.emit_op(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump)
+            }
+            Stmt::Print { expression } => {
+                self.expression(*expression)?;
+                self.emit_op(OpCode::Print);
+                Ok(())
+            }
+            Stmt::Return { keyword, value } => {
+                self.at(keyword);
+                match value {
+                    Some(value) => self.return_value(*value)?,
+                    None => self.emit_return(),
+                }
+                Ok(())
+            }
+            Stmt::Var { name, initializer } => {
+                let global = self.declare_variable(name)?;
+                match initializer {
+                    Some(initializer) => self.expression(*initializer)?,
+                    None => self.emit_op(OpCode::Nil),
+                }
+                self.mark_initialized();
+                self.define_variable(global);
+                Ok(())
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+            } => {
+                let loop_start = self.current_chunk_len();
+                self.expression(*condition)?;
+
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+
+                let scope_depth = self.state().scope_depth;
+                self.state().loops.push(LoopState {
+                    scope_depth,
+                    breaks: Vec::new(),
+                    continues: Vec::new(),
+                });
+                let result = self.statement(body);
+                let loop_state = self.state().loops.pop().expect("loop state was pushed");
+                result?;
+
+                for jump in loop_state.continues {
+                    self.patch_jump(jump)?;
+                }
+                if let Some(increment) = increment {
+                    self.expression(*increment)?;
+                    self.emit_op(OpCode::Pop);
+                }
+                self.emit_loop(loop_start)?;
+
+                self.patch_jump(exit_jump)?;
+                self.emit_op(OpCode::Pop);
+                for jump in loop_state.breaks {
+                    self.patch_jump(jump)?;
+                }
+                Ok(())
+            }
+            Stmt::Break { keyword } | Stmt::Continue { keyword } => {
+                self.at(keyword);
+                self.discard_loop_locals();
+                let jump = self.emit_jump(OpCode::Jump);
+                let loop_state = self
+                    .state()
+                    .loops
+                    .last_mut()
+                    .expect("the resolver rejects break and continue outside loops");
+                if keyword.token_type == TokenType::Break {
+                    loop_state.breaks.push(jump);
+                } else {
+                    loop_state.continues.push(jump);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn function(&mut self, declaration: &FunctionDecl, kind: FunctionKind) -> Result<(), LoxError> {
+        self.states.push(FunctionState::new(
+            declaration.name.lexeme.to_string(),
+            kind,
+        ));
+        self.begin_scope();
+
+        self.state().function.arity = declaration.params.len();
+        for param in &declaration.params {
+            self.declare_variable(param)?;
+            self.mark_initialized();
+        }
+
+        for statement in &declaration.body {
+            self.statement(statement)?;
+        }
+
+        let (function, upvalues) = self.end_function();
+        let function = self.heap.alloc(Obj::Function(function));
+        self.at(&declaration.name);
+        let constant = self.make_constant(Value::obj(function))?;
+        self.emit_with_operand(OpCode::Closure, constant);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+        Ok(())
+    }
+
+    /// Compiles a method and attaches it to the class on top of the stack
+    /// with `op`, either `Method` or `ClassMethod`.
+    fn method(
+        &mut self,
+        declaration: &FunctionDecl,
+        kind: FunctionKind,
+        op: OpCode,
+    ) -> Result<(), LoxError> {
+        let constant = self.identifier_constant(&declaration.name)?;
+        self.function(declaration, kind)?;
+        self.emit_with_operand(op, constant);
+        Ok(())
+    }
+
+    /// Finishes the innermost function, emitting its implicit return.
+    /// Also returns the variables it captures, which the enclosing function
+    /// lists after its `OP_CLOSURE`.
+    fn end_function(&mut self) -> (ObjFunction, Vec<Upvalue>) {
+        self.emit_return();
+        let state = self.states.pop().expect("compiler has no function");
+        (state.function, state.upvalues)
+    }
+
+    /// Emits the instruction or instructions for binary operator
+    /// `token_type`, applied to the top two values on the stack.
+    fn binary_op(&mut self, token_type: TokenType) {
+        match token_type {
+            TokenType::BangEqual => {
+                self.emit_op(OpCode::Equal);
+                self.emit_op(OpCode::Not);
+            }
+            TokenType::EqualEqual => self.emit_op(OpCode::Equal),
+            TokenType::Greater => self.emit_op(OpCode::Greater),
+            TokenType::GreaterEqual => {
+                self.emit_op(OpCode::Less);
+                self.emit_op(OpCode::Not);
+            }
+            TokenType::Less => self.emit_op(OpCode::Less),
+            TokenType::LessEqual => {
+                self.emit_op(OpCode::Greater);
+                self.emit_op(OpCode::Not);
+            }
+            TokenType::Plus => self.emit_op(OpCode::Add),
+            TokenType::Minus => self.emit_op(OpCode::Subtract),
+            TokenType::Star => self.emit_op(OpCode::Multiply),
+            TokenType::Slash => self.emit_op(OpCode::Divide),
+            TokenType::Percent => self.emit_op(OpCode::Modulo),
+            TokenType::StarStar => self.emit_op(OpCode::Power),
+            TokenType::Ampersand => self.emit_op(OpCode::BitAnd),
+            TokenType::Pipe => self.emit_op(OpCode::BitOr),
+            TokenType::Caret => self.emit_op(OpCode::BitXor),
+            TokenType::LessLess => self.emit_op(OpCode::ShiftLeft),
+            TokenType::GreaterGreater => self.emit_op(OpCode::ShiftRight),
+            _ => unreachable!("invalid binary operator"),
+        }
+    }
+
+    /// Compiles a long [chain](Ast::chain), like `a + b + c + ...`. Each
+    /// link starts by compiling the one it continues, so compiling the last
+    /// would recurse all the way down it. Instead its links are compiled
+    /// from the innermost out, each leaving its value on the stack for the
+    /// next, which `chained` tells not to compile it again.
+    fn chain(&mut self, expr: ExprRef) -> Result<(), LoxError> {
+        let ast = self.ast;
+        let (_, links) = ast.chain(expr);
+        for (i, &link) in links[..links.len() - 1].iter().enumerate() {
+            // A method called straight off a property access is invoked by
+            // the call, on the object under it.
+            let invoked = matches!(ast[link], Expr::Get { .. })
+                && matches!(ast[links[i + 1]], Expr::Call { .. });
+            if !invoked {
+                self.expression(link)?;
+                self.chained = Some(link);
+            }
+        }
+        self.expression(expr)
+    }
+
+    fn expression(&mut self, expr: ExprRef) -> Result<(), LoxError> {
+        if self.chained.take_if(|link| *link == expr).is_some() {
+            return Ok(());
+        }
+        // While a link's value is being handed on, `expr` is the next link
+        // of a chain already being compiled.
+        if self.chained.is_none() && self.ast.chain_length(expr) > SHORT_CHAIN {
+            return self.chain(expr);
+        }
+        match &self.ast[expr] {
+            Expr::Assign { name, value, .. } => {
+                self.expression(*value)?;
+                self.at(name);
+                let (_, set_op, operand) = self.resolve_variable(name)?;
+                self.emit_with_operand(set_op, operand);
+                if set_op == OpCode::SetGlobal {
+                    self.note_near_local(name);
+                }
+                Ok(())
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } if operator.token_type == TokenType::Comma => {
+                self.expression(*left)?;
+                self.emit_op(OpCode::Pop);
+                self.expression(*right)
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(*left)?;
+                self.expression(*right)?;
+                self.at(operator);
+                self.binary_op(operator.token_type);
+                Ok(())
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => self.call(*callee, paren, arguments, OpCode::Call),
+            Expr::Get { object, name } => {
+                self.expression(*object)?;
+                self.at(name);
+                let constant = self.identifier_constant(name)?;
+                self.emit_with_operand(OpCode::GetProperty, constant);
+                self.emit_cache();
+                Ok(())
+            }
+            Expr::Set {
+                object,
+                name,
+                operator,
+                value,
+            } => {
+                self.expression(*object)?;
+                let constant = self.identifier_constant(name)?;
+                if let Some(operator) = operator {
+                    // Reads the field from a copy of the object, leaving the
+                    // object for the write.
+                    self.at(name);
+                    self.emit_bytes(OpCode::Dup, 1);
+                    self.emit_with_operand(OpCode::GetProperty, constant);
+                    self.emit_cache();
+                    self.expression(*value)?;
+                    self.at(operator);
+                    self.binary_op(operator.token_type);
+                } else {
+                    self.expression(*value)?;
+                }
+                self.at(name);
+                self.emit_with_operand(OpCode::SetProperty, constant);
+                Ok(())
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                self.expression(*object)?;
+                self.expression(*index)?;
+                self.at(bracket);
+                self.emit_op(OpCode::GetIndex);
+                Ok(())
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                operator,
+                value,
+            } => {
+                self.expression(*object)?;
+                self.expression(*index)?;
+                if let Some(operator) = operator {
+                    // Reads the element from copies of the object and index,
+                    // leaving them for the write.
+                    self.at(bracket);
+                    self.emit_bytes(OpCode::Dup, 2);
+                    self.emit_op(OpCode::GetIndex);
+                    self.expression(*value)?;
+                    self.at(operator);
+                    self.binary_op(operator.token_type);
+                } else {
+                    self.expression(*value)?;
+                }
+                self.at(bracket);
+                self.emit_op(OpCode::SetIndex);
+                Ok(())
+            }
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+            } => {
+                self.expression(*object)?;
+                // A missing bound is `nil`, for the start or end.
+                for bound in [start, end] {
+                    match bound {
+                        Some(bound) => self.expression(*bound)?,
+                        None => self.emit_op(OpCode::Nil),
+                    }
+                }
+                self.at(bracket);
+                self.emit_op(OpCode::GetSlice);
+                Ok(())
+            }
+            Expr::Grouping { expression } => self.expression(*expression),
+            Expr::List { bracket, elements } => {
+                for element in elements {
+                    self.expression(*element)?;
+                }
+                self.at(bracket);
+                self.emit_bytes(OpCode::BuildList, elements.len() as u8);
+                Ok(())
+            }
+            Expr::Map { brace, entries } => {
+                for &(key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.at(brace);
+                self.emit_bytes(OpCode::BuildMap, entries.len() as u8);
+                Ok(())
+            }
+            Expr::Literal { value } => match value {
+                LiteralValue::Nil => {
+                    self.emit_op(OpCode::Nil);
+                    Ok(())
+                }
+                LiteralValue::Bool(true) => {
+                    self.emit_op(OpCode::True);
+                    Ok(())
+                }
+                LiteralValue::Bool(false) => {
+                    self.emit_op(OpCode::False);
+                    Ok(())
+                }
+                LiteralValue::Number(n) => self.emit_constant(Value::number(*n)),
+                LiteralValue::String(s) => {
+                    let string = self.heap.intern(s);
+                    self.emit_constant(Value::obj(string))
+                }
+            },
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(*condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.expression(*then_branch)?;
+
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump)?;
+                self.emit_op(OpCode::Pop);
+                self.expression(*else_branch)?;
+                self.patch_jump(else_jump)
+            }
+            Expr::Lambda { declaration } => self.function(declaration, FunctionKind::Function),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(*left)?;
+                self.at(operator);
+                if operator.token_type == TokenType::And {
+                    let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    self.emit_op(OpCode::Pop);
+                    self.expression(*right)?;
+                    self.patch_jump(end_jump)
+                } else {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump)?;
+                    self.emit_op(OpCode::Pop);
+                    self.expression(*right)?;
+                    self.patch_jump(end_jump)
+                }
+            }
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                self.this(keyword)?;
+                self.named_variable(keyword)?;
+                self.at(method);
+                let constant = self.identifier_constant(method)?;
+                self.emit_with_operand(OpCode::GetSuper, constant);
+                Ok(())
+            }
+            Expr::This { keyword, .. } => self.named_variable(keyword),
+            Expr::Unary { operator, right } => {
+                self.expression(*right)?;
+                self.at(operator);
+                match operator.token_type {
+                    TokenType::Bang => self.emit_op(OpCode::Not),
+                    TokenType::Minus => self.emit_op(OpCode::Negate),
+                    TokenType::Tilde => self.emit_op(OpCode::BitNot),
+                    TokenType::Interpolation => self.emit_op(OpCode::ToString),
+                    _ => unreachable!("invalid unary operator"),
+                }
+                Ok(())
+            }
+            Expr::Variable { name, .. } => self.named_variable(name),
+        }
+    }
+
+    fn named_variable(&mut self, name: &Token) -> Result<(), LoxError> {
+        self.at(name);
+        let (get_op, _, operand) = self.resolve_variable(name)?;
+        self.emit_with_operand(get_op, operand);
+        if get_op.has_inline_cache() {
+            self.emit_cache();
+        }
+        if get_op == OpCode::GetGlobal {
+            self.note_near_local(name);
+        }
+        Ok(())
+    }
+
+    /// Records the local variable in scope, in this function or one
+    /// enclosing it, most likely meant by `name`, the global the instruction
+    /// just emitted uses.
+    fn note_near_local(&mut self, name: &Token) {
+        let locals = self
+            .states
+            .iter()
+            .flat_map(|state| &state.locals)
+            .map(|local| &*local.name);
+        if let Some(local) = suggest::closest(&name.lexeme, locals).map(str::to_string) {
+            let chunk = &mut self.state().function.chunk;
+            chunk.near_locals.insert(chunk.code.len(), local);
+        }
+    }
+
+    /// Loads the receiver of the method enclosing a `super` expression.
+    fn this(&mut self, keyword: &Token) -> Result<(), LoxError> {
+        self.named_variable(&Token {
+            lexeme: intern("this"),
+            ..keyword.clone()
+        })
+    }
+
+    /// Compiles `return value;`. A call, including one in either branch of
+    /// a `?:`, becomes a tail call: the callee takes over this frame, and the
+    /// `Return` only runs if it turns out not to be a closure.
+    fn return_value(&mut self, value: ExprRef) -> Result<(), LoxError> {
+        let ast = self.ast;
+        match &ast[value] {
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => self.call(*callee, paren, arguments, OpCode::TailCall)?,
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(*condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.return_value(*then_branch)?;
+                self.patch_jump(then_jump)?;
+                self.emit_op(OpCode::Pop);
+                self.return_value(*else_branch)?;
+                return Ok(());
+            }
+            _ => self.expression(value)?,
+        }
+        self.emit_op(OpCode::Return);
+        Ok(())
+    }
+
+    /// Compiles a call, with `op` either `Call` or `TailCall`. Calling a
+    /// method straight off a property access or `super` skips creating a
+    /// bound method, and a tail call of one uses the tail form of the
+    /// instruction.
+    fn call(
+        &mut self,
+        callee: ExprRef,
+        paren: &Token,
+        arguments: &[ExprRef],
+        op: OpCode,
+    ) -> Result<(), LoxError> {
+        let ast = self.ast;
+        let tail = op == OpCode::TailCall;
+        let (op, name) = match &ast[callee] {
+            Expr::Get { object, name } if tail => {
+                self.expression(*object)?;
+                (OpCode::TailInvoke, Some(name))
+            }
+            Expr::Get { object, name } => {
+                self.expression(*object)?;
+                (OpCode::Invoke, Some(name))
+            }
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                self.this(keyword)?;
+                let op = if tail {
+                    OpCode::TailSuperInvoke
+                } else {
+                    OpCode::SuperInvoke
+                };
+                (op, Some(method))
+            }
+            _ => {
+                self.expression(callee)?;
+                (op, None)
+            }
+        };
+        for argument in arguments {
+            self.expression(*argument)?;
+        }
+
+        self.at(paren);
+        if let Expr::Super { keyword, .. } = &ast[callee] {
+            self.named_variable(keyword)?;
+        }
+        match name {
+            Some(name) => {
+                // As in the tree-walker, errors finding or calling the
+                // method point at its name.
+                self.span = Some(name.span);
+                let constant = self.identifier_constant(name)?;
+                self.emit_with_operand(op, constant);
+                self.emit_byte(arguments.len() as u8);
+                if op.has_inline_cache() {
+                    self.emit_cache();
+                }
+            }
+            None => self.emit_bytes(op, arguments.len() as u8),
+        }
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+
+        let depth = state.scope_depth;
+        let mut popped = Vec::new();
+        while state
+            .locals
+            .last()
+            .is_some_and(|local| local.depth.is_some_and(|d| d > depth))
+        {
+            let local = state.locals.pop().expect("checked above");
+            popped.push(local.is_captured);
+        }
+
+        for is_captured in popped {
+            if is_captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+        }
+    }
+
+    /// Emits the pops for locals declared inside the innermost loop's body,
+    /// without forgetting them, since compilation of the body continues.
+    fn discard_loop_locals(&mut self) {
+        let state = self.state();
+        let depth = state
+            .loops
+            .last()
+            .map_or(0, |loop_state| loop_state.scope_depth);
+        let captured: Vec<bool> = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|d| d > depth))
+            .map(|local| local.is_captured)
+            .collect();
+
+        for is_captured in captured {
+            if is_captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+        }
+    }
+
+    /// Declares `name` in the current scope. Returns the name's constant
+    /// index when it is a global, which locals don't need.
+    fn declare_variable(&mut self, name: &Token) -> Result<Option<usize>, LoxError> {
+        self.at(name);
+        if self.state().scope_depth == 0 {
+            return self.identifier_constant(name).map(Some);
+        }
+
+        if self.state().locals.len() == MAX_LOCALS {
+            return Err(LoxError::compile_at(
+                name,
+                "C0001",
+                "Too many local variables in function.".to_string(),
+            ));
+        }
+
+        self.state().locals.push(Local {
+            name: name.lexeme.clone(),
+            depth: None,
+            is_captured: false,
+        });
+        Ok(None)
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return;
+        }
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    fn define_variable(&mut self, global: Option<usize>) {
+        if let Some(constant) = global {
+            self.emit_with_operand(OpCode::DefineGlobal, constant);
+        }
+    }
+
+    /// Works out how to reach `name` from the current function, returning
+    /// the opcodes that read and write it along with their operand.
+    fn resolve_variable(&mut self, name: &Token) -> Result<(OpCode, OpCode, usize), LoxError> {
+        let current = self.states.len() - 1;
+        if let Some(slot) = Self::find_local(&self.states[current], &name.lexeme) {
+            return Ok((OpCode::GetLocal, OpCode::SetLocal, slot as usize));
+        }
+        if let Some(index) = self.resolve_upvalue(current, name)? {
+            return Ok((OpCode::GetUpvalue, OpCode::SetUpvalue, index as usize));
+        }
+        let constant = self.identifier_constant(name)?;
+        Ok((OpCode::GetGlobal, OpCode::SetGlobal, constant))
+    }
+
+    /// Looks for `name` in the functions enclosing `states[state]`, threading
+    /// an upvalue through every function in between.
+    fn resolve_upvalue(&mut self, state: usize, name: &Token) -> Result<Option<u8>, LoxError> {
+        if state == 0 {
+            return Ok(None);
+        }
+        let enclosing = state - 1;
+
+        if let Some(slot) = Self::find_local(&self.states[enclosing], &name.lexeme) {
+            self.states[enclosing].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(state, slot, true, name).map(Some);
+        }
+
+        match self.resolve_upvalue(enclosing, name)? {
+            Some(index) => self.add_upvalue(state, index, false, name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        state: usize,
+        index: u8,
+        is_local: bool,
+        name: &Token,
+    ) -> Result<u8, LoxError> {
+        let state = &mut self.states[state];
+        let upvalue = Upvalue { index, is_local };
+        if let Some(existing) = state.upvalues.iter().position(|u| *u == upvalue) {
+            return Ok(existing as u8);
+        }
+
+        if state.upvalues.len() == MAX_UPVALUES {
+            return Err(LoxError::compile_at(
+                name,
+                "C0002",
+                "Too many closure variables in function.".to_string(),
+            ));
+        }
+
+        state.upvalues.push(upvalue);
+        state.function.upvalue_count = state.upvalues.len();
+        Ok((state.upvalues.len() - 1) as u8)
+    }
+
+    fn find_local(state: &FunctionState, name: &Symbol) -> Option<u8> {
+        state
+            .locals
+            .iter()
+            .rposition(|local| &local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> Result<usize, LoxError> {
+        let string = self.heap.intern(&name.lexeme);
+        self.make_constant(Value::obj(string))
+    }
+
+    fn make_constant(&mut self, value: Value) -> Result<usize, LoxError> {
+        let constant = self.state().function.chunk.add_constant(value);
+        if constant >= 1 << (8 * LONG_OPERAND_BYTES) {
+            return Err(LoxError::compile(
+                self.line,
+                "C0003",
+                "Too many constants in one chunk.".to_string(),
+            ));
+        }
+        Ok(constant)
+    }
+
+    fn emit_constant(&mut self, value: Value) -> Result<(), LoxError> {
+        let constant = self.make_constant(value)?;
+        self.emit_with_operand(OpCode::Constant, constant);
+        Ok(())
+    }
+
+    fn current_chunk_len(&mut self) -> usize {
+        self.state().function.chunk.code.len()
+    }
+
+    /// Marks the code emitted from here on as coming from `token`.
+    fn at(&mut self, token: &Token) {
+        self.line = token.line;
+        self.span = Some(token.span);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let (line, span) = (self.line, self.span);
+        self.state().function.chunk.write(byte, line, span);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op.into());
+    }
+
+    /// Emits the return for falling off the end of a function or a bare
+    /// `return`: `nil`, except that initializers return `this`.
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit_bytes(OpCode::GetLocal, 0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    fn emit_bytes(&mut self, op: OpCode, operand: u8) {
+        self.emit_op(op);
+        self.emit_byte(operand);
+    }
+
+    /// Emits `op` with a one-byte operand, or for a constant index too big
+    /// for that, the long form of `op`.
+    fn emit_with_operand(&mut self, op: OpCode, operand: usize) {
+        match u8::try_from(operand) {
+            Ok(operand) => self.emit_bytes(op, operand),
+            Err(_) => {
+                self.emit_op(op.for_constant(operand));
+                let bytes = operand.to_be_bytes();
+                for byte in &bytes[bytes.len() - LONG_OPERAND_BYTES..] {
+                    self.emit_byte(*byte);
+                }
+            }
+        }
+    }
+
+    /// Emits the operand of an instruction with an inline cache: the index
+    /// of a fresh cache for it, or [`NO_CACHE`](super::chunk::NO_CACHE)
+    /// once the chunk is out of them.
+    fn emit_cache(&mut self) {
+        let cache = self.state().function.chunk.add_cache();
+        let [high, low] = cache.to_be_bytes();
+        self.emit_byte(high);
+        self.emit_byte(low);
+    }
+
+    /// Emits a jump with a placeholder offset and returns where to patch it.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        self.current_chunk_len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) -> Result<(), LoxError> {
+        // -2 to adjust for the bytecode for the jump offset itself.
+        let jump = self.current_chunk_len() - offset - 2;
+        let jump = u16::try_from(jump).map_err(|_| {
+            LoxError::compile(
+                self.line,
+                "C0005",
+                "Too much code to jump over.".to_string(),
+            )
+        })?;
+
+        let code = &mut self.state().function.chunk.code;
+        code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> Result<(), LoxError> {
+        self.emit_op(OpCode::Loop);
+
+        let offset = self.current_chunk_len() - loop_start + 2;
+        let offset = u16::try_from(offset).map_err(|_| {
+            LoxError::compile(self.line, "C0005", "Loop body too large.".to_string())
+        })?;
+        let [high, low] = offset.to_be_bytes();
+        self.emit_byte(high);
+        self.emit_byte(low);
+        Ok(())
+    }
+}
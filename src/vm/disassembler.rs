@@ -0,0 +1,210 @@
+use std::fmt::Write;
+
+use crate::vm::{
+    chunk::{Chunk, LONG_OPERAND_BYTES},
+    heap::Heap,
+    object::Obj,
+    opcode::OpCode,
+};
+
+/// Renders every instruction in `chunk` under a `== name ==` header.
+pub fn disassemble(chunk: &Chunk, name: &str, heap: &Heap) -> String {
+    let mut out = format!("== {} ==\n", name);
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, heap, &mut out);
+    }
+    out
+}
+
+/// Renders the instruction at `offset` into `out` and returns the offset of
+/// the next instruction.
+pub fn disassemble_instruction(
+    chunk: &Chunk,
+    offset: usize,
+    heap: &Heap,
+    out: &mut String,
+) -> usize {
+    let _ = write!(out, "{:04} ", offset);
+    let line = chunk.line_for(offset);
+    if offset > 0 && line == chunk.line_for(offset - 1) {
+        out.push_str("   | ");
+    } else {
+        let _ = write!(out, "{:4} ", line);
+    }
+
+    let Ok(op) = OpCode::try_from(chunk.code[offset]) else {
+        let _ = writeln!(out, "Unknown opcode {}", chunk.code[offset]);
+        return offset + 1;
+    };
+
+    let next = match op {
+        OpCode::Constant
+        | OpCode::ConstantLong
+        | OpCode::GetGlobal
+        | OpCode::GetGlobalLong
+        | OpCode::DefineGlobal
+        | OpCode::DefineGlobalLong
+        | OpCode::SetGlobal
+        | OpCode::SetGlobalLong
+        | OpCode::GetProperty
+        | OpCode::GetPropertyLong
+        | OpCode::SetProperty
+        | OpCode::SetPropertyLong
+        | OpCode::GetSuper
+        | OpCode::GetSuperLong
+        | OpCode::Class
+        | OpCode::ClassLong
+        | OpCode::Method
+        | OpCode::MethodLong
+        | OpCode::ClassMethod
+        | OpCode::ClassMethodLong => constant_instruction(op, chunk, offset, heap, out),
+        OpCode::Invoke
+        | OpCode::InvokeLong
+        | OpCode::SuperInvoke
+        | OpCode::SuperInvokeLong
+        | OpCode::TailInvoke
+        | OpCode::TailInvokeLong
+        | OpCode::TailSuperInvoke
+        | OpCode::TailSuperInvokeLong => invoke_instruction(op, chunk, offset, heap, out),
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call
+        | OpCode::TailCall
+        | OpCode::BuildList
+        | OpCode::BuildMap
+        | OpCode::Dup => byte_instruction(op, chunk, offset, out),
+        OpCode::Closure | OpCode::ClosureLong => closure_instruction(op, chunk, offset, heap, out),
+        OpCode::Jump | OpCode::JumpIfFalse => jump_instruction(op, 1, chunk, offset, out),
+        OpCode::Loop => jump_instruction(op, -1, chunk, offset, out),
+        _ => {
+            let _ = writeln!(out, "{}", op.name());
+            offset + 1
+        }
+    };
+    // Skip the inline cache index, which only means anything at runtime.
+    if op.has_inline_cache() {
+        next + 2
+    } else {
+        next
+    }
+}
+
+/// Disassembles `chunk` followed by every function nested in its constants.
+pub fn disassemble_all(chunk: &Chunk, name: &str, heap: &Heap) -> String {
+    let mut out = disassemble(chunk, name, heap);
+    for constant in &chunk.constants {
+        if let Some(Obj::Function(function)) = constant.as_obj().map(|obj| heap.get(obj)) {
+            out.push('\n');
+            out.push_str(&disassemble_all(&function.chunk, &function.name, heap));
+        }
+    }
+    out
+}
+
+fn constant_instruction(
+    op: OpCode,
+    chunk: &Chunk,
+    offset: usize,
+    heap: &Heap,
+    out: &mut String,
+) -> usize {
+    let (constant, offset) = constant_operand(op, chunk, offset);
+    let value = chunk.constants[constant];
+    let _ = writeln!(
+        out,
+        "{:<16} {:4} '{}'",
+        op.name(),
+        constant,
+        heap.display(value)
+    );
+    offset
+}
+
+/// Reads the constant index of the instruction at `offset`, returning it and
+/// the offset just past it.
+fn constant_operand(op: OpCode, chunk: &Chunk, offset: usize) -> (usize, usize) {
+    let width = if op.is_long() { LONG_OPERAND_BYTES } else { 1 };
+    let operand = &chunk.code[offset + 1..offset + 1 + width];
+    let constant = operand
+        .iter()
+        .fold(0, |constant, &byte| constant << 8 | byte as usize);
+    (constant, offset + 1 + width)
+}
+
+/// A method name followed by an argument count.
+fn invoke_instruction(
+    op: OpCode,
+    chunk: &Chunk,
+    offset: usize,
+    heap: &Heap,
+    out: &mut String,
+) -> usize {
+    let (constant, offset) = constant_operand(op, chunk, offset);
+    let arg_count = chunk.code[offset];
+    let _ = writeln!(
+        out,
+        "{:<16} ({} args) {:4} '{}'",
+        op.name(),
+        arg_count,
+        constant,
+        heap.display(chunk.constants[constant])
+    );
+    offset + 1
+}
+
+fn closure_instruction(
+    op: OpCode,
+    chunk: &Chunk,
+    offset: usize,
+    heap: &Heap,
+    out: &mut String,
+) -> usize {
+    let (constant, mut offset) = constant_operand(op, chunk, offset);
+    let value = chunk.constants[constant];
+    let _ = writeln!(
+        out,
+        "{:<16} {:4} {}",
+        op.name(),
+        constant,
+        heap.display(value)
+    );
+
+    let upvalue_count = value
+        .as_obj()
+        .map_or(0, |function| heap.as_function(function).upvalue_count);
+    for _ in 0..upvalue_count {
+        let is_local = chunk.code[offset];
+        let index = chunk.code[offset + 1];
+        let _ = writeln!(
+            out,
+            "{:04}    |                     {} {}",
+            offset,
+            if is_local == 1 { "local" } else { "upvalue" },
+            index
+        );
+        offset += 2;
+    }
+    offset
+}
+
+fn byte_instruction(op: OpCode, chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    let slot = chunk.code[offset + 1];
+    let _ = writeln!(out, "{:<16} {:4}", op.name(), slot);
+    offset + 2
+}
+
+fn jump_instruction(
+    op: OpCode,
+    sign: i64,
+    chunk: &Chunk,
+    offset: usize,
+    out: &mut String,
+) -> usize {
+    let jump = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+    let target = offset as i64 + 3 + sign * jump as i64;
+    let _ = writeln!(out, "{:<16} {:4} -> {}", op.name(), offset, target);
+    offset + 3
+}
@@ -0,0 +1,415 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{
+    map::{LoxMap, MapKey},
+    value::{format_number, quote},
+    vm::{
+        object::{Obj, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjUpvalue},
+        value::Value,
+    },
+};
+
+/// Handle to an object stored in the [`Heap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub(crate) usize);
+
+/// Tuning knobs for the garbage collector.
+#[derive(Debug, Clone, Copy)]
+pub struct GcConfig {
+    /// Bytes allocated before the first collection.
+    pub initial_threshold: usize,
+    /// After a collection the next threshold is the live size times this.
+    pub growth_factor: usize,
+    /// Collect before every allocation the VM makes, to shake out rooting bugs.
+    pub stress: bool,
+    /// Bytes the heap may hold. An allocation that would go over is first
+    /// given a collection to make room, then fails with "Out of memory.".
+    pub max_heap: Option<usize>,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            initial_threshold: 1024 * 1024,
+            growth_factor: 2,
+            stress: false,
+            max_heap: None,
+        }
+    }
+}
+
+struct Entry {
+    obj: Obj,
+    /// Size charged to `bytes_allocated` when the object was allocated.
+    size: usize,
+    marked: bool,
+}
+
+/// Owns every object allocated by the compiler and the VM, and reclaims the
+/// unreachable ones with a mark-and-sweep collector.
+pub struct Heap {
+    entries: Vec<Option<Entry>>,
+    free: Vec<usize>,
+    /// Interned strings. Entries are weak: the sweep drops strings that are
+    /// no longer reachable from anywhere else.
+    strings: HashMap<String, ObjRef>,
+    gray: Vec<ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    config: GcConfig,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new(GcConfig::default())
+    }
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Self {
+            entries: Vec::new(),
+            free: Vec::new(),
+            strings: HashMap::new(),
+            gray: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: config.initial_threshold,
+            config,
+        }
+    }
+
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        let size = obj.size();
+        self.bytes_allocated += size;
+        let entry = Some(Entry {
+            obj,
+            size,
+            marked: false,
+        });
+        match self.free.pop() {
+            Some(index) => {
+                self.entries[index] = entry;
+                ObjRef(index)
+            }
+            None => {
+                self.entries.push(entry);
+                ObjRef(self.entries.len() - 1)
+            }
+        }
+    }
+
+    /// Returns the canonical string object for `s`, allocating it if this is
+    /// the first time it has been seen.
+    pub fn intern(&mut self, s: &str) -> ObjRef {
+        match self.find_string(s) {
+            Some(existing) => existing,
+            None => self.intern_new(s.to_string()),
+        }
+    }
+
+    pub fn find_string(&self, s: &str) -> Option<ObjRef> {
+        self.strings.get(s).copied()
+    }
+
+    /// Allocates `s` as a string object. The caller must have checked that
+    /// it isn't interned already.
+    pub fn intern_new(&mut self, s: String) -> ObjRef {
+        let obj = self.alloc(Obj::String(s.clone()));
+        self.strings.insert(s, obj);
+        obj
+    }
+
+    pub fn get(&self, obj: ObjRef) -> &Obj {
+        &self.entries[obj.0]
+            .as_ref()
+            .expect("use of a collected object")
+            .obj
+    }
+
+    pub fn get_mut(&mut self, obj: ObjRef) -> &mut Obj {
+        &mut self.entries[obj.0]
+            .as_mut()
+            .expect("use of a collected object")
+            .obj
+    }
+
+    pub fn as_string(&self, value: Value) -> Option<&str> {
+        match self.get(value.as_obj()?) {
+            Obj::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_function(&self, obj: ObjRef) -> &ObjFunction {
+        match self.get(obj) {
+            Obj::Function(function) => function,
+            other => panic!("expected a function, found {:?}", other),
+        }
+    }
+
+    pub fn as_closure(&self, obj: ObjRef) -> &ObjClosure {
+        match self.get(obj) {
+            Obj::Closure(closure) => closure,
+            other => panic!("expected a closure, found {:?}", other),
+        }
+    }
+
+    pub fn as_upvalue(&self, obj: ObjRef) -> &ObjUpvalue {
+        match self.get(obj) {
+            Obj::Upvalue(upvalue) => upvalue,
+            other => panic!("expected an upvalue, found {:?}", other),
+        }
+    }
+
+    pub fn as_class(&self, obj: ObjRef) -> &ObjClass {
+        match self.get(obj) {
+            Obj::Class(class) => class,
+            other => panic!("expected a class, found {:?}", other),
+        }
+    }
+
+    pub fn as_instance(&self, obj: ObjRef) -> &ObjInstance {
+        match self.get(obj) {
+            Obj::Instance(instance) => instance,
+            other => panic!("expected an instance, found {:?}", other),
+        }
+    }
+
+    pub fn as_list(&self, obj: ObjRef) -> &Vec<Value> {
+        match self.get(obj) {
+            Obj::List(elements) => elements,
+            other => panic!("expected a list, found {:?}", other),
+        }
+    }
+
+    pub fn as_map(&self, obj: ObjRef) -> &LoxMap<ObjRef, Value> {
+        match self.get(obj) {
+            Obj::Map(map) => map,
+            other => panic!("expected a map, found {:?}", other),
+        }
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    /// Whether allocating another `size` bytes would go over
+    /// [`GcConfig::max_heap`].
+    pub fn exceeds_limit(&self, size: usize) -> bool {
+        self.config
+            .max_heap
+            .is_some_and(|max| self.bytes_allocated + size > max)
+    }
+
+    /// Updates the size charged for `obj` after it has grown or shrunk in
+    /// place, e.g. an instance gaining a field.
+    pub fn recharge(&mut self, obj: ObjRef) {
+        let entry = self.entries[obj.0]
+            .as_mut()
+            .expect("use of a collected object");
+        let size = entry.obj.size();
+        self.bytes_allocated = self.bytes_allocated - entry.size + size;
+        entry.size = size;
+    }
+
+    /// Whether the VM should collect before its next allocation.
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let Some(obj) = value.as_obj() {
+            self.mark_object(obj);
+        }
+    }
+
+    pub fn mark_object(&mut self, obj: ObjRef) {
+        let entry = self.entries[obj.0]
+            .as_mut()
+            .expect("marking a collected object");
+        if entry.marked {
+            return;
+        }
+        entry.marked = true;
+        self.gray.push(obj);
+    }
+
+    /// Finishes a collection once the caller has marked every root: traces
+    /// everything reachable from them, then frees the rest.
+    pub fn collect(&mut self) {
+        self.trace_references();
+        self.sweep();
+        self.next_gc =
+            (self.bytes_allocated * self.config.growth_factor).max(self.config.initial_threshold);
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(obj) = self.gray.pop() {
+            self.blacken(obj);
+        }
+    }
+
+    fn blacken(&mut self, obj: ObjRef) {
+        let children: Vec<Value> = match self.get(obj) {
+            Obj::String(_)
+            | Obj::Upvalue(ObjUpvalue::Open(_))
+            | Obj::Native(_)
+            | Obj::Introspection(_) => return,
+            Obj::Function(function) => function.chunk.constants.clone(),
+            Obj::Closure(closure) => std::iter::once(closure.function)
+                .chain(closure.upvalues.iter().copied())
+                .map(Value::obj)
+                .collect(),
+            Obj::Upvalue(ObjUpvalue::Closed(value)) => vec![*value],
+            Obj::Class(class) => std::iter::once(class.name)
+                .chain(class.superclass)
+                .map(Value::obj)
+                .chain(table_entries(&class.methods))
+                .chain(table_entries(&class.class_methods))
+                .chain(class.field_slots.keys().copied().map(Value::obj))
+                .collect(),
+            Obj::Instance(instance) => std::iter::once(Value::obj(instance.class))
+                .chain(instance.fields.iter().flatten().copied())
+                .collect(),
+            Obj::BoundMethod(bound) => vec![bound.receiver, Value::obj(bound.method)],
+            Obj::List(elements) => elements.clone(),
+            Obj::Map(map) => map
+                .entries()
+                .flat_map(|&(key, value)| [map_key_value(key), value])
+                .collect(),
+            Obj::NativeMethod(bound) => vec![Value::obj(bound.receiver)],
+        };
+        for child in children {
+            self.mark_value(child);
+        }
+    }
+
+    fn sweep(&mut self) {
+        for (index, slot) in self.entries.iter_mut().enumerate() {
+            match slot {
+                Some(entry) if entry.marked => entry.marked = false,
+                Some(entry) => {
+                    if let Obj::String(s) = &entry.obj {
+                        self.strings.remove(s);
+                    }
+                    self.bytes_allocated -= entry.size;
+                    *slot = None;
+                    self.free.push(index);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Wraps `value` so it can be printed with the heap it refers into.
+    pub fn display(&self, value: Value) -> DisplayValue<'_> {
+        DisplayValue { heap: self, value }
+    }
+
+    /// Writes `[1, "a", 3]` or `{"a": 1, "b": 2}`, given the lists and maps
+    /// `obj` is inside of. Strings in it are quoted. One that contains itself
+    /// shows as `[...]` or `{...}` where it comes round again.
+    fn write_collection(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        obj: ObjRef,
+        enclosing: &mut Vec<ObjRef>,
+    ) -> std::fmt::Result {
+        let (open, close) = match self.get(obj) {
+            Obj::List(_) => ("[", "]"),
+            _ => ("{", "}"),
+        };
+        if enclosing.contains(&obj) {
+            return write!(f, "{}...{}", open, close);
+        }
+        enclosing.push(obj);
+        write!(f, "{}", open)?;
+        let items: Vec<(Option<Value>, Value)> = match self.get(obj) {
+            Obj::List(elements) => elements.iter().map(|&element| (None, element)).collect(),
+            Obj::Map(map) => map
+                .entries()
+                .map(|&(key, value)| (Some(map_key_value(key)), value))
+                .collect(),
+            other => panic!("expected a list or map, found {:?}", other),
+        };
+        for (i, (key, value)) in items.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            if let Some(key) = key {
+                self.write_element(f, key, enclosing)?;
+                write!(f, ": ")?;
+            }
+            self.write_element(f, value, enclosing)?;
+        }
+        enclosing.pop();
+        write!(f, "{}", close)
+    }
+
+    /// Writes a key or element of a collection: quoted if it's a string.
+    fn write_element(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        value: Value,
+        enclosing: &mut Vec<ObjRef>,
+    ) -> std::fmt::Result {
+        match value.as_obj().map(|obj| (obj, self.get(obj))) {
+            Some((_, Obj::String(s))) => write!(f, "{}", quote(s)),
+            Some((inner, Obj::List(_) | Obj::Map(_))) => self.write_collection(f, inner, enclosing),
+            _ => write!(f, "{}", self.display(value)),
+        }
+    }
+}
+
+/// The VM value of a map key.
+pub fn map_key_value(key: MapKey<ObjRef>) -> Value {
+    match key {
+        MapKey::Number(bits) => Value::number(f64::from_bits(bits)),
+        MapKey::String(s) => Value::obj(s),
+    }
+}
+
+/// The keys and values of a name-keyed table, all of which a collection
+/// must keep alive.
+fn table_entries(table: &HashMap<ObjRef, Value>) -> impl Iterator<Item = Value> + '_ {
+    table
+        .iter()
+        .flat_map(|(&name, &value)| [Value::obj(name), value])
+}
+
+pub struct DisplayValue<'a> {
+    heap: &'a Heap,
+    value: Value,
+}
+
+impl Display for DisplayValue<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(obj) = self.value.as_obj() {
+            return match self.heap.get(obj) {
+                Obj::String(s) => write!(f, "{}", s),
+                Obj::Function(function) if function.name.is_empty() => write!(f, "<script>"),
+                Obj::Function(function) => write!(f, "<fn {}>", function.name),
+                Obj::Closure(closure) => {
+                    write!(f, "{}", self.heap.display(Value::obj(closure.function)))
+                }
+                Obj::Upvalue(_) => write!(f, "upvalue"),
+                Obj::Native(_) | Obj::Introspection(_) => write!(f, "<native fn>"),
+                Obj::Class(class) => write!(f, "{}", self.heap.display(Value::obj(class.name))),
+                Obj::Instance(instance) => {
+                    let class = self.heap.as_class(instance.class);
+                    write!(f, "{} instance", self.heap.display(Value::obj(class.name)))
+                }
+                Obj::BoundMethod(bound) => {
+                    write!(f, "{}", self.heap.display(Value::obj(bound.method)))
+                }
+                Obj::List(_) | Obj::Map(_) => self.heap.write_collection(f, obj, &mut Vec::new()),
+                Obj::NativeMethod(_) => write!(f, "<native fn>"),
+            };
+        }
+        match (self.value.as_bool(), self.value.as_number()) {
+            (Some(b), _) => write!(f, "{}", b),
+            (_, Some(n)) => write!(f, "{}", format_number(n)),
+            _ => write!(f, "nil"),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! The `.loxc` format: a compiled script saved to disk so it can be run
+//! later without scanning, parsing, or compiling it again.
+//!
+//! A file is [`MAGIC`], the [`VERSION`] as a little-endian `u16`, a
+//! checksum of the rest of the file as a `u32`, then the script function.
+//! A function is its name, arity, upvalue count, code, line runs, inline
+//! cache count, the locals to suggest for undefined globals by offset, and
+//! constants, each constant tagged with its kind.
+//! Functions nested in the constants are written out in full in their
+//! place. A line run's span is a flag byte, then the span if the flag is 1.
+//! All integers are little-endian `u32`s and strings are a length followed
+//! by UTF-8.
+//!
+//! The checksum only catches accidental damage. Since the VM trusts its
+//! bytecode, loading also [verifies](verifier) every function, so that a
+//! file put together by hand is rejected rather than crashing the VM.
+
+use std::fmt::Display;
+
+use crate::{
+    lexer::Span,
+    parser::MAX_ARGUMENTS,
+    vm::{
+        chunk::{Chunk, InlineCache, LineRun},
+        compiler::MAX_UPVALUES,
+        heap::{Heap, ObjRef},
+        object::{Obj, ObjFunction},
+        value::Value,
+        verifier,
+    },
+};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+
+/// Bump whenever the layout or the instruction set changes, so older files
+/// are rejected instead of misread.
+pub const VERSION: u16 = 9;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// The file doesn't start with [`MAGIC`].
+    NotBytecode,
+    /// The file was written by a different version of the format.
+    UnsupportedVersion(u16),
+    /// The file ends partway through.
+    Truncated,
+    /// The file's contents don't match its checksum.
+    Corrupted,
+    /// The file's contents don't make sense, e.g. an unknown constant tag or
+    /// a jump out of the code.
+    Malformed(&'static str),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::NotBytecode => write!(f, "Not a compiled Lox file."),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Compiled with bytecode version {}, but this build runs version {}.",
+                version, VERSION
+            ),
+            LoadError::Truncated => write!(f, "Compiled file is truncated."),
+            LoadError::Corrupted => write!(f, "Compiled file is corrupted."),
+            LoadError::Malformed(what) => write!(f, "Compiled file is malformed: {}.", what),
+        }
+    }
+}
+
+/// Whether `bytes` looks like a `.loxc` file rather than source code.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Serializes the script function `function` and everything nested in it.
+pub fn write(heap: &Heap, function: ObjRef) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_function(heap, heap.as_function(function), &mut body);
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&checksum(&body).to_le_bytes());
+    out.extend(body);
+    out
+}
+
+/// 32-bit FNV-1a, which is enough to notice a damaged or cut-off file.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn write_function(heap: &Heap, function: &ObjFunction, out: &mut Vec<u8>) {
+    write_str(&function.name, out);
+    write_u32(function.arity, out);
+    write_u32(function.upvalue_count, out);
+
+    let chunk = &function.chunk;
+    write_u32(chunk.code.len(), out);
+    out.extend_from_slice(&chunk.code);
+    write_u32(chunk.lines.len(), out);
+    for run in &chunk.lines {
+        write_u32(run.start, out);
+        write_u32(run.line, out);
+        match run.span {
+            Some(span) => {
+                out.push(1);
+                write_u32(span.start, out);
+                write_u32(span.len, out);
+                write_u32(span.column, out);
+            }
+            None => out.push(0),
+        }
+    }
+    write_u32(chunk.caches.len(), out);
+    // Sorted, so compiling the same script always writes the same file.
+    let mut near_locals: Vec<_> = chunk.near_locals.iter().collect();
+    near_locals.sort();
+    write_u32(near_locals.len(), out);
+    for (&offset, local) in near_locals {
+        write_u32(offset, out);
+        write_str(local, out);
+    }
+
+    write_u32(chunk.constants.len(), out);
+    for &constant in &chunk.constants {
+        if let Some(n) = constant.as_number() {
+            out.push(TAG_NUMBER);
+            out.extend_from_slice(&n.to_bits().to_le_bytes());
+            continue;
+        }
+        let obj = constant.as_obj().expect("constants are numbers or objects");
+        match heap.get(obj) {
+            Obj::String(s) => {
+                out.push(TAG_STRING);
+                write_str(s, out);
+            }
+            Obj::Function(nested) => {
+                out.push(TAG_FUNCTION);
+                write_function(heap, nested, out);
+            }
+            other => unreachable!("{:?} in a constant pool", other),
+        }
+    }
+}
+
+fn write_u32(n: usize, out: &mut Vec<u8>) {
+    let n = u32::try_from(n).expect("chunk too large to serialize");
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_str(s: &str, out: &mut Vec<u8>) {
+    write_u32(s.len(), out);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Deserializes a script function written by [`write()`] into `heap`. Like
+/// the compiler, this allocates without collecting.
+pub fn read(heap: &mut Heap, bytes: &[u8]) -> Result<ObjRef, LoadError> {
+    if !is_bytecode(bytes) {
+        return Err(LoadError::NotBytecode);
+    }
+    let mut reader = Reader {
+        bytes,
+        position: MAGIC.len(),
+    };
+    let version = u16::from_le_bytes(reader.take_array()?);
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let expected = u32::from_le_bytes(reader.take_array()?);
+    if checksum(&bytes[reader.position..]) != expected {
+        return Err(LoadError::Corrupted);
+    }
+
+    let function = reader.function(heap)?;
+    if reader.position != bytes.len() {
+        return Err(LoadError::Malformed("trailing bytes"));
+    }
+    // The script is called with no arguments, and its closure is made
+    // without any upvalues.
+    if function.arity != 0 || function.upvalue_count != 0 {
+        return Err(LoadError::Malformed("script with parameters or upvalues"));
+    }
+    Ok(heap.alloc(Obj::Function(function)))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn function(&mut self, heap: &mut Heap) -> Result<ObjFunction, LoadError> {
+        let name = self.string()?;
+        let arity = self.u32()?;
+        let upvalue_count = self.u32()?;
+        // The compiler never goes past these, and the verifier tracks a
+        // stack slot for every parameter.
+        if arity > MAX_ARGUMENTS {
+            return Err(LoadError::Malformed("too many parameters"));
+        }
+        if upvalue_count > MAX_UPVALUES {
+            return Err(LoadError::Malformed("too many upvalues"));
+        }
+
+        let mut chunk = Chunk::new();
+        let code_len = self.u32()?;
+        chunk.code = self.take(code_len)?.to_vec();
+        for _ in 0..self.u32()? {
+            let start = self.u32()?;
+            let line = self.u32()?;
+            let span = match self.take_array::<1>()?[0] {
+                0 => None,
+                1 => Some(Span {
+                    start: self.u32()?,
+                    len: self.u32()?,
+                    column: self.u32()?,
+                }),
+                _ => return Err(LoadError::Malformed("unknown span flag")),
+            };
+            chunk.lines.push(LineRun { start, line, span });
+        }
+        // Every instruction with a cache takes up more than one byte.
+        let cache_count = self.u32()?;
+        if cache_count > chunk.code.len() {
+            return Err(LoadError::Malformed("more inline caches than code"));
+        }
+        chunk.caches = vec![InlineCache::Empty; cache_count];
+        for _ in 0..self.u32()? {
+            let offset = self.u32()?;
+            let local = self.string()?;
+            chunk.near_locals.insert(offset, local);
+        }
+
+        for _ in 0..self.u32()? {
+            let constant = match self.take_array::<1>()?[0] {
+                TAG_NUMBER => Value::number(f64::from_bits(u64::from_le_bytes(self.take_array()?))),
+                TAG_STRING => {
+                    let s = self.string()?;
+                    Value::obj(heap.intern(&s))
+                }
+                TAG_FUNCTION => {
+                    let function = self.function(heap)?;
+                    Value::obj(heap.alloc(Obj::Function(function)))
+                }
+                _ => return Err(LoadError::Malformed("unknown constant tag")),
+            };
+            chunk.constants.push(constant);
+        }
+
+        let function = ObjFunction {
+            arity,
+            upvalue_count,
+            chunk,
+            name,
+        };
+        verifier::verify(heap, &function).map_err(LoadError::Malformed)?;
+        Ok(function)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], LoadError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or(LoadError::Truncated)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let bytes = self.take(N)?;
+        Ok(bytes.try_into().expect("took exactly N bytes"))
+    }
+
+    fn u32(&mut self) -> Result<usize, LoadError> {
+        Ok(u32::from_le_bytes(self.take_array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| LoadError::Malformed("invalid UTF-8 in a string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse_source, vm::Vm};
+
+    fn compile(source: &str) -> Vec<u8> {
+        let ast = parse_source(source.to_string(), false).unwrap();
+        Vm::new().compile_to_bytecode(&ast).unwrap()
+    }
+
+    /// Overwrites the `u32` after `marker` with `value`, and fixes up the
+    /// checksum so only the change is left to catch.
+    fn patch(bytes: &mut [u8], marker: &[u8], value: u32) {
+        let at = bytes
+            .windows(marker.len())
+            .position(|window| window == marker)
+            .unwrap()
+            + marker.len();
+        bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        let body = MAGIC.len() + 2 + 4;
+        let sum = checksum(&bytes[body..]);
+        bytes[body - 4..body].copy_from_slice(&sum.to_le_bytes());
+    }
+
+    #[test]
+    fn rejects_a_nested_function_with_too_many_parameters() {
+        let mut bytes = compile("fun f(a) {}");
+        // The constant holding `f`, then its arity.
+        patch(&mut bytes, &[TAG_FUNCTION, 1, 0, 0, 0, b'f'], 2_000_000_000);
+        let result = read(&mut Heap::default(), &bytes);
+        assert_eq!(result, Err(LoadError::Malformed("too many parameters")));
+    }
+
+    #[test]
+    fn rejects_a_nested_function_with_too_many_upvalues() {
+        let mut bytes = compile("fun f(a) {}");
+        let marker = [TAG_FUNCTION, 1, 0, 0, 0, b'f', 1, 0, 0, 0];
+        patch(&mut bytes, &marker, 2_000_000_000);
+        let result = read(&mut Heap::default(), &bytes);
+        assert_eq!(result, Err(LoadError::Malformed("too many upvalues")));
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let bytes = compile("fun f(a) { return a; }");
+        assert!(read(&mut Heap::default(), &bytes).is_ok());
+    }
+}
@@ -0,0 +1,1974 @@
+pub mod chunk;
+pub mod compiler;
+pub mod disassembler;
+pub mod heap;
+pub mod loxc;
+pub mod object;
+pub mod opcode;
+pub mod value;
+pub mod verifier;
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    diagnostic,
+    error::{Fault, Limit, LoxError, TraceFrame},
+    intern::intern,
+    interpreter::{CLOCK_CHECK_INTERVAL, DEFAULT_MAX_CALL_DEPTH},
+    introspect::{self, Introspection},
+    list::{self, ListMethod, Returned},
+    map::{self, LoxMap, MapKey, MapMethod},
+    native::{natives, Capability, Deadline, Input, NativeFunction, TypedNative, CONSTANTS},
+    parser::Ast,
+    string::{self, Argument, StringMethod},
+    suggest,
+    value::{shift_left, shift_right, to_integer, ToLox},
+};
+
+use self::{
+    chunk::{InlineCache, LONG_OPERAND_BYTES, NO_CACHE},
+    compiler::Compiler,
+    heap::{GcConfig, Heap, ObjRef},
+    loxc::LoadError,
+    object::{
+        NativeMethod, Obj, ObjBoundMethod, ObjClass, ObjClosure, ObjInstance, ObjNativeMethod,
+        ObjUpvalue,
+    },
+    opcode::OpCode,
+    value::Value,
+};
+
+struct CallFrame {
+    closure: ObjRef,
+    function: ObjRef,
+    ip: usize,
+    /// Index of the frame's first stack slot (the callee itself).
+    slots: usize,
+}
+
+/// Settings for a [`Vm`].
+#[derive(Debug, Clone, Copy)]
+pub struct VmOptions {
+    pub gc: GcConfig,
+    /// Calls nested deeper than this are a "Stack overflow." error.
+    pub max_call_depth: usize,
+    /// Print every instruction to stderr as it runs, after the contents of
+    /// the stack.
+    pub trace_execution: bool,
+    /// Stop each run after this many instructions with a [`Limit::Steps`]
+    /// error.
+    pub max_steps: Option<u64>,
+    /// Stop each run that takes longer than this with a [`Limit::Time`]
+    /// error.
+    pub timeout: Option<Duration>,
+    /// Whether natives that read and write files are defined.
+    pub allow_fs: bool,
+    /// Whether natives that make network connections are defined.
+    pub allow_net: bool,
+    /// Whether natives that start processes, read the environment, or exit
+    /// are defined.
+    pub allow_process: bool,
+}
+
+impl VmOptions {
+    /// The default options, minus every [`Capability`].
+    pub fn sandboxed() -> Self {
+        Self {
+            allow_fs: false,
+            allow_net: false,
+            allow_process: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Fs => self.allow_fs,
+            Capability::Net => self.allow_net,
+            Capability::Process => self.allow_process,
+        }
+    }
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        Self {
+            gc: GcConfig::default(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            trace_execution: false,
+            max_steps: None,
+            timeout: None,
+            allow_fs: true,
+            allow_net: true,
+            allow_process: true,
+        }
+    }
+}
+
+/// What a property name turned out to mean on an instance.
+enum PropertyLookup {
+    Field(Value),
+    Method(ObjRef),
+    Missing,
+}
+
+/// Stack-based bytecode virtual machine, an alternative backend to the
+/// tree-walking [`Interpreter`](crate::interpreter::Interpreter).
+pub struct Vm {
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    /// Each global's slot in `global_values`.
+    globals: HashMap<ObjRef, usize>,
+    global_values: Vec<Value>,
+    /// Upvalues still pointing at live stack slots, so closures capturing
+    /// the same variable share one upvalue.
+    open_upvalues: Vec<ObjRef>,
+    /// The interned name initializers are looked up by.
+    init_string: ObjRef,
+    max_frames: usize,
+    trace_execution: bool,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    /// Instructions executed in the current run before the latest batch.
+    steps: u64,
+    /// How many instructions the latest batch allowed, and how many of them
+    /// are left. The limits are only checked between batches, which keeps
+    /// the cost per instruction down to a decrement.
+    batch: u64,
+    batch_left: u64,
+    /// When the current run has to stop by, given a timeout.
+    deadline: Deadline,
+    /// Set from elsewhere, e.g. a signal handler, to stop the current run.
+    interrupt: Option<Arc<AtomicBool>>,
+    /// Where `print` writes.
+    stdout: Box<dyn Write>,
+    /// Where [`Vm::report`] and the execution trace write.
+    stderr: Box<dyn Write>,
+    /// Where `readLine` reads.
+    stdin: Input,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::with_gc_config(GcConfig::default())
+    }
+
+    pub fn with_gc_config(gc: GcConfig) -> Self {
+        Self::with_options(VmOptions {
+            gc,
+            ..VmOptions::default()
+        })
+    }
+
+    pub fn with_options(options: VmOptions) -> Self {
+        let mut heap = Heap::new(options.gc);
+        let init_string = heap.intern("init");
+        let mut vm = Self {
+            heap,
+            stack: Vec::with_capacity(u8::MAX as usize),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            global_values: Vec::new(),
+            open_upvalues: Vec::new(),
+            init_string,
+            max_frames: options.max_call_depth,
+            trace_execution: options.trace_execution,
+            max_steps: options.max_steps,
+            timeout: options.timeout,
+            steps: 0,
+            batch: 0,
+            batch_left: 0,
+            deadline: Deadline::default(),
+            interrupt: None,
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            stdin: Input::default(),
+        };
+        let allowed = |capability| options.allows(capability);
+        for native in natives(allowed, &vm.stdin, &vm.deadline) {
+            vm.define_native(native);
+        }
+        for introspection in Introspection::ALL {
+            let name = vm.heap.intern(introspection.name());
+            let function = vm.heap.alloc(Obj::Introspection(introspection));
+            vm.define_global(name, Value::obj(function));
+        }
+        for (name, value) in CONSTANTS {
+            let name = vm.heap.intern(name);
+            vm.define_global(name, Value::number(value));
+        }
+        vm
+    }
+
+    /// Sends `print` output to `stdout`, and error reports and the execution
+    /// trace to `stderr`, instead of the process's own.
+    pub fn set_output(&mut self, stdout: Box<dyn Write>, stderr: Box<dyn Write>) {
+        self.stdout = stdout;
+        self.stderr = stderr;
+    }
+
+    /// Has `readLine` and `readNumber` read from `stdin` instead of the
+    /// process's own, e.g. to feed a script canned input.
+    pub fn set_input(&mut self, stdin: Box<dyn BufRead>) {
+        *self.stdin.borrow_mut() = Some(stdin);
+    }
+
+    /// Stops the run in progress with an "Interrupted." error once `flag`
+    /// is set, clearing it again. Like the limits, it is checked every thousand
+    /// or so instructions, so it can be set from another thread or a signal
+    /// handler.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
+
+    /// Writes `error` and its backtrace to this VM's stderr.
+    pub fn report(&mut self, error: &LoxError) {
+        let _ = error.report_to(&mut self.stderr);
+    }
+
+    /// Defines a global function `name` that scripts can call with `arity`
+    /// arguments to run `function`, as
+    /// [`Interpreter::register_native`](crate::interpreter::Interpreter::register_native)
+    /// does.
+    pub fn register_native<R: ToLox>(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[crate::value::Value]) -> Result<R, String> + 'static,
+    ) {
+        self.define_native(NativeFunction::new(name, arity, function));
+    }
+
+    /// Defines a global function `name` that converts its arguments for
+    /// `function`, as
+    /// [`Interpreter::register_typed_native`](crate::interpreter::Interpreter::register_typed_native)
+    /// does.
+    pub fn register_typed_native<Args, F: TypedNative<Args> + 'static>(
+        &mut self,
+        name: &str,
+        function: F,
+    ) {
+        self.define_native(NativeFunction::typed(name, function));
+    }
+
+    /// Natives are defined outside of any run, so like the compiler this
+    /// allocates without collecting or checking the heap limit.
+    fn define_native(&mut self, native: NativeFunction) {
+        let name = self.heap.intern(&native.name);
+        let function = self.heap.alloc(Obj::Native(native));
+        self.define_global(name, Value::obj(function));
+    }
+
+    /// Each global variable's name and its value as `print` would show it,
+    /// sorted by name.
+    pub fn globals(&self) -> Vec<(String, String)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .iter()
+            .map(|(&name, &slot)| {
+                let value = self.global_values[slot];
+                (
+                    self.heap.display(Value::obj(name)).to_string(),
+                    self.heap.display(value).to_string(),
+                )
+            })
+            .collect();
+        globals.sort();
+        globals
+    }
+
+    /// The properties that can be accessed on the global variable `name`:
+    /// an instance's fields and methods, a class's class methods, or a
+    /// list's methods. Sorted, and empty if `name` holds anything else.
+    pub fn members(&self, name: &str) -> Vec<String> {
+        let value = self
+            .globals
+            .iter()
+            .find(|(&global, _)| self.heap.as_string(Value::obj(global)) == Some(name))
+            .map(|(_, &slot)| self.global_values[slot]);
+        let names: Vec<ObjRef> = match value
+            .and_then(|value| value.as_obj())
+            .map(|obj| self.heap.get(obj))
+        {
+            Some(Obj::Instance(instance)) => {
+                let class = self.heap.as_class(instance.class);
+                let fields = class
+                    .field_slots
+                    .iter()
+                    .filter(|(_, &slot)| matches!(instance.fields.get(slot), Some(Some(_))));
+                fields
+                    .map(|(&name, _)| name)
+                    .chain(class.methods.keys().copied())
+                    .collect()
+            }
+            Some(Obj::Class(class)) => class.class_methods.keys().copied().collect(),
+            Some(Obj::List(_)) => {
+                return ListMethod::ALL
+                    .iter()
+                    .map(|method| method.name().to_string())
+                    .collect()
+            }
+            Some(Obj::Map(_)) => {
+                return MapMethod::ALL
+                    .iter()
+                    .map(|method| method.name().to_string())
+                    .collect()
+            }
+            Some(Obj::String(_)) => {
+                return StringMethod::ALL
+                    .iter()
+                    .map(|method| method.name().to_string())
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let mut members: Vec<String> = names
+            .into_iter()
+            .map(|name| self.heap.display(Value::obj(name)).to_string())
+            .collect();
+        members.sort();
+        members.dedup();
+        members
+    }
+
+    /// Bytes currently held by objects on the VM's heap.
+    pub fn heap_size(&self) -> usize {
+        self.heap.bytes_allocated()
+    }
+
+    fn define_global(&mut self, name: ObjRef, value: Value) {
+        match self.globals.get(&name) {
+            Some(&slot) => self.global_values[slot] = value,
+            None => {
+                self.globals.insert(name, self.global_values.len());
+                self.global_values.push(value);
+            }
+        }
+    }
+
+    /// Compiles `ast` and returns a listing of the script's bytecode
+    /// and that of every function it declares, without running anything.
+    pub fn disassemble(&mut self, ast: &Ast) -> Result<String, LoxError> {
+        let function = Compiler::new(&mut self.heap, ast).compile()?;
+        let chunk = &self.heap.as_function(function).chunk;
+        Ok(disassembler::disassemble_all(chunk, "<script>", &self.heap))
+    }
+
+    /// Compiles `ast` and serializes the bytecode in the [`loxc`] format.
+    pub fn compile_to_bytecode(&mut self, ast: &Ast) -> Result<Vec<u8>, LoxError> {
+        let function = Compiler::new(&mut self.heap, ast).compile()?;
+        Ok(loxc::write(&self.heap, function))
+    }
+
+    /// Loads a script serialized by [`Vm::compile_to_bytecode`], ready to
+    /// pass to [`Vm::run_script`].
+    pub fn load_bytecode(&mut self, bytes: &[u8]) -> Result<ObjRef, LoadError> {
+        loxc::read(&mut self.heap, bytes)
+    }
+
+    /// Compiles `ast` to bytecode and runs it.
+    pub fn interpret(&mut self, ast: &Ast) -> Result<(), LoxError> {
+        let function = self.compile(ast)?;
+        self.run_script(function)
+    }
+
+    /// Compiles `ast` to a script function, ready to pass to
+    /// [`Vm::run_script`].
+    pub fn compile(&mut self, ast: &Ast) -> Result<ObjRef, LoxError> {
+        Compiler::new(&mut self.heap, ast).compile()
+    }
+
+    /// Runs a compiled script function.
+    pub fn run_script(&mut self, function: ObjRef) -> Result<(), LoxError> {
+        self.steps = 0;
+        self.batch = 0;
+        self.batch_left = 0;
+        self.deadline
+            .set(self.timeout.map(|timeout| Instant::now() + timeout));
+
+        self.stack.push(Value::obj(function));
+        let result = self
+            .alloc(Obj::Closure(ObjClosure {
+                function,
+                upvalues: Vec::new(),
+            }))
+            .and_then(|closure| {
+                self.pop();
+                self.stack.push(Value::obj(closure));
+                self.call(closure, 0)
+            })
+            .and_then(|()| self.run());
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn run(&mut self) -> Result<(), LoxError> {
+        loop {
+            if self.trace_execution {
+                self.trace_instruction();
+            }
+            if self.batch_left == 0 {
+                self.next_batch()?;
+            }
+            self.batch_left -= 1;
+            let instruction = self.read_byte();
+            let op = OpCode::try_from(instruction)
+                .map_err(|byte| self.runtime_error("R0016", format!("Unknown opcode {}.", byte)))?;
+
+            match op {
+                OpCode::Constant | OpCode::ConstantLong => {
+                    let constant = self.read_constant(op);
+                    self.push(constant);
+                }
+                OpCode::Nil => self.push(Value::nil()),
+                OpCode::True => self.push(Value::bool(true)),
+                OpCode::False => self.push(Value::bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::Dup => {
+                    let count = self.read_byte() as usize;
+                    let first = self.stack.len() - count;
+                    self.stack.extend_from_within(first..);
+                }
+                OpCode::GetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let value = self.stack[self.frame().slots + slot];
+                    self.push(value);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let index = self.frame().slots + slot;
+                    self.stack[index] = self.peek(0);
+                }
+                OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                    let name = self.read_string(op);
+                    let cache = self.read_short() as usize;
+                    let slot = match self.cache(cache) {
+                        InlineCache::Global(slot) => slot,
+                        _ => {
+                            let Some(&slot) = self.globals.get(&name) else {
+                                return Err(self.undefined_variable(name));
+                            };
+                            self.set_cache(cache, InlineCache::Global(slot));
+                            slot
+                        }
+                    };
+                    self.push(self.global_values[slot]);
+                }
+                OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                    let name = self.read_string(op);
+                    let value = self.pop();
+                    self.define_global(name, value);
+                }
+                OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                    let name = self.read_string(op);
+                    let value = self.peek(0);
+                    match self.globals.get(&name) {
+                        Some(&slot) => self.global_values[slot] = value,
+                        None => return Err(self.undefined_variable(name)),
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let upvalue = self.heap.as_closure(self.frame().closure).upvalues[slot];
+                    let value = match self.heap.as_upvalue(upvalue) {
+                        ObjUpvalue::Open(index) => self.stack[*index],
+                        ObjUpvalue::Closed(value) => *value,
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let upvalue = self.heap.as_closure(self.frame().closure).upvalues[slot];
+                    let value = self.peek(0);
+                    match self.heap.get_mut(upvalue) {
+                        Obj::Upvalue(ObjUpvalue::Open(index)) => {
+                            let index = *index;
+                            self.stack[index] = value;
+                        }
+                        Obj::Upvalue(ObjUpvalue::Closed(closed)) => *closed = value,
+                        other => panic!("expected an upvalue, found {:?}", other),
+                    }
+                }
+                OpCode::GetProperty | OpCode::GetPropertyLong => {
+                    let name = self.read_string(op);
+                    let cache = self.read_short() as usize;
+                    let value = self.property(self.peek(0), name, cache)?;
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::SetProperty | OpCode::SetPropertyLong => {
+                    let name = self.read_string(op);
+                    let Some(instance) = self
+                        .peek(1)
+                        .as_obj()
+                        .filter(|&obj| matches!(self.heap.get(obj), Obj::Instance(_)))
+                    else {
+                        return Err(
+                            self.runtime_error("R0006", "Only instances have fields.".to_string())
+                        );
+                    };
+                    let value = self.pop();
+                    let slot = self.field_slot(self.heap.as_instance(instance).class, name);
+                    let mut grew = false;
+                    if let Obj::Instance(instance) = self.heap.get_mut(instance) {
+                        if instance.fields.len() <= slot {
+                            instance.fields.resize(slot + 1, None);
+                            grew = true;
+                        }
+                        instance.fields[slot] = Some(value);
+                    }
+                    if grew {
+                        self.heap.recharge(instance);
+                        self.make_room(0)?;
+                    }
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::GetSuper | OpCode::GetSuperLong => {
+                    let name = self.read_string(op);
+                    let superclass = self.class_operand(self.peek(0), "Superclass")?;
+                    let bound = self.bind_method(self.peek(1), superclass, name)?;
+                    self.pop();
+                    self.pop();
+                    self.push(bound);
+                }
+                OpCode::BuildList => {
+                    let count = self.read_byte() as usize;
+                    let first = self.stack.len() - count;
+                    // The elements stay on the stack while the list is allocated.
+                    let list = self.alloc(Obj::List(self.stack[first..].to_vec()))?;
+                    self.stack.truncate(first);
+                    self.push(Value::obj(list));
+                }
+                OpCode::BuildMap => {
+                    let count = self.read_byte() as usize;
+                    let first = self.stack.len() - 2 * count;
+                    let mut entries = LoxMap::default();
+                    for pair in (first..self.stack.len()).step_by(2) {
+                        let key = self.map_key(self.stack[pair])?;
+                        entries.insert(key, self.stack[pair + 1]);
+                    }
+                    // The entries stay on the stack while the map is allocated.
+                    let map = self.alloc(Obj::Map(entries))?;
+                    self.stack.truncate(first);
+                    self.push(Value::obj(map));
+                }
+                OpCode::GetIndex => {
+                    let object = self.indexed(self.peek(1))?;
+                    let value = match self.heap.get(object) {
+                        Obj::List(elements) => {
+                            let index = list::element(self.peek(0).as_number(), elements.len())
+                                .map_err(|fault| self.fault(fault))?;
+                            elements[index]
+                        }
+                        Obj::Map(entries) => {
+                            let key = self.map_key(self.peek(0))?;
+                            match entries.get(&key) {
+                                Some(&value) => value,
+                                None => {
+                                    let key = key.describe(|&s| {
+                                        self.name(s).unwrap_or_default().to_string()
+                                    });
+                                    return Err(self.fault(map::missing_key(&key)));
+                                }
+                            }
+                        }
+                        Obj::String(s) => {
+                            let c = string::char_at(s, self.peek(0).as_number())
+                                .map_err(|fault| self.fault(fault))?;
+                            Value::obj(self.intern(c.to_string())?)
+                        }
+                        _ => unreachable!("indexed objects are lists, maps, or strings"),
+                    };
+                    self.pop();
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::GetSlice => {
+                    let object = self.peek(2);
+                    let bound = |bound: Value, default: usize| {
+                        if bound.is_nil() {
+                            Some(default as f64)
+                        } else {
+                            bound.as_number()
+                        }
+                    };
+                    let value = match object.as_obj().map(|obj| self.heap.get(obj)) {
+                        Some(Obj::List(elements)) => {
+                            let (start, end) =
+                                (bound(self.peek(1), 0), bound(self.peek(0), elements.len()));
+                            let slice = list::slice(elements, start, end)
+                                .map_err(|fault| self.fault(fault))?;
+                            Value::obj(self.alloc(Obj::List(slice))?)
+                        }
+                        Some(Obj::String(s)) => {
+                            let (start, end) = (
+                                bound(self.peek(1), 0),
+                                bound(self.peek(0), s.chars().count()),
+                            );
+                            let slice = string::substring(s, start, end)
+                                .map_err(|fault| self.fault(fault))?;
+                            Value::obj(self.intern(slice.to_string())?)
+                        }
+                        _ => return Err(self.fault(list::not_sliceable())),
+                    };
+                    self.stack.truncate(self.stack.len() - 3);
+                    self.push(value);
+                }
+                OpCode::SetIndex => {
+                    let object = self.indexed(self.peek(2))?;
+                    let index = self.peek(1);
+                    let value = self.peek(0);
+                    match self.heap.get(object) {
+                        Obj::List(elements) => {
+                            let index = list::element(index.as_number(), elements.len())
+                                .map_err(|fault| self.fault(fault))?;
+                            if let Obj::List(elements) = self.heap.get_mut(object) {
+                                elements[index] = value;
+                            }
+                        }
+                        Obj::String(_) => return Err(self.fault(string::immutable())),
+                        _ => {
+                            let key = self.map_key(index)?;
+                            if let Obj::Map(entries) = self.heap.get_mut(object) {
+                                entries.insert(key, value);
+                            }
+                            // Charge for any growth while the map is sure to
+                            // still be alive.
+                            self.heap.recharge(object);
+                        }
+                    }
+                    self.stack.truncate(self.stack.len() - 3);
+                    self.push(value);
+                    self.make_room(0)?;
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::bool(a == b));
+                }
+                OpCode::Greater => {
+                    let (a, b) = self.number_operands()?;
+                    self.push(Value::bool(a > b));
+                }
+                OpCode::Less => {
+                    let (a, b) = self.number_operands()?;
+                    self.push(Value::bool(a < b));
+                }
+                OpCode::Add => self.add()?,
+                OpCode::Subtract => {
+                    let (a, b) = self.number_operands()?;
+                    self.push(Value::number(a - b));
+                }
+                OpCode::Multiply => {
+                    let (a, b) = self.number_operands()?;
+                    self.push(Value::number(a * b));
+                }
+                OpCode::Divide => {
+                    let (a, b) = self.number_operands()?;
+                    self.push(Value::number(a / b));
+                }
+                OpCode::Modulo => {
+                    let (a, b) = self.number_operands()?;
+                    self.push(Value::number(a % b));
+                }
+                OpCode::Power => {
+                    let (a, b) = self.number_operands()?;
+                    self.push(Value::number(a.powf(b)));
+                }
+                OpCode::BitAnd => {
+                    let (a, b) = self.integer_operands()?;
+                    self.push(Value::number((a & b) as f64));
+                }
+                OpCode::BitOr => {
+                    let (a, b) = self.integer_operands()?;
+                    self.push(Value::number((a | b) as f64));
+                }
+                OpCode::BitXor => {
+                    let (a, b) = self.integer_operands()?;
+                    self.push(Value::number((a ^ b) as f64));
+                }
+                OpCode::ShiftLeft => {
+                    let (a, b) = self.integer_operands()?;
+                    let Some(n) = shift_left(a, b) else {
+                        return Err(self.shift_error());
+                    };
+                    self.push(Value::number(n as f64));
+                }
+                OpCode::ShiftRight => {
+                    let (a, b) = self.integer_operands()?;
+                    let Some(n) = shift_right(a, b) else {
+                        return Err(self.shift_error());
+                    };
+                    self.push(Value::number(n as f64));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::bool(value.is_falsey()));
+                }
+                OpCode::Negate => {
+                    let Some(n) = self.peek(0).as_number() else {
+                        return Err(
+                            self.runtime_error("R0001", "Operand must be a number.".to_string())
+                        );
+                    };
+                    self.pop();
+                    self.push(Value::number(-n));
+                }
+                OpCode::BitNot => {
+                    let Some(n) = self.peek(0).as_number().and_then(to_integer) else {
+                        return Err(
+                            self.runtime_error("R0011", "Operand must be an integer.".to_string())
+                        );
+                    };
+                    self.pop();
+                    self.push(Value::number(!n as f64));
+                }
+                OpCode::ToString => {
+                    let value = self.peek(0);
+                    if self.heap.as_string(value).is_none() {
+                        let string = self.heap.display(value).to_string();
+                        let string = self.intern(string)?;
+                        self.pop();
+                        self.push(Value::obj(string));
+                    }
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    if let Err(error) = writeln!(self.stdout, "{}", self.heap.display(value)) {
+                        return Err(
+                            self.runtime_error("R0016", format!("Error writing output: {}", error))
+                        );
+                    }
+                }
+                OpCode::Jump => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip += offset as usize;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip -= offset as usize;
+                }
+                OpCode::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    self.call_value(self.peek(arg_count), arg_count)?;
+                }
+                OpCode::TailCall => {
+                    let arg_count = self.read_byte() as usize;
+                    self.tail_call_value(self.peek(arg_count), arg_count)?;
+                }
+                OpCode::Invoke
+                | OpCode::InvokeLong
+                | OpCode::TailInvoke
+                | OpCode::TailInvokeLong => {
+                    let name = self.read_string(op);
+                    let arg_count = self.read_byte() as usize;
+                    let cache = self.read_short() as usize;
+                    let tail = matches!(op, OpCode::TailInvoke | OpCode::TailInvokeLong);
+                    self.invoke(name, arg_count, cache, tail)?;
+                }
+                OpCode::SuperInvoke
+                | OpCode::SuperInvokeLong
+                | OpCode::TailSuperInvoke
+                | OpCode::TailSuperInvokeLong => {
+                    let name = self.read_string(op);
+                    let arg_count = self.read_byte() as usize;
+                    let superclass = self.pop();
+                    let superclass = self.class_operand(superclass, "Superclass")?;
+                    let receiver = self.peek(arg_count);
+                    let tail = matches!(op, OpCode::TailSuperInvoke | OpCode::TailSuperInvokeLong);
+                    self.invoke_from_class(receiver, superclass, name, arg_count, tail)?;
+                }
+                OpCode::Closure | OpCode::ClosureLong => {
+                    let function = self
+                        .read_constant(op)
+                        .as_obj()
+                        .expect("closure constant is not a function");
+                    let upvalue_count = self.heap.as_function(function).upvalue_count;
+                    let closure = self.alloc(Obj::Closure(ObjClosure {
+                        function,
+                        upvalues: Vec::with_capacity(upvalue_count),
+                    }))?;
+                    // Keep the closure reachable while its upvalues are allocated.
+                    self.push(Value::obj(closure));
+
+                    for _ in 0..upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().slots + index)?
+                        } else {
+                            self.heap.as_closure(self.frame().closure).upvalues[index]
+                        };
+                        if let Obj::Closure(closure) = self.heap.get_mut(closure) {
+                            closure.upvalues.push(upvalue);
+                        }
+                    }
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("no frame to return from");
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.push(result);
+                }
+                OpCode::Class | OpCode::ClassLong => {
+                    let name = self.read_string(op);
+                    let class = self.alloc(Obj::Class(ObjClass {
+                        name,
+                        superclass: None,
+                        methods: HashMap::new(),
+                        class_methods: HashMap::new(),
+                        field_slots: HashMap::new(),
+                    }))?;
+                    self.push(Value::obj(class));
+                }
+                OpCode::Inherit => {
+                    let superclass = self.class_operand(self.peek(1), "Superclass")?;
+                    let subclass = self.class_operand(self.peek(0), "Subclass")?;
+                    // Copy the inherited methods down now; the subclass's own
+                    // are attached afterwards, overriding them.
+                    let inherited = self.heap.as_class(superclass);
+                    let methods = inherited.methods.clone();
+                    let class_methods = inherited.class_methods.clone();
+                    self.pop();
+                    if let Obj::Class(subclass) = self.heap.get_mut(subclass) {
+                        subclass.superclass = Some(superclass);
+                        subclass.methods.extend(methods);
+                        subclass.class_methods.extend(class_methods);
+                    }
+                }
+                OpCode::Method
+                | OpCode::MethodLong
+                | OpCode::ClassMethod
+                | OpCode::ClassMethodLong => {
+                    let name = self.read_string(op);
+                    let method = self.peek(0);
+                    if !method
+                        .as_obj()
+                        .is_some_and(|obj| matches!(self.heap.get(obj), Obj::Closure(_)))
+                    {
+                        return Err(
+                            self.runtime_error("R0016", "Method must be a function.".to_string())
+                        );
+                    }
+                    let class = self.class_operand(self.peek(1), "Method's class")?;
+                    if let Obj::Class(class) = self.heap.get_mut(class) {
+                        let table = match op {
+                            OpCode::Method | OpCode::MethodLong => &mut class.methods,
+                            _ => &mut class.class_methods,
+                        };
+                        table.insert(name, method);
+                    }
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    /// Prints the stack and the instruction about to run, like clox's
+    /// `DEBUG_TRACE_EXECUTION`.
+    fn trace_instruction(&mut self) {
+        let mut out = " ".repeat(10);
+        for &value in &self.stack {
+            out.push_str(&format!("[ {} ]", self.heap.display(value)));
+        }
+        out.push('\n');
+        let chunk = &self.heap.as_function(self.frame().function).chunk;
+        disassembler::disassemble_instruction(chunk, self.frame().ip, &self.heap, &mut out);
+        let _ = self.stderr.write_all(out.as_bytes());
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), LoxError> {
+        if let Some(obj) = callee.as_obj() {
+            match self.heap.get(obj) {
+                Obj::Closure(_) => return self.call(obj, arg_count),
+                Obj::Native(native) => return self.call_native(native.clone(), arg_count),
+                Obj::Class(_) => return self.call_class(obj, arg_count),
+                Obj::BoundMethod(bound) => {
+                    let method = bound.method;
+                    self.set_receiver(bound.receiver, arg_count);
+                    return self.call(method, arg_count);
+                }
+                Obj::NativeMethod(bound) => {
+                    return self.call_native_method(bound.receiver, bound.method, arg_count)
+                }
+                &Obj::Introspection(introspection) => {
+                    return self.call_introspection(introspection, arg_count)
+                }
+                _ => {}
+            }
+        }
+        Err(self.runtime_error("R0007", "Can only call functions and classes.".to_string()))
+    }
+
+    /// Calls `callee` in place of the current frame, so tail recursion runs
+    /// in constant space. Only closures and bound methods get frames;
+    /// anything else is called normally, and the `Return` following the
+    /// `TailCall` hands back its result.
+    fn tail_call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), LoxError> {
+        let Some(obj) = callee.as_obj() else {
+            return self.call_value(callee, arg_count);
+        };
+        let closure = match self.heap.get(obj) {
+            Obj::Closure(_) => obj,
+            Obj::BoundMethod(bound) => {
+                let method = bound.method;
+                self.set_receiver(bound.receiver, arg_count);
+                method
+            }
+            _ => return self.call_value(callee, arg_count),
+        };
+        self.tail_call(closure, arg_count)
+    }
+
+    /// Runs `closure` in place of the current frame.
+    fn tail_call(&mut self, closure: ObjRef, arg_count: usize) -> Result<(), LoxError> {
+        let function = self.heap.as_closure(closure).function;
+        self.check_arity(function, arg_count)?;
+
+        // Drop the caller's locals and slide the callee and its arguments
+        // down into their place.
+        let slots = self.frame().slots;
+        self.close_upvalues(slots);
+        let callee_slot = self.stack.len() - arg_count - 1;
+        self.stack.drain(slots..callee_slot);
+
+        let frame = self.frame_mut();
+        frame.closure = closure;
+        frame.function = function;
+        frame.ip = 0;
+        Ok(())
+    }
+
+    fn call(&mut self, closure: ObjRef, arg_count: usize) -> Result<(), LoxError> {
+        let function = self.heap.as_closure(closure).function;
+        self.check_arity(function, arg_count)?;
+
+        // The script's own frame doesn't count as a call.
+        if self.frames.len() > self.max_frames {
+            return Err(self.runtime_error("R0010", "Stack overflow.".to_string()));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slots: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    /// Creates an instance of `class` in place of the callee and runs its
+    /// initializer, if it has one, on the arguments.
+    fn call_class(&mut self, class: ObjRef, arg_count: usize) -> Result<(), LoxError> {
+        // The class is still in the callee slot while the instance is allocated.
+        let instance = self.alloc(Obj::Instance(ObjInstance {
+            class,
+            fields: Vec::new(),
+        }))?;
+        self.set_receiver(Value::obj(instance), arg_count);
+
+        let initializer = self.heap.as_class(class).methods.get(&self.init_string);
+        match initializer.and_then(|initializer| initializer.as_obj()) {
+            Some(initializer) => self.call(initializer, arg_count),
+            None if arg_count != 0 => Err(self.runtime_error(
+                "R0008",
+                format!("Expected 0 arguments but got {}.", arg_count),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Puts `receiver` in the callee's slot, where the method about to be
+    /// called finds it as `this`.
+    fn set_receiver(&mut self, receiver: Value, arg_count: usize) {
+        let slot = self.stack.len() - arg_count - 1;
+        self.stack[slot] = receiver;
+    }
+
+    /// Reads property `name` of `receiver`: a field, or else a method bound
+    /// to it. Classes only have class methods, and lists only their
+    /// built-in methods.
+    fn property(&mut self, receiver: Value, name: ObjRef, cache: usize) -> Result<Value, LoxError> {
+        let class = match receiver.as_obj().map(|obj| (obj, self.heap.get(obj))) {
+            Some((instance, Obj::Instance(_))) => {
+                return match self.instance_property(instance, name, cache) {
+                    PropertyLookup::Field(value) => Ok(value),
+                    PropertyLookup::Method(method) => self.bind(receiver, method),
+                    PropertyLookup::Missing => Err(self.undefined_property(receiver, name)),
+                };
+            }
+            Some((object, Obj::List(_) | Obj::Map(_) | Obj::String(_))) => {
+                let method = self.native_method(receiver, name)?;
+                let bound = ObjNativeMethod {
+                    receiver: object,
+                    method,
+                };
+                return Ok(Value::obj(self.alloc(Obj::NativeMethod(bound))?));
+            }
+            Some((class, Obj::Class(_))) => class,
+            _ => {
+                return Err(
+                    self.runtime_error("R0006", "Only instances have properties.".to_string())
+                )
+            }
+        };
+        self.bind_method(receiver, class, name)
+    }
+
+    /// Calls method `name` on the receiver below the arguments, without
+    /// allocating a bound method for it, and for a `tail` call in place of
+    /// the current frame. A field holding a function is called instead if
+    /// there is one.
+    fn invoke(
+        &mut self,
+        name: ObjRef,
+        arg_count: usize,
+        cache: usize,
+        tail: bool,
+    ) -> Result<(), LoxError> {
+        let receiver = self.peek(arg_count);
+        let class = match receiver.as_obj().map(|obj| (obj, self.heap.get(obj))) {
+            Some((instance, Obj::Instance(_))) => {
+                return match self.instance_property(instance, name, cache) {
+                    PropertyLookup::Field(field) => {
+                        self.set_receiver(field, arg_count);
+                        if tail {
+                            self.tail_call_value(field, arg_count)
+                        } else {
+                            self.call_value(field, arg_count)
+                        }
+                    }
+                    PropertyLookup::Method(method) => self.call_method(method, arg_count, tail),
+                    PropertyLookup::Missing => Err(self.undefined_property(receiver, name)),
+                };
+            }
+            Some((object, Obj::List(_) | Obj::Map(_) | Obj::String(_))) => {
+                let method = self.native_method(receiver, name)?;
+                return self.call_native_method(object, method, arg_count);
+            }
+            Some((class, Obj::Class(_))) => class,
+            _ => {
+                return Err(
+                    self.runtime_error("R0006", "Only instances have properties.".to_string())
+                )
+            }
+        };
+        self.invoke_from_class(receiver, class, name, arg_count, tail)
+    }
+
+    /// The method of list, map, or string `receiver` called `name`.
+    fn native_method(&self, receiver: Value, name: ObjRef) -> Result<NativeMethod, LoxError> {
+        let object = receiver.as_obj().expect("receiver is an object");
+        let method = self
+            .name(name)
+            .and_then(|name| match self.heap.get(object) {
+                Obj::List(_) => ListMethod::named(name).map(NativeMethod::List),
+                Obj::Map(_) => MapMethod::named(name).map(NativeMethod::Map),
+                _ => StringMethod::named(name).map(NativeMethod::String),
+            });
+        method.ok_or_else(|| self.undefined_property(receiver, name))
+    }
+
+    /// Runs `method` on `receiver` with the arguments at the top of the
+    /// stack, and replaces them and the callee with its result.
+    fn call_native_method(
+        &mut self,
+        receiver: ObjRef,
+        method: NativeMethod,
+        arg_count: usize,
+    ) -> Result<(), LoxError> {
+        if arg_count != method.arity() {
+            return Err(self.runtime_error(
+                "R0008",
+                format!(
+                    "Expected {} arguments but got {}.",
+                    method.arity(),
+                    arg_count
+                ),
+            ));
+        }
+
+        let first = self.stack.len() - arg_count;
+        let arguments = self.stack[first..].to_vec();
+        let result = match method {
+            NativeMethod::List(method) => {
+                let Obj::List(elements) = self.heap.get_mut(receiver) else {
+                    panic!("list method of a non-list");
+                };
+                let returned = method.call(elements, &arguments, |argument| argument.as_number());
+                let returned = returned.map_err(|fault| self.fault(fault))?;
+                // Charge for any growth while the list is sure to still be
+                // alive.
+                self.heap.recharge(receiver);
+                match returned {
+                    Returned::Nil => Value::nil(),
+                    Returned::Number(n) => Value::number(n),
+                    Returned::Element(element) => element,
+                    Returned::List(elements) => Value::obj(self.alloc(Obj::List(elements))?),
+                }
+            }
+            NativeMethod::Map(method) => self.call_map_method(receiver, method, &arguments)?,
+            NativeMethod::String(method) => {
+                self.call_string_method(receiver, method, &arguments)?
+            }
+        };
+        self.stack.truncate(first - 1);
+        self.push(result);
+        self.make_room(0)
+    }
+
+    fn call_map_method(
+        &mut self,
+        map: ObjRef,
+        method: MapMethod,
+        arguments: &[Value],
+    ) -> Result<Value, LoxError> {
+        let elements = match method {
+            MapMethod::Delete => {
+                let key = self.map_key(arguments[0])?;
+                let Obj::Map(entries) = self.heap.get_mut(map) else {
+                    panic!("map method of a non-map");
+                };
+                return Ok(Value::bool(entries.remove(&key).is_some()));
+            }
+            MapMethod::Has => {
+                let key = self.map_key(arguments[0])?;
+                return Ok(Value::bool(self.heap.as_map(map).contains(&key)));
+            }
+            MapMethod::Length => return Ok(Value::number(self.heap.as_map(map).len() as f64)),
+            MapMethod::Keys => self
+                .heap
+                .as_map(map)
+                .entries()
+                .map(|&(key, _)| heap::map_key_value(key))
+                .collect(),
+            MapMethod::Values => self
+                .heap
+                .as_map(map)
+                .entries()
+                .map(|&(_, value)| value)
+                .collect(),
+        };
+        Ok(Value::obj(self.alloc(Obj::List(elements))?))
+    }
+
+    fn call_string_method(
+        &mut self,
+        s: ObjRef,
+        method: StringMethod,
+        arguments: &[Value],
+    ) -> Result<Value, LoxError> {
+        let arguments: Vec<Argument> = arguments
+            .iter()
+            .map(
+                |&argument| match (self.heap.as_string(argument), argument.as_number()) {
+                    (Some(s), _) => Argument::String(s),
+                    (None, Some(n)) => Argument::Number(n),
+                    (None, None) => Argument::Other,
+                },
+            )
+            .collect();
+        let s = self.name(s).expect("string method of a non-string");
+        let returned = method
+            .call(s, &arguments)
+            .map_err(|fault| self.fault(fault))?;
+        Ok(match returned {
+            string::Returned::Number(n) => Value::number(n),
+            string::Returned::String(s) => Value::obj(self.intern(s)?),
+            string::Returned::List(parts) => {
+                // The parts stay on the stack while the list is allocated.
+                let first = self.stack.len();
+                for part in parts {
+                    let part = self.intern(part)?;
+                    self.push(Value::obj(part));
+                }
+                let list = self.alloc(Obj::List(self.stack[first..].to_vec()))?;
+                self.stack.truncate(first);
+                Value::obj(list)
+            }
+        })
+    }
+
+    /// The list, map, or string `object[...]` indexes, or an error if
+    /// `object` isn't one.
+    fn indexed(&self, object: Value) -> Result<ObjRef, LoxError> {
+        let indexable = |obj: &ObjRef| {
+            matches!(
+                self.heap.get(*obj),
+                Obj::List(_) | Obj::Map(_) | Obj::String(_)
+            )
+        };
+        object.as_obj().filter(indexable).ok_or_else(|| {
+            self.runtime_error(
+                "R0018",
+                "Can only index lists, maps, and strings.".to_string(),
+            )
+        })
+    }
+
+    /// The map key `value` is, or an error if it can't be one.
+    fn map_key(&self, value: Value) -> Result<MapKey<ObjRef>, LoxError> {
+        let string = value
+            .as_obj()
+            .filter(|_| self.heap.as_string(value).is_some());
+        MapKey::new(value.as_number(), string).map_err(|fault| self.fault(fault))
+    }
+
+    /// Works out what property `name` of `instance` is, trusting inline
+    /// cache `cache` when it was filled for the instance's class.
+    fn instance_property(
+        &mut self,
+        instance: ObjRef,
+        name: ObjRef,
+        cache: usize,
+    ) -> PropertyLookup {
+        let class = self.heap.as_instance(instance).class;
+        let (slot, method) = match self.cache(cache) {
+            InlineCache::Property {
+                class: cached,
+                slot,
+                method,
+                field_count,
+            } if cached == class
+                && (slot.is_some()
+                    || self.heap.as_class(class).field_slots.len() == field_count) =>
+            {
+                (slot, method)
+            }
+            _ => {
+                let class_obj = self.heap.as_class(class);
+                let slot = class_obj.field_slots.get(&name).copied();
+                let method = class_obj.methods.get(&name).and_then(|m| m.as_obj());
+                let field_count = class_obj.field_slots.len();
+                self.set_cache(
+                    cache,
+                    InlineCache::Property {
+                        class,
+                        slot,
+                        method,
+                        field_count,
+                    },
+                );
+                (slot, method)
+            }
+        };
+
+        let fields = &self.heap.as_instance(instance).fields;
+        match (
+            slot.and_then(|slot| fields.get(slot).copied().flatten()),
+            method,
+        ) {
+            (Some(value), _) => PropertyLookup::Field(value),
+            (None, Some(method)) => PropertyLookup::Method(method),
+            (None, None) => PropertyLookup::Missing,
+        }
+    }
+
+    /// The slot instances of `class` keep field `name` in, assigning the
+    /// next free one if no instance has set it before.
+    fn field_slot(&mut self, class: ObjRef, name: ObjRef) -> usize {
+        let Obj::Class(class) = self.heap.get_mut(class) else {
+            panic!("instance of a non-class");
+        };
+        let next = class.field_slots.len();
+        *class.field_slots.entry(name).or_insert(next)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        receiver: Value,
+        class: ObjRef,
+        name: ObjRef,
+        arg_count: usize,
+        tail: bool,
+    ) -> Result<(), LoxError> {
+        match self.find_method(receiver, class, name) {
+            Some(method) => self.call_method(method, arg_count, tail),
+            None => Err(self.undefined_property(receiver, name)),
+        }
+    }
+
+    /// Runs `method` in a frame of its own, or for a `tail` call in place of
+    /// the current one.
+    fn call_method(
+        &mut self,
+        method: ObjRef,
+        arg_count: usize,
+        tail: bool,
+    ) -> Result<(), LoxError> {
+        if tail {
+            self.tail_call(method, arg_count)
+        } else {
+            self.call(method, arg_count)
+        }
+    }
+
+    /// Looks up method `name` of `class` and binds it to `receiver`, which
+    /// must be on the stack so it survives the allocation.
+    fn bind_method(
+        &mut self,
+        receiver: Value,
+        class: ObjRef,
+        name: ObjRef,
+    ) -> Result<Value, LoxError> {
+        let Some(method) = self.find_method(receiver, class, name) else {
+            return Err(self.undefined_property(receiver, name));
+        };
+        self.bind(receiver, method)
+    }
+
+    /// Same reachability rule as [`Vm::bind_method`].
+    fn bind(&mut self, receiver: Value, method: ObjRef) -> Result<Value, LoxError> {
+        let bound = self.alloc(Obj::BoundMethod(ObjBoundMethod { receiver, method }))?;
+        Ok(Value::obj(bound))
+    }
+
+    /// Looks up a method of `class` to call on `receiver`. When the receiver
+    /// is itself a class, as `this` is in a class method, that means one of
+    /// the class methods.
+    fn find_method(&self, receiver: Value, class: ObjRef, name: ObjRef) -> Option<ObjRef> {
+        let class = self.heap.as_class(class);
+        let on_class = receiver
+            .as_obj()
+            .is_some_and(|obj| matches!(self.heap.get(obj), Obj::Class(_)));
+        let methods = if on_class {
+            &class.class_methods
+        } else {
+            &class.methods
+        };
+        methods.get(&name).and_then(|method| method.as_obj())
+    }
+
+    /// `value` as a class, or an error saying what, `what`, has to be one.
+    /// Compiled code always has a class where it expects one, but a loaded
+    /// `.loxc` file might not.
+    fn class_operand(&self, value: Value, what: &str) -> Result<ObjRef, LoxError> {
+        value
+            .as_obj()
+            .filter(|&obj| matches!(self.heap.get(obj), Obj::Class(_)))
+            .ok_or_else(|| self.runtime_error("R0009", format!("{} must be a class.", what)))
+    }
+
+    fn check_arity(&self, function: ObjRef, arg_count: usize) -> Result<(), LoxError> {
+        let arity = self.heap.as_function(function).arity;
+        if arg_count != arity {
+            return Err(self.runtime_error(
+                "R0008",
+                format!("Expected {} arguments but got {}.", arity, arg_count),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs a native on the arguments at the top of the stack and replaces
+    /// them and the callee with its result.
+    fn call_native(&mut self, native: NativeFunction, arg_count: usize) -> Result<(), LoxError> {
+        if arg_count != native.arity {
+            return Err(self.runtime_error(
+                "R0008",
+                format!("Expected {} arguments but got {}.", native.arity, arg_count),
+            ));
+        }
+
+        let first = self.stack.len() - arg_count;
+        let arguments = self.stack[first..]
+            .iter()
+            .map(|&argument| self.native_argument(argument, &mut Vec::new()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = (native.function)(&arguments).map_err(|fault| self.fault(fault))?;
+        // A native can take far longer than a batch of instructions.
+        if self.past_deadline() {
+            let error = LoxError::limit_exceeded(self.frame_line(self.frame()), Limit::Time);
+            return Err(self.with_backtrace(error));
+        }
+        let result = self.native_result(result)?;
+        self.stack.truncate(first - 1);
+        self.push(result);
+        Ok(())
+    }
+
+    fn call_introspection(
+        &mut self,
+        introspection: Introspection,
+        arg_count: usize,
+    ) -> Result<(), LoxError> {
+        if arg_count != introspection.arity() {
+            return Err(self.runtime_error(
+                "R0008",
+                format!(
+                    "Expected {} arguments but got {}.",
+                    introspection.arity(),
+                    arg_count
+                ),
+            ));
+        }
+
+        let first = self.stack.len() - arg_count;
+        let result = match introspection {
+            Introspection::IsInstance => {
+                let class = self.stack[first + 1]
+                    .as_obj()
+                    .filter(|&obj| matches!(self.heap.get(obj), Obj::Class(_)))
+                    .ok_or_else(|| self.fault(introspect::not_a_class()))?;
+                let instance = self.stack[first].as_obj().map(|obj| self.heap.get(obj));
+                let mut next = match instance {
+                    Some(Obj::Instance(instance)) => Some(instance.class),
+                    _ => None,
+                };
+                let mut found = false;
+                while let Some(ancestor) = next {
+                    found |= ancestor == class;
+                    next = self.heap.as_class(ancestor).superclass;
+                }
+                Value::bool(found)
+            }
+            Introspection::Str => {
+                let text = self.heap.display(self.stack[first]).to_string();
+                Value::obj(self.intern(text)?)
+            }
+            Introspection::Type => {
+                let name = self.type_name(self.stack[first]);
+                Value::obj(self.intern(name.to_string())?)
+            }
+        };
+        self.stack.truncate(first - 1);
+        self.push(result);
+        Ok(())
+    }
+
+    /// What `type(value)` returns for `value`.
+    fn type_name(&self, value: Value) -> &'static str {
+        let Some(obj) = value.as_obj() else {
+            return if value.is_nil() {
+                "nil"
+            } else if value.as_bool().is_some() {
+                "bool"
+            } else {
+                "number"
+            };
+        };
+        match self.heap.get(obj) {
+            Obj::String(_) => "string",
+            Obj::Class(_) => "class",
+            Obj::Instance(_) => "instance",
+            Obj::List(_) => "list",
+            Obj::Map(_) => "map",
+            Obj::Function(_)
+            | Obj::Closure(_)
+            | Obj::Upvalue(_)
+            | Obj::Native(_)
+            | Obj::BoundMethod(_)
+            | Obj::NativeMethod(_)
+            | Obj::Introspection(_) => "function",
+        }
+    }
+
+    /// Converts a VM value to the representation natives work with, given
+    /// the lists and maps it is inside of. Lists and maps are copied, so a
+    /// native changing one doesn't change the script's.
+    fn native_argument(
+        &self,
+        value: Value,
+        enclosing: &mut Vec<ObjRef>,
+    ) -> Result<crate::value::Value, LoxError> {
+        if let Some(b) = value.as_bool() {
+            return Ok(crate::value::Value::Bool(b));
+        }
+        if let Some(n) = value.as_number() {
+            return Ok(crate::value::Value::Number(n));
+        }
+        if let Some(s) = self.heap.as_string(value) {
+            return Ok(crate::value::Value::String(intern(s)));
+        }
+        if value.is_nil() {
+            return Ok(crate::value::Value::Nil);
+        }
+        match value.as_obj().map(|obj| (obj, self.heap.get(obj))) {
+            Some((obj, Obj::List(_) | Obj::Map(_))) if enclosing.contains(&obj) => Err(self
+                .runtime_error(
+                    "R0016",
+                    "Native functions can't take a list or map that contains itself.".to_string(),
+                )),
+            Some((obj, Obj::List(elements))) => {
+                enclosing.push(obj);
+                let elements = elements
+                    .iter()
+                    .map(|&element| self.native_argument(element, enclosing))
+                    .collect::<Result<Vec<_>, _>>()?;
+                enclosing.pop();
+                Ok(crate::value::Value::List(Rc::new(RefCell::new(elements))))
+            }
+            Some((obj, Obj::Map(entries))) => {
+                enclosing.push(obj);
+                let mut map = LoxMap::default();
+                for &(key, value) in entries.entries() {
+                    let key = match key {
+                        MapKey::Number(bits) => MapKey::Number(bits),
+                        MapKey::String(s) => {
+                            MapKey::String(intern(self.name(s).unwrap_or_default()))
+                        }
+                    };
+                    map.insert(key, self.native_argument(value, enclosing)?);
+                }
+                enclosing.pop();
+                Ok(crate::value::Value::Map(Rc::new(RefCell::new(map))))
+            }
+            _ => Err(self.runtime_error(
+                "R0016",
+                "Native functions only take nil, booleans, numbers, strings, lists, and maps."
+                    .to_string(),
+            )),
+        }
+    }
+
+    /// Converts a native's result back to a VM value.
+    fn native_result(&mut self, value: crate::value::Value) -> Result<Value, LoxError> {
+        match value {
+            crate::value::Value::Nil => Ok(Value::nil()),
+            crate::value::Value::Bool(b) => Ok(Value::bool(b)),
+            crate::value::Value::Number(n) => Ok(Value::number(n)),
+            crate::value::Value::String(s) => Ok(Value::obj(self.intern(s.to_string())?)),
+            crate::value::Value::List(elements) => {
+                // The elements stay on the stack while the list is allocated.
+                let first = self.stack.len();
+                for element in elements.borrow().iter() {
+                    let element = self.native_result(element.clone())?;
+                    self.push(element);
+                }
+                let list = self.alloc(Obj::List(self.stack[first..].to_vec()))?;
+                self.stack.truncate(first);
+                Ok(Value::obj(list))
+            }
+            crate::value::Value::Map(entries) => {
+                // So are the keys and values while the map is.
+                let first = self.stack.len();
+                for (key, value) in entries.borrow().entries() {
+                    let key = match key {
+                        MapKey::Number(bits) => Value::number(f64::from_bits(*bits)),
+                        MapKey::String(s) => Value::obj(self.intern(s.to_string())?),
+                    };
+                    self.push(key);
+                    let value = self.native_result(value.clone())?;
+                    self.push(value);
+                }
+                let mut map = LoxMap::default();
+                for pair in (first..self.stack.len()).step_by(2) {
+                    let key = self.map_key(self.stack[pair])?;
+                    map.insert(key, self.stack[pair + 1]);
+                }
+                let map = self.alloc(Obj::Map(map))?;
+                self.stack.truncate(first);
+                Ok(Value::obj(map))
+            }
+            _ => Err(self.runtime_error(
+                "R0016",
+                "Native functions only return nil, booleans, numbers, strings, lists, and maps."
+                    .to_string(),
+            )),
+        }
+    }
+
+    fn add(&mut self) -> Result<(), LoxError> {
+        let (a, b) = (self.peek(1), self.peek(0));
+        if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+            self.pop();
+            self.pop();
+            self.push(Value::number(a + b));
+            return Ok(());
+        }
+
+        if let (Some(a), Some(b)) = (self.heap.as_string(a), self.heap.as_string(b)) {
+            let result = format!("{}{}", a, b);
+            let result = self.intern(result)?;
+            self.pop();
+            self.pop();
+            self.push(Value::obj(result));
+            return Ok(());
+        }
+
+        let error = self.runtime_error(
+            "R0002",
+            "Operands must be two numbers or two strings.".to_string(),
+        );
+        if self.heap.as_string(a).is_some() || self.heap.as_string(b).is_some() {
+            Err(error.with_help(diagnostic::INTERPOLATE))
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Returns the upvalue for the stack slot at `location`, reusing an open
+    /// one if another closure already captured the same variable.
+    fn capture_upvalue(&mut self, location: usize) -> Result<ObjRef, LoxError> {
+        let existing = self.open_upvalues.iter().copied().find(|&upvalue| {
+            matches!(self.heap.as_upvalue(upvalue), ObjUpvalue::Open(index) if *index == location)
+        });
+        if let Some(upvalue) = existing {
+            return Ok(upvalue);
+        }
+
+        let upvalue = self.alloc(Obj::Upvalue(ObjUpvalue::Open(location)))?;
+        self.open_upvalues.push(upvalue);
+        Ok(upvalue)
+    }
+
+    /// Moves every variable at or above stack slot `last` that a closure
+    /// captured off the stack and into its upvalue.
+    fn close_upvalues(&mut self, last: usize) {
+        let heap = &mut self.heap;
+        let stack = &self.stack;
+        self.open_upvalues.retain(|&upvalue| {
+            let Obj::Upvalue(state) = heap.get_mut(upvalue) else {
+                return false;
+            };
+            match *state {
+                ObjUpvalue::Open(index) if index >= last => {
+                    *state = ObjUpvalue::Closed(stack[index]);
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn number_operands(&mut self) -> Result<(f64, f64), LoxError> {
+        match (self.peek(1).as_number(), self.peek(0).as_number()) {
+            (Some(a), Some(b)) => {
+                self.pop();
+                self.pop();
+                Ok((a, b))
+            }
+            _ => Err(self.runtime_error("R0001", "Operands must be numbers.".to_string())),
+        }
+    }
+
+    /// Like [`Vm::number_operands`], but both operands must also be
+    /// integers.
+    fn integer_operands(&mut self) -> Result<(i64, i64), LoxError> {
+        let a = self.peek(1).as_number().and_then(to_integer);
+        let b = self.peek(0).as_number().and_then(to_integer);
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                self.pop();
+                self.pop();
+                Ok((a, b))
+            }
+            _ => Err(self.runtime_error("R0011", "Operands must be integers.".to_string())),
+        }
+    }
+
+    fn shift_error(&mut self) -> LoxError {
+        self.runtime_error(
+            "R0011",
+            "Shift amount must be between 0 and 63.".to_string(),
+        )
+    }
+
+    /// Interns a string built at runtime, first collecting garbage if an
+    /// allocation is needed and one is due. Any value that must survive has
+    /// to be reachable from the stack, a call frame, or a global beforehand.
+    fn intern(&mut self, s: String) -> Result<ObjRef, LoxError> {
+        if let Some(existing) = self.heap.find_string(&s) {
+            return Ok(existing);
+        }
+        // The heap stores a copy of `s` with no spare capacity.
+        self.make_room(size_of::<Obj>() + s.len())?;
+        Ok(self.heap.intern_new(s))
+    }
+
+    /// Allocates `obj`, first collecting garbage if one is due. The same
+    /// reachability rule as [`Vm::intern`] applies.
+    fn alloc(&mut self, obj: Obj) -> Result<ObjRef, LoxError> {
+        self.make_room(obj.size())?;
+        Ok(self.heap.alloc(obj))
+    }
+
+    /// Collects garbage if one is due or `size` more bytes wouldn't fit
+    /// under the heap limit, failing if they still don't.
+    fn make_room(&mut self, size: usize) -> Result<(), LoxError> {
+        if self.heap.should_collect() || self.heap.exceeds_limit(size) {
+            self.collect_garbage();
+        }
+        if self.heap.exceeds_limit(size) {
+            return Err(self.out_of_memory());
+        }
+        Ok(())
+    }
+
+    fn out_of_memory(&self) -> LoxError {
+        // The script's closure is allocated before its frame exists.
+        if self.frames.is_empty() {
+            return LoxError::limit_exceeded(0, Limit::Memory);
+        }
+        let error = LoxError::limit_exceeded(self.frame_line(self.frame()), Limit::Memory);
+        self.with_backtrace(error)
+    }
+
+    fn collect_garbage(&mut self) {
+        for &value in &self.stack {
+            self.heap.mark_value(value);
+        }
+        for frame in &self.frames {
+            self.heap.mark_object(frame.closure);
+        }
+        for &upvalue in &self.open_upvalues {
+            self.heap.mark_object(upvalue);
+        }
+        for &name in self.globals.keys() {
+            self.heap.mark_object(name);
+        }
+        for &value in &self.global_values {
+            self.heap.mark_value(value);
+        }
+        self.heap.mark_object(self.init_string);
+        self.heap.collect();
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("no active call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("no active call frame")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frames.last_mut().expect("no active call frame");
+        let byte = self.heap.as_function(frame.function).chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn cache(&self, index: usize) -> InlineCache {
+        if index == NO_CACHE as usize {
+            return InlineCache::Empty;
+        }
+        self.heap.as_function(self.frame().function).chunk.caches[index]
+    }
+
+    fn set_cache(&mut self, index: usize, cache: InlineCache) {
+        if index == NO_CACHE as usize {
+            return;
+        }
+        let function = self.frame().function;
+        if let Obj::Function(function) = self.heap.get_mut(function) {
+            function.chunk.caches[index] = cache;
+        }
+    }
+
+    fn read_short(&mut self) -> u16 {
+        let high = self.read_byte();
+        let low = self.read_byte();
+        u16::from_be_bytes([high, low])
+    }
+
+    /// Reads the constant index operand of `op`, one byte or
+    /// [`LONG_OPERAND_BYTES`] wide, and looks it up.
+    fn read_constant(&mut self, op: OpCode) -> Value {
+        let width = if op.is_long() { LONG_OPERAND_BYTES } else { 1 };
+        let index = (0..width).fold(0, |index, _| index << 8 | self.read_byte() as usize);
+        self.heap.as_function(self.frame().function).chunk.constants[index]
+    }
+
+    fn read_string(&mut self, op: OpCode) -> ObjRef {
+        self.read_constant(op)
+            .as_obj()
+            .expect("constant is not a string")
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// The error for reading property `name` of `receiver`, an instance or
+    /// a class, which doesn't have it.
+    fn undefined_property(&self, receiver: Value, name: ObjRef) -> LoxError {
+        let receiver = receiver.as_obj().expect("receiver is an object");
+        let name = self.name(name).unwrap_or_default();
+        let mut properties = Vec::new();
+        match self.heap.get(receiver) {
+            Obj::List(_) => {
+                let methods = ListMethod::ALL.map(ListMethod::name);
+                return self.runtime_error("R0005", suggest::undefined("property", name, methods));
+            }
+            Obj::Map(_) => {
+                let methods = MapMethod::ALL.map(MapMethod::name);
+                return self.runtime_error("R0005", suggest::undefined("property", name, methods));
+            }
+            Obj::String(_) => {
+                let methods = StringMethod::ALL.map(StringMethod::name);
+                return self.runtime_error("R0005", suggest::undefined("property", name, methods));
+            }
+            Obj::Instance(instance) => {
+                let class = self.heap.as_class(instance.class);
+                properties.extend(class.methods.keys());
+                properties.extend(class.field_slots.iter().filter_map(|(&field, &slot)| {
+                    instance.fields.get(slot)?.is_some().then_some(field)
+                }));
+            }
+            Obj::Class(class) => properties.extend(class.class_methods.keys()),
+            _ => {}
+        }
+        let candidates = properties
+            .into_iter()
+            .filter_map(|property| self.name(property));
+        self.runtime_error("R0005", suggest::undefined("property", name, candidates))
+    }
+
+    fn undefined_variable(&self, name: ObjRef) -> LoxError {
+        let frame = self.frame();
+        let near_local = self
+            .heap
+            .as_function(frame.function)
+            .chunk
+            .near_locals
+            .get(&frame.ip)
+            .map(String::as_str);
+        let candidates = self
+            .globals
+            .keys()
+            .filter_map(|&global| self.name(global))
+            .chain(near_local);
+        let name = self.name(name).unwrap_or_default();
+        self.runtime_error("R0003", suggest::undefined("variable", name, candidates))
+    }
+
+    /// The text of `name`, a string object.
+    fn name(&self, name: ObjRef) -> Option<&str> {
+        self.heap.as_string(Value::obj(name))
+    }
+
+    /// Whether the current run has gone on longer than its timeout.
+    fn past_deadline(&self) -> bool {
+        self.deadline
+            .get()
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Checks the limits the VM was given once the current batch of
+    /// instructions has run, failing if one is exceeded, and otherwise
+    /// allows as many more as can run before the next check is due.
+    fn next_batch(&mut self) -> Result<(), LoxError> {
+        self.steps += self.batch;
+        let limit = if self.max_steps.is_some_and(|max| self.steps >= max) {
+            Some(Limit::Steps)
+        } else if self.past_deadline() {
+            Some(Limit::Time)
+        } else {
+            None
+        };
+        if let Some(limit) = limit {
+            let error = LoxError::limit_exceeded(self.frame_line(self.frame()), limit);
+            return Err(self.with_backtrace(error));
+        }
+        if let Some(interrupt) = &self.interrupt {
+            if interrupt.swap(false, Ordering::Relaxed) {
+                return Err(self.runtime_error("R0015", "Interrupted.".to_string()));
+            }
+        }
+
+        let left = self.max_steps.map_or(u64::MAX, |max| max - self.steps);
+        self.batch = if self.deadline.get().is_some() || self.interrupt.is_some() {
+            left.min(CLOCK_CHECK_INTERVAL)
+        } else {
+            left
+        };
+        self.batch_left = self.batch;
+        Ok(())
+    }
+
+    /// Builds an error pointing at the instruction being executed.
+    fn runtime_error(&self, code: &'static str, message: String) -> LoxError {
+        let frame = self.frame();
+        let error = LoxError::runtime(self.frame_line(frame), code, message);
+        let chunk = &self.heap.as_function(frame.function).chunk;
+        let error = match chunk.span_for(frame.ip.saturating_sub(1)) {
+            Some(span) => error.with_span(span),
+            None => error,
+        };
+        self.with_backtrace(error)
+    }
+
+    /// Builds the error for `fault`, as [`runtime_error`](Vm::runtime_error)
+    /// does.
+    fn fault(&self, fault: Fault) -> LoxError {
+        self.runtime_error(fault.code, fault.message)
+    }
+
+    /// Records the calls in progress as `error`'s backtrace.
+    fn with_backtrace(&self, mut error: LoxError) -> LoxError {
+        if let (
+            LoxError::RuntimeError { trace, .. } | LoxError::LimitExceeded { trace, .. },
+            true,
+        ) = (&mut error, self.frames.len() > 1)
+        {
+            *trace = self
+                .frames
+                .iter()
+                .rev()
+                .map(|frame| {
+                    let name = &self.heap.as_function(frame.function).name;
+                    TraceFrame {
+                        // Only the top-level script function is unnamed.
+                        function: (!name.is_empty()).then(|| name.clone()),
+                        line: self.frame_line(frame),
+                    }
+                })
+                .collect();
+        }
+        error
+    }
+
+    /// The line of the instruction `frame` is executing, which for a caller
+    /// is its call.
+    fn frame_line(&self, frame: &CallFrame) -> usize {
+        let chunk = &self.heap.as_function(frame.function).chunk;
+        chunk.line_for(frame.ip.saturating_sub(1))
+    }
+}
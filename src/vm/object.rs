@@ -0,0 +1,141 @@
+use std::{collections::HashMap, mem::size_of};
+
+use crate::{
+    introspect::Introspection,
+    list::ListMethod,
+    map::{LoxMap, MapMethod},
+    native::NativeFunction,
+    string::StringMethod,
+    vm::{
+        chunk::{Chunk, LineRun},
+        heap::ObjRef,
+        value::Value,
+    },
+};
+
+#[derive(Debug, Default)]
+pub struct ObjFunction {
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    pub name: String,
+}
+
+/// A function paired with the variables it captured from enclosing scopes.
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+/// A captured variable. It points at a stack slot while the variable is
+/// still live on the stack, and owns the value once that slot is popped.
+#[derive(Debug)]
+pub enum ObjUpvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// A class. Inherited methods are copied down into `methods` when the
+/// class is declared, so lookups never walk the superclass chain.
+#[derive(Debug)]
+pub struct ObjClass {
+    pub name: ObjRef,
+    pub superclass: Option<ObjRef>,
+    pub methods: HashMap<ObjRef, Value>,
+    /// Methods declared with `class`, called on the class itself.
+    pub class_methods: HashMap<ObjRef, Value>,
+    /// Every field name set on any instance so far, with the slot all
+    /// instances keep it in. Slots are never reused or moved.
+    pub field_slots: HashMap<ObjRef, usize>,
+}
+
+#[derive(Debug)]
+pub struct ObjInstance {
+    pub class: ObjRef,
+    /// Indexed by the class's field slots; `None`, or past the end, where
+    /// this instance hasn't set the field.
+    pub fields: Vec<Option<Value>>,
+}
+
+/// A method closure paired with the receiver it was accessed on, which
+/// becomes `this` when it is called.
+#[derive(Debug)]
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+/// A list's, map's, or string's method accessed without calling it, like
+/// `var push = xs.push;`, which still works on that receiver when called
+/// later.
+#[derive(Debug)]
+pub struct ObjNativeMethod {
+    pub receiver: ObjRef,
+    pub method: NativeMethod,
+}
+
+/// A method built into lists, maps, or strings.
+#[derive(Debug, Clone, Copy)]
+pub enum NativeMethod {
+    List(ListMethod),
+    Map(MapMethod),
+    String(StringMethod),
+}
+
+impl NativeMethod {
+    pub fn arity(self) -> usize {
+        match self {
+            NativeMethod::List(method) => method.arity(),
+            NativeMethod::Map(method) => method.arity(),
+            NativeMethod::String(method) => method.arity(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Obj {
+    String(String),
+    Function(ObjFunction),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Native(NativeFunction),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+    List(Vec<Value>),
+    Map(LoxMap<ObjRef, Value>),
+    NativeMethod(ObjNativeMethod),
+    Introspection(Introspection),
+}
+
+impl Obj {
+    /// Approximate number of bytes owned by this object, used to pace the
+    /// garbage collector.
+    pub fn size(&self) -> usize {
+        size_of::<Obj>()
+            + match self {
+                Obj::String(s) => s.capacity(),
+                Obj::Function(function) => {
+                    function.name.capacity()
+                        + function.chunk.code.capacity()
+                        + function.chunk.constants.capacity() * size_of::<Value>()
+                        + function.chunk.lines.capacity() * size_of::<LineRun>()
+                }
+                Obj::Closure(closure) => closure.upvalues.capacity() * size_of::<ObjRef>(),
+                Obj::Class(class) => {
+                    (class.methods.capacity() + class.class_methods.capacity())
+                        * size_of::<(ObjRef, Value)>()
+                        + class.field_slots.capacity() * size_of::<(ObjRef, usize)>()
+                }
+                Obj::Instance(instance) => instance.fields.capacity() * size_of::<Option<Value>>(),
+                Obj::List(elements) => elements.capacity() * size_of::<Value>(),
+                Obj::Map(map) => map.capacity_bytes(),
+                Obj::Upvalue(_)
+                | Obj::Native(_)
+                | Obj::BoundMethod(_)
+                | Obj::NativeMethod(_)
+                | Obj::Introspection(_) => 0,
+            }
+    }
+}
@@ -0,0 +1,311 @@
+/// A single bytecode instruction. Operands, if any, follow the opcode byte
+/// inline in the chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    ConstantLong,
+    Nil,
+    True,
+    False,
+    Pop,
+    Dup,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    GetGlobalLong,
+    DefineGlobal,
+    DefineGlobalLong,
+    SetGlobal,
+    SetGlobalLong,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    GetPropertyLong,
+    SetProperty,
+    SetPropertyLong,
+    GetSuper,
+    GetSuperLong,
+    BuildList,
+    BuildMap,
+    GetIndex,
+    SetIndex,
+    GetSlice,
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Not,
+    Negate,
+    BitNot,
+    ToString,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    TailCall,
+    Invoke,
+    InvokeLong,
+    SuperInvoke,
+    SuperInvokeLong,
+    TailInvoke,
+    TailInvokeLong,
+    TailSuperInvoke,
+    TailSuperInvokeLong,
+    Closure,
+    ClosureLong,
+    CloseUpvalue,
+    Return,
+    Class,
+    ClassLong,
+    Inherit,
+    Method,
+    MethodLong,
+    ClassMethod,
+    ClassMethodLong,
+}
+
+impl OpCode {
+    /// The clox-style mnemonic, e.g. `OP_CONSTANT`.
+    pub fn name(self) -> &'static str {
+        match self {
+            OpCode::Constant => "OP_CONSTANT",
+            OpCode::ConstantLong => "OP_CONSTANT_LONG",
+            OpCode::Nil => "OP_NIL",
+            OpCode::True => "OP_TRUE",
+            OpCode::False => "OP_FALSE",
+            OpCode::Pop => "OP_POP",
+            OpCode::Dup => "OP_DUP",
+            OpCode::GetLocal => "OP_GET_LOCAL",
+            OpCode::SetLocal => "OP_SET_LOCAL",
+            OpCode::GetGlobal => "OP_GET_GLOBAL",
+            OpCode::GetGlobalLong => "OP_GET_GLOBAL_LONG",
+            OpCode::DefineGlobal => "OP_DEFINE_GLOBAL",
+            OpCode::DefineGlobalLong => "OP_DEFINE_GLOBAL_LONG",
+            OpCode::SetGlobal => "OP_SET_GLOBAL",
+            OpCode::SetGlobalLong => "OP_SET_GLOBAL_LONG",
+            OpCode::GetUpvalue => "OP_GET_UPVALUE",
+            OpCode::SetUpvalue => "OP_SET_UPVALUE",
+            OpCode::GetProperty => "OP_GET_PROPERTY",
+            OpCode::GetPropertyLong => "OP_GET_PROPERTY_LONG",
+            OpCode::SetProperty => "OP_SET_PROPERTY",
+            OpCode::SetPropertyLong => "OP_SET_PROPERTY_LONG",
+            OpCode::GetSuper => "OP_GET_SUPER",
+            OpCode::GetSuperLong => "OP_GET_SUPER_LONG",
+            OpCode::BuildList => "OP_BUILD_LIST",
+            OpCode::BuildMap => "OP_BUILD_MAP",
+            OpCode::GetIndex => "OP_GET_INDEX",
+            OpCode::SetIndex => "OP_SET_INDEX",
+            OpCode::GetSlice => "OP_GET_SLICE",
+            OpCode::Equal => "OP_EQUAL",
+            OpCode::Greater => "OP_GREATER",
+            OpCode::Less => "OP_LESS",
+            OpCode::Add => "OP_ADD",
+            OpCode::Subtract => "OP_SUBTRACT",
+            OpCode::Multiply => "OP_MULTIPLY",
+            OpCode::Divide => "OP_DIVIDE",
+            OpCode::Modulo => "OP_MODULO",
+            OpCode::Power => "OP_POWER",
+            OpCode::BitAnd => "OP_BIT_AND",
+            OpCode::BitOr => "OP_BIT_OR",
+            OpCode::BitXor => "OP_BIT_XOR",
+            OpCode::ShiftLeft => "OP_SHIFT_LEFT",
+            OpCode::ShiftRight => "OP_SHIFT_RIGHT",
+            OpCode::Not => "OP_NOT",
+            OpCode::Negate => "OP_NEGATE",
+            OpCode::BitNot => "OP_BIT_NOT",
+            OpCode::ToString => "OP_TO_STRING",
+            OpCode::Print => "OP_PRINT",
+            OpCode::Jump => "OP_JUMP",
+            OpCode::JumpIfFalse => "OP_JUMP_IF_FALSE",
+            OpCode::Loop => "OP_LOOP",
+            OpCode::Call => "OP_CALL",
+            OpCode::TailCall => "OP_TAIL_CALL",
+            OpCode::Invoke => "OP_INVOKE",
+            OpCode::InvokeLong => "OP_INVOKE_LONG",
+            OpCode::SuperInvoke => "OP_SUPER_INVOKE",
+            OpCode::SuperInvokeLong => "OP_SUPER_INVOKE_LONG",
+            OpCode::TailInvoke => "OP_TAIL_INVOKE",
+            OpCode::TailInvokeLong => "OP_TAIL_INVOKE_LONG",
+            OpCode::TailSuperInvoke => "OP_TAIL_SUPER_INVOKE",
+            OpCode::TailSuperInvokeLong => "OP_TAIL_SUPER_INVOKE_LONG",
+            OpCode::Closure => "OP_CLOSURE",
+            OpCode::ClosureLong => "OP_CLOSURE_LONG",
+            OpCode::CloseUpvalue => "OP_CLOSE_UPVALUE",
+            OpCode::Return => "OP_RETURN",
+            OpCode::Class => "OP_CLASS",
+            OpCode::ClassLong => "OP_CLASS_LONG",
+            OpCode::Inherit => "OP_INHERIT",
+            OpCode::Method => "OP_METHOD",
+            OpCode::MethodLong => "OP_METHOD_LONG",
+            OpCode::ClassMethod => "OP_CLASS_METHOD",
+            OpCode::ClassMethodLong => "OP_CLASS_METHOD_LONG",
+        }
+    }
+
+    /// Whether this instruction's constant index operand is
+    /// [`LONG_OPERAND_BYTES`](crate::vm::chunk::LONG_OPERAND_BYTES) wide
+    /// rather than one byte.
+    pub fn is_long(self) -> bool {
+        matches!(
+            self,
+            OpCode::ConstantLong
+                | OpCode::GetGlobalLong
+                | OpCode::DefineGlobalLong
+                | OpCode::SetGlobalLong
+                | OpCode::ClosureLong
+                | OpCode::GetPropertyLong
+                | OpCode::SetPropertyLong
+                | OpCode::GetSuperLong
+                | OpCode::InvokeLong
+                | OpCode::SuperInvokeLong
+                | OpCode::TailInvokeLong
+                | OpCode::TailSuperInvokeLong
+                | OpCode::ClassLong
+                | OpCode::MethodLong
+                | OpCode::ClassMethodLong
+        )
+    }
+
+    /// Whether this instruction ends with a two-byte index into its chunk's
+    /// [`caches`](crate::vm::chunk::Chunk::caches).
+    pub fn has_inline_cache(self) -> bool {
+        matches!(
+            self,
+            OpCode::GetGlobal
+                | OpCode::GetGlobalLong
+                | OpCode::GetProperty
+                | OpCode::GetPropertyLong
+                | OpCode::Invoke
+                | OpCode::InvokeLong
+                | OpCode::TailInvoke
+                | OpCode::TailInvokeLong
+        )
+    }
+
+    /// The form of an instruction taking a constant index that fits
+    /// `constant`: the one-byte operand form where possible, else the long
+    /// form.
+    pub fn for_constant(self, constant: usize) -> OpCode {
+        if constant <= u8::MAX as usize {
+            return self;
+        }
+        match self {
+            OpCode::Constant => OpCode::ConstantLong,
+            OpCode::GetGlobal => OpCode::GetGlobalLong,
+            OpCode::DefineGlobal => OpCode::DefineGlobalLong,
+            OpCode::SetGlobal => OpCode::SetGlobalLong,
+            OpCode::Closure => OpCode::ClosureLong,
+            OpCode::GetProperty => OpCode::GetPropertyLong,
+            OpCode::SetProperty => OpCode::SetPropertyLong,
+            OpCode::GetSuper => OpCode::GetSuperLong,
+            OpCode::Invoke => OpCode::InvokeLong,
+            OpCode::SuperInvoke => OpCode::SuperInvokeLong,
+            OpCode::TailInvoke => OpCode::TailInvokeLong,
+            OpCode::TailSuperInvoke => OpCode::TailSuperInvokeLong,
+            OpCode::Class => OpCode::ClassLong,
+            OpCode::Method => OpCode::MethodLong,
+            OpCode::ClassMethod => OpCode::ClassMethodLong,
+            _ => unreachable!("{} takes no constant", self.name()),
+        }
+    }
+
+    const ALL: [OpCode; 71] = [
+        OpCode::Constant,
+        OpCode::ConstantLong,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::Dup,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::GetGlobalLong,
+        OpCode::DefineGlobal,
+        OpCode::DefineGlobalLong,
+        OpCode::SetGlobal,
+        OpCode::SetGlobalLong,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::GetPropertyLong,
+        OpCode::SetProperty,
+        OpCode::SetPropertyLong,
+        OpCode::GetSuper,
+        OpCode::GetSuperLong,
+        OpCode::BuildList,
+        OpCode::BuildMap,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+        OpCode::GetSlice,
+        OpCode::Equal,
+        OpCode::Greater,
+        OpCode::Less,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Modulo,
+        OpCode::Power,
+        OpCode::BitAnd,
+        OpCode::BitOr,
+        OpCode::BitXor,
+        OpCode::ShiftLeft,
+        OpCode::ShiftRight,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::BitNot,
+        OpCode::ToString,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::TailCall,
+        OpCode::Invoke,
+        OpCode::InvokeLong,
+        OpCode::SuperInvoke,
+        OpCode::SuperInvokeLong,
+        OpCode::TailInvoke,
+        OpCode::TailInvokeLong,
+        OpCode::TailSuperInvoke,
+        OpCode::TailSuperInvokeLong,
+        OpCode::Closure,
+        OpCode::ClosureLong,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::ClassLong,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::MethodLong,
+        OpCode::ClassMethod,
+        OpCode::ClassMethodLong,
+    ];
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Self::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+impl From<OpCode> for u8 {
+    fn from(op: OpCode) -> Self {
+        op as u8
+    }
+}
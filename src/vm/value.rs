@@ -0,0 +1,164 @@
+use crate::vm::heap::ObjRef;
+
+/// A VM value. Heap-allocated data lives in the [`Heap`](super::heap::Heap)
+/// and is referred to by handle.
+///
+/// The rest of the VM only goes through the constructors and accessors, so
+/// the representation can be swapped for the NaN-boxed one with the
+/// `nan-boxing` feature.
+#[cfg(not(feature = "nan-boxing"))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Obj(ObjRef),
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+    pub fn nil() -> Self {
+        Value::Nil
+    }
+
+    pub fn bool(b: bool) -> Self {
+        Value::Bool(b)
+    }
+
+    pub fn number(n: f64) -> Self {
+        Value::Number(n)
+    }
+
+    pub fn obj(obj: ObjRef) -> Self {
+        Value::Obj(obj)
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_obj(&self) -> Option<ObjRef> {
+        match self {
+            Value::Obj(obj) => Some(*obj),
+            _ => None,
+        }
+    }
+
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+}
+
+/// A VM value packed into a single `u64`.
+///
+/// Numbers are stored as their IEEE 754 bits. Every other value hides in the
+/// payload of a quiet NaN that arithmetic never produces: nil and booleans
+/// use small tags in the low bits, and objects set the sign bit and keep
+/// their heap index in the low 48 bits.
+#[cfg(feature = "nan-boxing")]
+#[derive(Clone, Copy)]
+pub struct Value(u64);
+
+#[cfg(feature = "nan-boxing")]
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const TAG_NIL: u64 = 1;
+#[cfg(feature = "nan-boxing")]
+const TAG_FALSE: u64 = 2;
+#[cfg(feature = "nan-boxing")]
+const TAG_TRUE: u64 = 3;
+#[cfg(feature = "nan-boxing")]
+const NIL: u64 = QNAN | TAG_NIL;
+#[cfg(feature = "nan-boxing")]
+const FALSE: u64 = QNAN | TAG_FALSE;
+#[cfg(feature = "nan-boxing")]
+const TRUE: u64 = QNAN | TAG_TRUE;
+
+#[cfg(feature = "nan-boxing")]
+impl Value {
+    pub fn nil() -> Self {
+        Value(NIL)
+    }
+
+    pub fn bool(b: bool) -> Self {
+        Value(if b { TRUE } else { FALSE })
+    }
+
+    pub fn number(n: f64) -> Self {
+        Value(n.to_bits())
+    }
+
+    pub fn obj(obj: ObjRef) -> Self {
+        Value(SIGN_BIT | QNAN | obj.0 as u64)
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.0 == NIL
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.0 {
+            TRUE => Some(true),
+            FALSE => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        (self.0 & QNAN != QNAN).then(|| f64::from_bits(self.0))
+    }
+
+    pub fn as_obj(&self) -> Option<ObjRef> {
+        (self.0 & (SIGN_BIT | QNAN) == SIGN_BIT | QNAN)
+            .then_some(ObjRef((self.0 & !(SIGN_BIT | QNAN)) as usize))
+    }
+
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_falsey(&self) -> bool {
+        self.0 == NIL || self.0 == FALSE
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+impl PartialEq for Value {
+    /// Numbers compare by value so that `NaN != NaN` and `0 == -0`, exactly
+    /// as with the enum representation. Everything else compares by bits.
+    fn eq(&self, other: &Self) -> bool {
+        match (self.as_number(), other.as_number()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.0 == other.0,
+        }
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(n) = self.as_number() {
+            write!(f, "Number({:?})", n)
+        } else if let Some(b) = self.as_bool() {
+            write!(f, "Bool({})", b)
+        } else if let Some(obj) = self.as_obj() {
+            write!(f, "Obj({:?})", obj)
+        } else {
+            write!(f, "Nil")
+        }
+    }
+}
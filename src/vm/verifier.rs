@@ -0,0 +1,334 @@
+//! Checks bytecode the compiler didn't produce, i.e. a loaded `.loxc` file,
+//! before the VM runs it.
+//!
+//! The VM indexes the code, constants, inline caches, and stack without
+//! checking, trusting the compiler to get them right. So every path through
+//! a function is followed here, tracking how many values are on the stack
+//! and which of them closures have captured, to make sure no instruction
+//! reads past the end of any of them, and that control never leaves the
+//! code except by returning.
+
+use crate::{
+    parser::MAX_ARGUMENTS,
+    vm::{
+        chunk::{Chunk, LONG_OPERAND_BYTES, NO_CACHE},
+        heap::Heap,
+        object::{Obj, ObjFunction},
+        opcode::OpCode,
+    },
+};
+
+/// One decoded instruction.
+struct Instruction {
+    op: OpCode,
+    /// The byte operand: a slot, an upvalue, or a count of values.
+    operand: usize,
+    /// Where a jump goes.
+    target: usize,
+    /// The `(is_local, index)` pairs of the variables a closure captures.
+    captures: Vec<(bool, usize)>,
+    next: usize,
+}
+
+/// Checks `function`, whose nested functions have already been checked,
+/// and describes the first problem found.
+pub fn verify(heap: &Heap, function: &ObjFunction) -> Result<(), &'static str> {
+    let chunk = &function.chunk;
+    if chunk.lines.first().is_none_or(|run| run.start != 0) {
+        return Err("code without a line");
+    }
+    if function.arity > MAX_ARGUMENTS {
+        return Err("too many parameters");
+    }
+
+    // Each slot on the stack is `true` if a closure has captured it, so it
+    // has to be closed rather than popped.
+    let mut stacks: Vec<Option<Vec<bool>>> = vec![None; chunk.code.len()];
+    let mut pending = vec![0];
+    // The callee and its arguments start off the frame.
+    *stacks.first_mut().ok_or("empty function")? = Some(vec![false; function.arity + 1]);
+
+    while let Some(offset) = pending.pop() {
+        let mut stack = stacks[offset].clone().expect("pending code has a stack");
+        let instruction = decode(heap, function, offset)?;
+        let (reads, pops, pushes) = stack_effect(&instruction);
+        if reads > stack.len() {
+            return Err("stack underflow");
+        }
+        let popped = &stack[stack.len() - pops..];
+        if instruction.op != OpCode::CloseUpvalue && popped.contains(&true) {
+            return Err("captured variable popped without being closed");
+        }
+        match instruction.op {
+            OpCode::GetLocal | OpCode::SetLocal if instruction.operand >= stack.len() => {
+                return Err("local slot out of range");
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue
+                if instruction.operand >= function.upvalue_count =>
+            {
+                return Err("upvalue index out of range");
+            }
+            _ => {}
+        }
+        for &(is_local, index) in &instruction.captures {
+            if is_local && index >= stack.len() {
+                return Err("captured local out of range");
+            }
+            if !is_local && index >= function.upvalue_count {
+                return Err("captured upvalue out of range");
+            }
+            if is_local {
+                stack[index] = true;
+            }
+        }
+        stack.truncate(stack.len() - pops);
+        stack.resize(stack.len() + pushes, false);
+
+        let successors = match instruction.op {
+            OpCode::Return => vec![],
+            OpCode::Jump | OpCode::Loop => vec![instruction.target],
+            OpCode::JumpIfFalse => vec![instruction.next, instruction.target],
+            _ => vec![instruction.next],
+        };
+        for successor in successors {
+            let Some(known) = stacks.get_mut(successor) else {
+                return Err("control runs past the end of the code");
+            };
+            match known {
+                None => {
+                    *known = Some(stack.clone());
+                    pending.push(successor);
+                }
+                Some(known) if known.len() != stack.len() => {
+                    return Err("stack depth differs where control flow joins");
+                }
+                Some(known) => {
+                    let mut changed = false;
+                    for (known, &captured) in known.iter_mut().zip(&stack) {
+                        changed |= captured && !*known;
+                        *known |= captured;
+                    }
+                    if changed {
+                        pending.push(successor);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How many values `instruction` needs on the stack, how many of those it
+/// pops, and how many it pushes.
+fn stack_effect(instruction: &Instruction) -> (usize, usize, usize) {
+    let count = instruction.operand;
+    match instruction.op {
+        OpCode::Constant
+        | OpCode::ConstantLong
+        | OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::GetLocal
+        | OpCode::GetGlobal
+        | OpCode::GetGlobalLong
+        | OpCode::GetUpvalue
+        | OpCode::Closure
+        | OpCode::ClosureLong
+        | OpCode::Class
+        | OpCode::ClassLong => (0, 0, 1),
+        OpCode::Pop
+        | OpCode::DefineGlobal
+        | OpCode::DefineGlobalLong
+        | OpCode::Print
+        | OpCode::CloseUpvalue => (1, 1, 0),
+        OpCode::Dup => (count, 0, count),
+        OpCode::SetLocal
+        | OpCode::SetGlobal
+        | OpCode::SetGlobalLong
+        | OpCode::SetUpvalue
+        | OpCode::JumpIfFalse
+        | OpCode::Return => (1, 0, 0),
+        OpCode::Jump | OpCode::Loop => (0, 0, 0),
+        OpCode::GetProperty
+        | OpCode::GetPropertyLong
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::BitNot
+        | OpCode::ToString => (1, 1, 1),
+        OpCode::SetProperty
+        | OpCode::SetPropertyLong
+        | OpCode::GetSuper
+        | OpCode::GetSuperLong
+        | OpCode::GetIndex
+        | OpCode::Equal
+        | OpCode::Greater
+        | OpCode::Less
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Modulo
+        | OpCode::Power
+        | OpCode::BitAnd
+        | OpCode::BitOr
+        | OpCode::BitXor
+        | OpCode::ShiftLeft
+        | OpCode::ShiftRight => (2, 2, 1),
+        OpCode::SetIndex | OpCode::GetSlice => (3, 3, 1),
+        OpCode::BuildList => (count, count, 1),
+        OpCode::BuildMap => (2 * count, 2 * count, 1),
+        OpCode::Call
+        | OpCode::TailCall
+        | OpCode::Invoke
+        | OpCode::InvokeLong
+        | OpCode::TailInvoke
+        | OpCode::TailInvokeLong => (count + 1, count + 1, 1),
+        OpCode::SuperInvoke
+        | OpCode::SuperInvokeLong
+        | OpCode::TailSuperInvoke
+        | OpCode::TailSuperInvokeLong => (count + 2, count + 2, 1),
+        OpCode::Inherit
+        | OpCode::Method
+        | OpCode::MethodLong
+        | OpCode::ClassMethod
+        | OpCode::ClassMethodLong => (2, 1, 0),
+    }
+}
+
+/// Reads the instruction at `offset` in `function`, checking that its
+/// operands are all there and that its constant and inline cache exist.
+fn decode(heap: &Heap, function: &ObjFunction, offset: usize) -> Result<Instruction, &'static str> {
+    let chunk = &function.chunk;
+    let mut reader = Operands {
+        chunk,
+        position: offset,
+    };
+    let op = OpCode::try_from(reader.byte()? as u8).map_err(|_| "unknown opcode")?;
+    let mut instruction = Instruction {
+        op,
+        operand: 0,
+        target: 0,
+        captures: Vec::new(),
+        next: 0,
+    };
+
+    match op {
+        OpCode::Constant | OpCode::ConstantLong => {
+            reader.constant(op)?;
+        }
+        OpCode::GetGlobal
+        | OpCode::GetGlobalLong
+        | OpCode::DefineGlobal
+        | OpCode::DefineGlobalLong
+        | OpCode::SetGlobal
+        | OpCode::SetGlobalLong
+        | OpCode::GetProperty
+        | OpCode::GetPropertyLong
+        | OpCode::SetProperty
+        | OpCode::SetPropertyLong
+        | OpCode::GetSuper
+        | OpCode::GetSuperLong
+        | OpCode::Class
+        | OpCode::ClassLong
+        | OpCode::Method
+        | OpCode::MethodLong
+        | OpCode::ClassMethod
+        | OpCode::ClassMethodLong => reader.name(heap, op)?,
+        OpCode::Invoke
+        | OpCode::InvokeLong
+        | OpCode::SuperInvoke
+        | OpCode::SuperInvokeLong
+        | OpCode::TailInvoke
+        | OpCode::TailInvokeLong
+        | OpCode::TailSuperInvoke
+        | OpCode::TailSuperInvokeLong => {
+            reader.name(heap, op)?;
+            instruction.operand = reader.byte()?;
+        }
+        OpCode::Dup
+        | OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::BuildList
+        | OpCode::BuildMap
+        | OpCode::Call
+        | OpCode::TailCall => instruction.operand = reader.byte()?,
+        OpCode::Jump | OpCode::JumpIfFalse => {
+            let jump = reader.short()?;
+            instruction.target = reader.position + jump;
+        }
+        OpCode::Loop => {
+            let jump = reader.short()?;
+            instruction.target = reader
+                .position
+                .checked_sub(jump)
+                .ok_or("loop before the code")?;
+        }
+        OpCode::Closure | OpCode::ClosureLong => {
+            let constant = chunk.constants[reader.constant(op)?];
+            let Some(Obj::Function(nested)) = constant.as_obj().map(|obj| heap.get(obj)) else {
+                return Err("closure of a non-function");
+            };
+            for _ in 0..nested.upvalue_count {
+                let is_local = reader.byte()? == 1;
+                let index = reader.byte()?;
+                instruction.captures.push((is_local, index));
+            }
+        }
+        _ => {}
+    }
+    if op.has_inline_cache() {
+        let cache = reader.short()?;
+        if cache != NO_CACHE as usize && cache >= chunk.caches.len() {
+            return Err("inline cache index out of range");
+        }
+    }
+    instruction.next = reader.position;
+    Ok(instruction)
+}
+
+/// Reads the operands following an opcode.
+struct Operands<'a> {
+    chunk: &'a Chunk,
+    position: usize,
+}
+
+impl Operands<'_> {
+    fn byte(&mut self) -> Result<usize, &'static str> {
+        let byte = *self
+            .chunk
+            .code
+            .get(self.position)
+            .ok_or("instruction cut off")?;
+        self.position += 1;
+        Ok(byte as usize)
+    }
+
+    fn short(&mut self) -> Result<usize, &'static str> {
+        Ok(self.byte()? << 8 | self.byte()?)
+    }
+
+    /// The index of an existing constant.
+    fn constant(&mut self, op: OpCode) -> Result<usize, &'static str> {
+        let width = if op.is_long() { LONG_OPERAND_BYTES } else { 1 };
+        let mut index = 0;
+        for _ in 0..width {
+            index = index << 8 | self.byte()?;
+        }
+        if index >= self.chunk.constants.len() {
+            return Err("constant index out of range");
+        }
+        Ok(index)
+    }
+
+    /// A constant naming a variable, property, or class, which has to be a
+    /// string.
+    fn name(&mut self, heap: &Heap, op: OpCode) -> Result<(), &'static str> {
+        let constant = self.chunk.constants[self.constant(op)?];
+        if heap.as_string(constant).is_none() {
+            return Err("name is not a string");
+        }
+        Ok(())
+    }
+}
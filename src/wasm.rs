@@ -0,0 +1,50 @@
+//! Bindings for running Lox in the browser, exported to JavaScript with
+//! `wasm-bindgen`, e.g. for a playground.
+
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{interpreter::InterpreterOptions, Lox};
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    /// JavaScript's `Date.now()`: milliseconds since the Unix epoch.
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    pub(crate) fn date_now() -> f64;
+}
+
+/// Runs `source` on a fresh, sandboxed interpreter and returns everything it
+/// printed, followed by the report of the error that stopped it, if any.
+#[wasm_bindgen]
+pub fn run_lox(source: &str) -> String {
+    let output = SharedBuffer::default();
+    let mut lox = Lox::with_options(InterpreterOptions::sandboxed());
+    lox.set_output(Box::new(output.clone()), Box::new(output.clone()));
+    if let Err(error) = lox.run(source) {
+        lox.report(&error);
+    }
+    let bytes = output.0.borrow();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// A byte buffer both of the interpreter's outputs can write into, so the
+/// text comes out interleaved as it would in a terminal.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}